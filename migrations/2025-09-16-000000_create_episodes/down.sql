@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_episodes_severity;
+DROP INDEX IF EXISTS idx_episodes_timestamp;
+DROP TABLE IF EXISTS episodes;
@@ -1,5 +1,6 @@
--- Create episodes table for vertigo episode logging
-CREATE TABLE episodes (
+-- Create episodes table for vertigo episode logging.
+-- IF NOT EXISTS lets databases created before migration tracking adopt this version.
+CREATE TABLE IF NOT EXISTS episodes (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
     timestamp DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
     duration_minutes INTEGER,
@@ -15,9 +16,5 @@ CREATE TABLE episodes (
 );
 
 -- Create index for faster queries
-CREATE INDEX idx_episodes_timestamp ON episodes(timestamp);
-CREATE INDEX idx_episodes_severity ON episodes(severity);
-
--- Insert a test episode for validation
-INSERT INTO episodes (severity, symptoms, duration_minutes, notes)
-VALUES (3, 'Mild dizziness and slight nausea', 10, 'Test episode for validation');
\ No newline at end of file
+CREATE INDEX IF NOT EXISTS idx_episodes_timestamp ON episodes(timestamp);
+CREATE INDEX IF NOT EXISTS idx_episodes_severity ON episodes(severity);
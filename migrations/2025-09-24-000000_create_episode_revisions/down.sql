@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_episode_revisions_episode;
+DROP TABLE IF EXISTS episode_revisions;
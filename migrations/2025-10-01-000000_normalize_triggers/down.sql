@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_episode_triggers_trigger;
+DROP TABLE IF EXISTS episode_triggers;
+DROP TABLE IF EXISTS triggers;
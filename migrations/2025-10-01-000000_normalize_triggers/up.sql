@@ -0,0 +1,44 @@
+-- Triggers become first-class rows linked to episodes. episodes.triggers is kept
+-- as a display copy of the linked names so existing clients keep working.
+CREATE TABLE triggers (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE TABLE episode_triggers (
+    episode_id INTEGER NOT NULL REFERENCES episodes(id) ON DELETE CASCADE,
+    trigger_id INTEGER NOT NULL REFERENCES triggers(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (episode_id, trigger_id)
+);
+
+CREATE INDEX idx_episode_triggers_trigger ON episode_triggers(trigger_id);
+
+-- Split the existing comma-separated text into one row per trigger
+CREATE TEMP TABLE trigger_backfill AS
+WITH RECURSIVE split(episode_id, position, item, rest) AS (
+    SELECT id, -1, '', triggers || ',' FROM episodes WHERE triggers IS NOT NULL
+    UNION ALL
+    SELECT episode_id,
+           position + 1,
+           trim(substr(rest, 1, instr(rest, ',') - 1)),
+           substr(rest, instr(rest, ',') + 1)
+    FROM split
+    WHERE rest <> ''
+)
+SELECT episode_id, position, item
+FROM split
+WHERE item <> '' AND lower(item) NOT IN ('unknown', 'none', 'n/a');
+
+-- First spelling seen wins as the display name
+INSERT OR IGNORE INTO triggers (name)
+SELECT item FROM trigger_backfill ORDER BY episode_id, position;
+
+INSERT OR IGNORE INTO episode_triggers (episode_id, trigger_id, position)
+SELECT b.episode_id, t.id, b.position
+FROM trigger_backfill b
+JOIN triggers t ON t.name = b.item
+ORDER BY b.episode_id, b.position;
+
+DROP TABLE trigger_backfill;
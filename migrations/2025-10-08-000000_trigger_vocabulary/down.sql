@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_trigger_synonyms_trigger;
+DROP TABLE IF EXISTS trigger_synonyms;
+ALTER TABLE triggers DROP COLUMN canonical;
@@ -0,0 +1,59 @@
+-- Canonical triggers form the vocabulary that free-text entries are mapped onto
+ALTER TABLE triggers ADD COLUMN canonical BOOLEAN NOT NULL DEFAULT 0;
+
+CREATE TABLE trigger_synonyms (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    phrase TEXT NOT NULL UNIQUE COLLATE NOCASE,
+    trigger_id INTEGER NOT NULL REFERENCES triggers(id) ON DELETE CASCADE,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_trigger_synonyms_trigger ON trigger_synonyms(trigger_id);
+
+-- Starter vocabulary; existing spellings of these names are kept
+INSERT OR IGNORE INTO triggers (name) VALUES
+    ('Lack of sleep'),
+    ('Stress'),
+    ('Dehydration'),
+    ('Weather change'),
+    ('Standing up quickly'),
+    ('Head movement'),
+    ('Bright lights'),
+    ('Screen time'),
+    ('Alcohol'),
+    ('Caffeine'),
+    ('Skipped meal'),
+    ('Loud noise');
+
+UPDATE triggers SET canonical = 1 WHERE name IN (
+    'Lack of sleep', 'Stress', 'Dehydration', 'Weather change', 'Standing up quickly',
+    'Head movement', 'Bright lights', 'Screen time', 'Alcohol', 'Caffeine',
+    'Skipped meal', 'Loud noise'
+);
+
+INSERT OR IGNORE INTO trigger_synonyms (phrase, trigger_id)
+SELECT s.phrase, t.id
+FROM (
+    SELECT 'poor sleep' AS phrase, 'Lack of sleep' AS name
+    UNION ALL SELECT 'no sleep', 'Lack of sleep'
+    UNION ALL SELECT 'tired', 'Lack of sleep'
+    UNION ALL SELECT 'fatigue', 'Lack of sleep'
+    UNION ALL SELECT 'sleep deprivation', 'Lack of sleep'
+    UNION ALL SELECT 'stressed', 'Stress'
+    UNION ALL SELECT 'not enough water', 'Dehydration'
+    UNION ALL SELECT 'thirsty', 'Dehydration'
+    UNION ALL SELECT 'barometric pressure', 'Weather change'
+    UNION ALL SELECT 'weather', 'Weather change'
+    UNION ALL SELECT 'stood up quickly', 'Standing up quickly'
+    UNION ALL SELECT 'getting up fast', 'Standing up quickly'
+    UNION ALL SELECT 'turning head', 'Head movement'
+    UNION ALL SELECT 'rolling over in bed', 'Head movement'
+    UNION ALL SELECT 'computer', 'Screen time'
+    UNION ALL SELECT 'phone', 'Screen time'
+    UNION ALL SELECT 'wine', 'Alcohol'
+    UNION ALL SELECT 'beer', 'Alcohol'
+    UNION ALL SELECT 'coffee', 'Caffeine'
+    UNION ALL SELECT 'missed meal', 'Skipped meal'
+    UNION ALL SELECT 'hunger', 'Skipped meal'
+) s
+JOIN triggers t ON t.name = s.name;
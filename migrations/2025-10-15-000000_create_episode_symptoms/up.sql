@@ -0,0 +1,14 @@
+-- Structured symptom checklist, one row per episode. episodes.symptoms stays free text for notes.
+CREATE TABLE episode_symptoms (
+    episode_id INTEGER PRIMARY KEY REFERENCES episodes(id) ON DELETE CASCADE,
+    vertigo_type TEXT CHECK(vertigo_type IN ('rotational', 'non_rotational')),
+    nausea BOOLEAN NOT NULL DEFAULT 0,
+    vomiting BOOLEAN NOT NULL DEFAULT 0,
+    tinnitus BOOLEAN NOT NULL DEFAULT 0,
+    aural_fullness BOOLEAN NOT NULL DEFAULT 0,
+    hearing_loss_side TEXT CHECK(hearing_loss_side IN ('left', 'right', 'bilateral')),
+    headache BOOLEAN NOT NULL DEFAULT 0,
+    visual_aura BOOLEAN NOT NULL DEFAULT 0,
+    imbalance BOOLEAN NOT NULL DEFAULT 0,
+    nystagmus_direction TEXT CHECK(nystagmus_direction IN ('left', 'right', 'up', 'down', 'torsional'))
+);
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS idx_medication_intakes_medication;
+DROP INDEX IF EXISTS idx_medication_intakes_episode;
+DROP TABLE IF EXISTS medication_intakes;
+DROP TABLE IF EXISTS medications;
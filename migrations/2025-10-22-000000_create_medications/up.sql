@@ -0,0 +1,23 @@
+-- Medication catalog and per-episode intake records
+CREATE TABLE medications (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+    drug_class TEXT,
+    strength TEXT,
+    usage TEXT NOT NULL CHECK(usage IN ('preventive', 'rescue')),
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE TABLE medication_intakes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    episode_id INTEGER NOT NULL REFERENCES episodes(id) ON DELETE CASCADE,
+    medication_id INTEGER NOT NULL REFERENCES medications(id),
+    dose TEXT NOT NULL,
+    -- Negative when taken before the episode started
+    minutes_after_onset INTEGER,
+    perceived_effect INTEGER CHECK(perceived_effect >= 0 AND perceived_effect <= 5),
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_medication_intakes_episode ON medication_intakes(episode_id);
+CREATE INDEX idx_medication_intakes_medication ON medication_intakes(medication_id);
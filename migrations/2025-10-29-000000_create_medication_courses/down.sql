@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_medication_courses_medication;
+DROP TABLE IF EXISTS medication_courses;
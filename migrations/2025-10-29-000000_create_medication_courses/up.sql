@@ -0,0 +1,12 @@
+-- When a medication was started and stopped, for before/after efficacy comparisons
+CREATE TABLE medication_courses (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    medication_id INTEGER NOT NULL REFERENCES medications(id) ON DELETE CASCADE,
+    started_on DATE NOT NULL,
+    stopped_on DATE,
+    notes TEXT,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    CHECK(stopped_on IS NULL OR stopped_on >= started_on)
+);
+
+CREATE INDEX idx_medication_courses_medication ON medication_courses(medication_id, started_on);
@@ -0,0 +1,9 @@
+DROP INDEX IF EXISTS idx_medication_courses_user;
+ALTER TABLE medication_courses DROP COLUMN user_id;
+
+DROP INDEX IF EXISTS idx_episodes_user;
+ALTER TABLE episodes DROP COLUMN user_id;
+
+DROP INDEX IF EXISTS idx_sessions_user;
+DROP TABLE IF EXISTS sessions;
+DROP TABLE IF EXISTS users;
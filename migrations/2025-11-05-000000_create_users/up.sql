@@ -0,0 +1,25 @@
+-- Accounts, login sessions, and per-user ownership of episodes and medication courses
+CREATE TABLE users (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL UNIQUE COLLATE NOCASE,
+    password_hash TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+-- Only a SHA-256 of each session token is stored
+CREATE TABLE sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    expires_at DATETIME NOT NULL
+);
+
+CREATE INDEX idx_sessions_user ON sessions(user_id);
+
+-- Existing rows stay unowned until the first account is registered, which adopts them
+ALTER TABLE episodes ADD COLUMN user_id INTEGER REFERENCES users(id);
+CREATE INDEX idx_episodes_user ON episodes(user_id, timestamp);
+
+ALTER TABLE medication_courses ADD COLUMN user_id INTEGER REFERENCES users(id);
+CREATE INDEX idx_medication_courses_user ON medication_courses(user_id);
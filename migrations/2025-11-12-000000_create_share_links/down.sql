@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS server_secrets;
+DROP INDEX IF EXISTS idx_share_access_log_link;
+DROP TABLE IF EXISTS share_access_log;
+DROP INDEX IF EXISTS idx_share_links_user;
+DROP TABLE IF EXISTS share_links;
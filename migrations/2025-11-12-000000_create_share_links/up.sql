@@ -0,0 +1,35 @@
+-- Read-only links a patient hands to a clinician, and every attempt to use one
+CREATE TABLE share_links (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    label TEXT,
+    from_date DATE,
+    to_date DATE,
+    pin_hash TEXT,
+    failed_pin_attempts INTEGER NOT NULL DEFAULT 0,
+    expires_at DATETIME NOT NULL,
+    revoked_at DATETIME,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    CHECK(from_date IS NULL OR to_date IS NULL OR from_date <= to_date)
+);
+
+CREATE INDEX idx_share_links_user ON share_links(user_id);
+
+CREATE TABLE share_access_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    share_link_id INTEGER NOT NULL REFERENCES share_links(id) ON DELETE CASCADE,
+    accessed_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    resource TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    client_ip TEXT,
+    user_agent TEXT
+);
+
+CREATE INDEX idx_share_access_log_link ON share_access_log(share_link_id, accessed_at);
+
+-- Server-generated keys, e.g. for signing share tokens when no secret is configured
+CREATE TABLE server_secrets (
+    name TEXT PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_episodes_user_duration;
+DROP INDEX IF EXISTS idx_episodes_user_severity;
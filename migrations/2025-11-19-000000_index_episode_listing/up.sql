@@ -0,0 +1,3 @@
+-- Keyset paging for the episode list sorts on (key, timestamp, id) within one user
+CREATE INDEX idx_episodes_user_severity ON episodes(user_id, severity, timestamp);
+CREATE INDEX idx_episodes_user_duration ON episodes(user_id, duration_minutes, timestamp);
@@ -0,0 +1,32 @@
+-- Full-text index over the free-text episode fields. It is an external-content
+-- table: the text lives in episodes, and the triggers below keep the index in step.
+CREATE VIRTUAL TABLE episodes_fts USING fts5(
+    symptoms,
+    notes,
+    activities_before,
+    location,
+    ai_analysis,
+    content = 'episodes',
+    content_rowid = 'id',
+    tokenize = 'porter unicode61 remove_diacritics 2'
+);
+
+CREATE TRIGGER episodes_fts_insert AFTER INSERT ON episodes BEGIN
+    INSERT INTO episodes_fts(rowid, symptoms, notes, activities_before, location, ai_analysis)
+    VALUES (new.id, new.symptoms, new.notes, new.activities_before, new.location, new.ai_analysis);
+END;
+
+CREATE TRIGGER episodes_fts_delete AFTER DELETE ON episodes BEGIN
+    INSERT INTO episodes_fts(episodes_fts, rowid, symptoms, notes, activities_before, location, ai_analysis)
+    VALUES ('delete', old.id, old.symptoms, old.notes, old.activities_before, old.location, old.ai_analysis);
+END;
+
+CREATE TRIGGER episodes_fts_update AFTER UPDATE OF symptoms, notes, activities_before, location, ai_analysis ON episodes BEGIN
+    INSERT INTO episodes_fts(episodes_fts, rowid, symptoms, notes, activities_before, location, ai_analysis)
+    VALUES ('delete', old.id, old.symptoms, old.notes, old.activities_before, old.location, old.ai_analysis);
+    INSERT INTO episodes_fts(rowid, symptoms, notes, activities_before, location, ai_analysis)
+    VALUES (new.id, new.symptoms, new.notes, new.activities_before, new.location, new.ai_analysis);
+END;
+
+-- Index everything recorded before search existed
+INSERT INTO episodes_fts(episodes_fts) VALUES ('rebuild');
@@ -0,0 +1,11 @@
+-- Subscribable calendar feeds; like sessions, only a SHA-256 of each token is stored
+CREATE TABLE calendar_feeds (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    label TEXT,
+    token_hash TEXT NOT NULL UNIQUE,
+    last_fetched_at DATETIME,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_calendar_feeds_user ON calendar_feeds(user_id);
@@ -0,0 +1,4 @@
+-- Admins curate what every account shares: the trigger vocabulary and the medication catalog.
+-- The first account, which adopted the pre-account data, becomes the first admin
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT 0;
+UPDATE users SET is_admin = 1 WHERE id = (SELECT MIN(id) FROM users);
@@ -48,12 +48,8 @@ check_success "Release build"
 
 # 4. Database Setup
 echo -e "${YELLOW}🗄️ Setting up database...${NC}"
-if [ ! -f "vertigo.db" ]; then
-    sqlite3 vertigo.db < migrations/001_create_episodes.sql
-    check_success "Database creation"
-else
-    echo -e "${GREEN}✅ Database already exists${NC}"
-fi
+./target/release/vertigo-logger --migrate-only
+check_success "Database migrations"
 
 # 5. Test database connection
 echo -e "${YELLOW}🔬 Testing database...${NC}"
@@ -86,7 +82,8 @@ fi
 
 # 9. API Test
 echo -e "${YELLOW}🔌 Testing API endpoints...${NC}"
-curl -f http://localhost:3000/api/episodes > /dev/null 2>&1
+# Episode routes require a session, so an anonymous request must be refused
+[ "$(curl -s -o /dev/null -w '%{http_code}' http://localhost:3000/api/episodes)" = "401" ]
 check_success "API endpoint test"
 
 # Stop test server
@@ -102,6 +99,7 @@ echo ""
 echo -e "${BLUE}Then access:${NC}"
 echo -e "  🌐 Web interface: ${YELLOW}http://localhost:3000${NC}"
 echo -e "  🔌 API docs: ${YELLOW}http://localhost:3000/api/episodes${NC}"
+echo -e "  👤 Create an account: ${YELLOW}POST /api/auth/register${NC} (the first account adopts the demo episodes)"
 echo ""
 echo -e "${BLUE}To stop the application:${NC}"
 echo -e "  ${YELLOW}Ctrl+C${NC}"
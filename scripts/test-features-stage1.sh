@@ -55,22 +55,40 @@ run_test "Server Health Check" "curl -f $BASE_URL/health > /dev/null 2>&1"
 # Test 5: Static Files
 run_test "Web Interface Serving" "curl -f $BASE_URL/ | grep -q 'Vertigo Episode Logger'"
 
-# Test 6: API - Get Episodes
-run_test "API - Get Episodes" "curl -f $BASE_URL/api/episodes > /dev/null 2>&1"
+# Test 6: API - Anonymous requests are rejected
+run_test "API - Requires Login" "[ \"\$(curl -s -o /dev/null -w '%{http_code}' $BASE_URL/api/episodes)\" = 401 ]"
 
-# Test 7: API - Create Episode
-run_test "API - Create Episode" "curl -f -X POST $BASE_URL/api/episodes -H 'Content-Type: application/json' -d '{\"severity\":3,\"symptoms\":\"Test dizziness\",\"duration_minutes\":15}' > /dev/null 2>&1"
+# Authenticated tests log in to an existing account rather than registering one,
+# because the first account registered adopts any episodes already recorded
+if [ -n "$VERTIGO_TEST_USER" ] && [ -n "$VERTIGO_TEST_PASSWORD" ]; then
+    TOKEN=$(curl -s -X POST $BASE_URL/api/auth/login -H 'Content-Type: application/json' \
+        -d "{\"username\":\"$VERTIGO_TEST_USER\",\"password\":\"$VERTIGO_TEST_PASSWORD\"}" \
+        | sed -n 's/.*"token":"\([^"]*\)".*/\1/p')
+    AUTH="Authorization: Bearer $TOKEN"
 
-# Test 8: API - AI Analysis
-run_test "API - AI Analysis" "curl -f -X POST $BASE_URL/api/analyze -H 'Content-Type: application/json' -d '{\"symptoms\":\"severe dizziness and nausea\",\"severity\":4}' | grep -q 'analysis'"
+    # Test 7: Auth - Login
+    run_test "API - Login" "[ -n \"$TOKEN\" ]"
 
-# Test 9: API - Export Data
-run_test "API - Export CSV" "curl -f $BASE_URL/api/export | grep -q 'ID,Timestamp'"
+    # Test 8: API - Get Episodes
+    run_test "API - Get Episodes" "curl -f -H '$AUTH' $BASE_URL/api/episodes > /dev/null 2>&1"
 
-# Test 10: Memory Usage Check
+    # Test 9: API - Create Episode
+    run_test "API - Create Episode" "curl -f -X POST $BASE_URL/api/episodes -H '$AUTH' -H 'Content-Type: application/json' -d '{\"severity\":3,\"symptoms\":\"Test dizziness\",\"duration_minutes\":15}' > /dev/null 2>&1"
+
+    # Test 10: API - AI Analysis
+    run_test "API - AI Analysis" "curl -f -X POST $BASE_URL/api/analyze -H '$AUTH' -H 'Content-Type: application/json' -d '{\"symptoms\":\"severe dizziness and nausea\",\"severity\":4}' | grep -q 'analysis'"
+
+    # Test 11: API - Export Data
+    run_test "API - Export CSV" "curl -f -H '$AUTH' $BASE_URL/api/export | grep -q 'ID,Timestamp'"
+else
+    echo -e "${YELLOW}Skipping authenticated API tests (set VERTIGO_TEST_USER and VERTIGO_TEST_PASSWORD)${NC}"
+    echo ""
+fi
+
+# Test 12: Memory Usage Check
 run_test "Memory Usage < 100MB" "ps aux | grep vertigo-logger | grep -v grep | awk '{print \$4}' | awk '{if(\$1 < 10.0) exit 0; else exit 1}'"
 
-# Test 11: Response Time Check
+# Test 13: Response Time Check
 run_test "Response Time < 1s" "time curl -f $BASE_URL/health 2>&1 | grep -q 'real.*0m0'"
 
 # Clean up
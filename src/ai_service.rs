@@ -2,7 +2,8 @@ use reqwest::Client;
 use serde_json::{json, Value};
 use std::env;
 
-use crate::models::{AnalysisRequest, AnalysisResponse, PatternAnalysis, Episode};
+use crate::error::AppError;
+use crate::models::{AnalysisRequest, AnalysisResponse, PatternAnalysis, Episode, TriggerCount};
 
 pub struct AIService {
     client: Client,
@@ -11,7 +12,7 @@ pub struct AIService {
 }
 
 impl AIService {
-    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn new() -> Result<Self, AppError> {
         let api_key = env::var("OPENROUTER_API_KEY")
             .unwrap_or_else(|_| "dummy_key_for_testing".to_string());
 
@@ -25,7 +26,7 @@ impl AIService {
         })
     }
 
-    pub async fn analyze_episode(&self, request: &AnalysisRequest) -> Result<AnalysisResponse, Box<dyn std::error::Error>> {
+    pub async fn analyze_episode(&self, request: &AnalysisRequest) -> Result<AnalysisResponse, AppError> {
         if self.api_key == "dummy_key_for_testing" {
             return self.mock_analysis(request);
         }
@@ -43,7 +44,7 @@ impl AIService {
         });
 
         let response = self.client
-            .post(&format!("{}/chat/completions", self.base_url))
+            .post(format!("{}/chat/completions", self.base_url))
             .header("Authorization", &format!("Bearer {}", self.api_key))
             .header("Content-Type", "application/json")
             .json(&payload)
@@ -51,7 +52,7 @@ impl AIService {
             .await?;
 
         if !response.status().is_success() {
-            return self.mock_analysis(request);
+            return Err(AppError::AiUpstream(format!("AI provider responded with {}", response.status())));
         }
 
         let response_body: Value = response.json().await?;
@@ -112,7 +113,7 @@ Keep response concise and medical-focused.",
         recommendations
     }
 
-    fn mock_analysis(&self, request: &AnalysisRequest) -> Result<AnalysisResponse, Box<dyn std::error::Error>> {
+    fn mock_analysis(&self, request: &AnalysisRequest) -> Result<AnalysisResponse, AppError> {
         let severity = request.severity.unwrap_or(3);
 
         let analysis = match severity {
@@ -136,7 +137,7 @@ Keep response concise and medical-focused.",
         })
     }
 
-    pub fn analyze_patterns(&self, episodes: &[Episode]) -> Result<PatternAnalysis, Box<dyn std::error::Error>> {
+    pub fn analyze_patterns(&self, episodes: &[Episode], trigger_frequency: &[TriggerCount]) -> Result<PatternAnalysis, AppError> {
         if episodes.is_empty() {
             return Ok(PatternAnalysis {
                 common_triggers: vec![],
@@ -147,7 +148,7 @@ Keep response concise and medical-focused.",
             });
         }
 
-        let common_triggers = self.identify_common_triggers(episodes);
+        let common_triggers = self.identify_common_triggers(trigger_frequency);
         let severity_patterns = self.analyze_severity_patterns(episodes);
         let time_patterns = self.analyze_time_patterns(episodes);
         let risk_factors = self.identify_risk_factors(episodes);
@@ -162,34 +163,13 @@ Keep response concise and medical-focused.",
         })
     }
 
-    fn identify_common_triggers(&self, episodes: &[Episode]) -> Vec<String> {
-        let mut trigger_counts = std::collections::HashMap::new();
-
-        for episode in episodes {
-            if let Some(triggers) = &episode.triggers {
-                if !triggers.trim().is_empty() {
-                    for trigger in triggers.split(',').map(|t| t.trim().to_lowercase()) {
-                        if !trigger.is_empty() && trigger != "unknown" && trigger != "none" {
-                            *trigger_counts.entry(trigger).or_insert(0) += 1;
-                        }
-                    }
-                }
-            }
-        }
-
-        let mut triggers: Vec<_> = trigger_counts.into_iter().collect();
-        triggers.sort_by(|a, b| b.1.cmp(&a.1));
+    fn identify_common_triggers(&self, trigger_frequency: &[TriggerCount]) -> Vec<String> {
+        let mut triggers: Vec<_> = trigger_frequency.iter().collect();
+        triggers.sort_by_key(|t| std::cmp::Reverse(t.count));
 
         triggers.into_iter()
-            .filter(|(_, count)| *count >= 2) // Only triggers that occur at least twice
-            .map(|(trigger, _)| {
-                // Capitalize first letter
-                let mut chars = trigger.chars();
-                match chars.next() {
-                    None => String::new(),
-                    Some(first) => first.to_uppercase().chain(chars).collect(),
-                }
-            })
+            .filter(|t| t.count >= 2) // Only triggers that occur at least twice
+            .map(|t| t.trigger.clone())
             .take(5)
             .collect()
     }
@@ -244,8 +224,8 @@ Keep response concise and medical-focused.",
         }
 
         // Analyze hour patterns
-        let mut hour_counts = vec![0; 24];
-        let mut weekday_counts = vec![0; 7];
+        let mut hour_counts = [0; 24];
+        let mut weekday_counts = [0; 7];
 
         for episode in episodes {
             let hour = episode.timestamp.hour() as usize;
@@ -311,7 +291,7 @@ Keep response concise and medical-focused.",
 
         // Duration-based risk factors
         let long_episodes = episodes.iter().filter(|e|
-            e.duration_minutes.map_or(false, |d| d > 120)
+            e.duration_minutes.is_some_and(|d| d > 120)
         ).count();
 
         if long_episodes > 0 {
@@ -360,7 +340,7 @@ Keep response concise and medical-focused.",
 
         // Duration-based recommendations
         let long_duration_episodes = episodes.iter().filter(|e|
-            e.duration_minutes.map_or(false, |d| d > 60)
+            e.duration_minutes.is_some_and(|d| d > 60)
         ).count();
 
         if long_duration_episodes > 0 {
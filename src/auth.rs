@@ -0,0 +1,149 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::sync::OnceLock;
+
+use crate::database;
+use crate::error::AppError;
+use crate::handlers::{with_conn, AppState};
+
+/// Cookie carrying the session token for browser clients.
+pub const SESSION_COOKIE: &str = "vertigo_session";
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .map(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+        .unwrap_or(false)
+}
+
+/// A real Argon2 hash of a password nobody has, for checking a login against
+/// when the username does not exist. Doing the same work either way keeps
+/// response times from revealing which usernames are registered.
+pub fn dummy_password_hash() -> &'static str {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    DUMMY.get_or_init(|| {
+        hash_password(&new_session_token()).expect("hashing a random password cannot fail")
+    })
+}
+
+/// 256 random bits, URL-safe so it can go in a header or cookie as-is.
+pub fn new_session_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// What the sessions table stores in place of the token.
+pub fn token_hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// How long a session stays valid, from `SESSION_TTL_HOURS` (default 30 days).
+pub fn session_ttl() -> chrono::Duration {
+    let hours = env::var("SESSION_TTL_HOURS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&h: &i64| h > 0)
+        .unwrap_or(24 * 30);
+    chrono::Duration::hours(hours)
+}
+
+pub fn session_cookie(token: &str, ttl: chrono::Duration) -> String {
+    format!("{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}", SESSION_COOKIE, token, ttl.num_seconds())
+}
+
+pub fn expired_session_cookie() -> String {
+    format!("{}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0", SESSION_COOKIE)
+}
+
+/// Reads the token from `Authorization: Bearer`, falling back to the session cookie.
+pub fn token_from_headers(headers: &HeaderMap) -> Option<String> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|t| !t.is_empty());
+    if let Some(token) = bearer {
+        return Some(token.to_string());
+    }
+
+    cookie_value(headers, SESSION_COOKIE)
+}
+
+/// The value of a non-empty request cookie.
+pub fn cookie_value(headers: &HeaderMap, cookie: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, value)| *name == cookie && !value.is_empty())
+        .map(|(_, value)| value.to_string())
+}
+
+/// The signed-in account behind a request. Extracting it rejects the
+/// request with 401 when there is no live session.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: i32,
+    pub username: String,
+    pub session_id: i32,
+    pub is_admin: bool,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let token = token_from_headers(&parts.headers)
+            .ok_or_else(|| AppError::Unauthorized("sign in to continue".to_string()))?;
+        let hash = token_hash(&token);
+
+        let (session, user) = with_conn(state, move |conn| database::find_session(conn, &hash))
+            .await
+            .map_err(|error| match error {
+                AppError::NotFound(_) => AppError::Unauthorized("the session has expired; sign in again".to_string()),
+                other => other,
+            })?;
+
+        Ok(AuthUser { id: user.id, username: user.username, session_id: session.id, is_admin: user.is_admin })
+    }
+}
+
+/// A signed-in admin. Extracting it rejects other accounts with 403, so
+/// handlers that change what every account shares take this instead of
+/// [`AuthUser`].
+#[derive(Debug, Clone)]
+pub struct AdminUser(pub AuthUser);
+
+#[async_trait]
+impl FromRequestParts<AppState> for AdminUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+        if !user.is_admin {
+            return Err(AppError::Forbidden("only an admin can change what every account shares".to_string()));
+        }
+        Ok(AdminUser(user))
+    }
+}
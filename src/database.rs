@@ -1,69 +1,1422 @@
+use diesel::connection::SimpleConnection;
 use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool};
 use diesel::sqlite::SqliteConnection;
-use diesel::result::Error;
+use diesel::result::{DatabaseErrorKind, Error};
+use std::collections::HashMap;
 use std::env;
+use std::time::Duration;
 use chrono::Datelike;
 
-use crate::models::{Episode, NewEpisode, EpisodeUpdate, AnalyticsData, SeverityCount, TriggerCount, MonthlyTrend, DurationStats};
-use crate::schema::episodes;
+use crate::models::{Episode, NewEpisode, SymptomChecklist, NewSymptomChecklist, SymptomCount, Medication, NewMedication, MedicationUpdate, MedicationIntake, IntakeRequest, NewMedicationIntake, IntakeUpdate, MedicationUsageStats, MedicationCourse, CourseRequest, NewMedicationCourse, MedicationEfficacy, EfficacyWindow, User, NewUser, Session, NewSession, ShareLink, NewShareLink, ShareAccess, NewShareAccess, CalendarFeed, NewCalendarFeed, EpisodeFilter, EpisodeListQuery, EpisodeSort, SortOrder, EpisodeCursor, EpisodePage, EpisodeSearchQuery, EpisodeSearchHit, EpisodeUpdate, EpisodeRevision, NewEpisodeRevision, Trigger, NewTrigger, NewEpisodeTrigger, TriggerSummary, TriggerSynonym, NewTriggerSynonym, TriggerRemap, AnalyticsData, SeverityCount, TriggerCount, MonthlyTrend, DurationStats};
+use crate::schema::{episodes, episode_revisions, triggers, episode_triggers, trigger_synonyms, episode_symptoms, medications, medication_intakes, medication_courses, users, sessions, share_links, share_access_log, server_secrets, calendar_feeds};
+use crate::trigger_vocabulary::Vocabulary;
+
+/// Free-text placeholders that mean "no trigger" rather than naming one.
+const PLACEHOLDER_TRIGGERS: &[&str] = &["unknown", "none", "n/a"];
 
 pub type DbConnection = SqliteConnection;
+pub type DbPool = Pool<ConnectionManager<SqliteConnection>>;
+
+/// Connection pool tuning, read from the environment with sensible defaults.
+#[derive(Debug, Clone)]
+pub struct PoolSettings {
+    pub database_url: String,
+    pub max_size: u32,
+    pub busy_timeout_ms: u32,
+    pub acquire_timeout_secs: u64,
+}
 
-pub fn establish_connection() -> ConnectionResult<SqliteConnection> {
-    let database_url = env::var("DATABASE_URL")
-        .unwrap_or_else(|_| "vertigo.db".to_string());
+impl PoolSettings {
+    pub fn from_env() -> Self {
+        fn parse_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+            env::var(key).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+        }
 
-    SqliteConnection::establish(&database_url)
+        PoolSettings {
+            database_url: env::var("DATABASE_URL").unwrap_or_else(|_| "vertigo.db".to_string()),
+            max_size: parse_or("DB_POOL_SIZE", 8),
+            busy_timeout_ms: parse_or("DB_BUSY_TIMEOUT_MS", 5_000),
+            acquire_timeout_secs: parse_or("DB_ACQUIRE_TIMEOUT_SECS", 10),
+        }
+    }
 }
 
-pub fn create_episode(conn: &mut SqliteConnection, new_episode: &NewEpisode) -> Result<Episode, Error> {
-    diesel::insert_into(episodes::table)
-        .values(new_episode)
-        .execute(conn)?;
+/// Applies per-connection pragmas whenever the pool opens a new connection.
+#[derive(Debug)]
+struct ConnectionOptions {
+    busy_timeout_ms: u32,
+}
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for ConnectionOptions {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+        // WAL lets readers proceed while a writer holds the lock; busy_timeout
+        // makes writers wait for each other instead of failing with SQLITE_BUSY.
+        conn.batch_execute(&format!(
+            "PRAGMA busy_timeout = {}; PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL; PRAGMA foreign_keys = ON;",
+            self.busy_timeout_ms
+        ))
+        .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+pub fn create_pool(settings: &PoolSettings) -> Result<DbPool, diesel::r2d2::PoolError> {
+    let manager = ConnectionManager::<SqliteConnection>::new(&settings.database_url);
+
+    Pool::builder()
+        .max_size(settings.max_size)
+        .connection_timeout(Duration::from_secs(settings.acquire_timeout_secs))
+        .connection_customizer(Box::new(ConnectionOptions {
+            busy_timeout_ms: settings.busy_timeout_ms,
+        }))
+        .build(manager)
+}
+
+/// Registers an account. The first account adopts any episodes and courses
+/// recorded before accounts existed, and is an admin.
+pub fn create_user(conn: &mut SqliteConnection, username: &str, password_hash: &str) -> Result<User, Error> {
+    conn.transaction(|conn| {
+        let existing_users = users::table.count().get_result::<i64>(conn)?;
+
+        diesel::insert_into(users::table)
+            .values(&NewUser { username: username.trim(), password_hash, is_admin: existing_users == 0 })
+            .execute(conn)?;
+
+        let user = users::table
+            .order(users::id.desc())
+            .first::<User>(conn)?;
+
+        if existing_users == 0 {
+            diesel::update(episodes::table.filter(episodes::user_id.is_null()))
+                .set(episodes::user_id.eq(user.id))
+                .execute(conn)?;
+            diesel::update(medication_courses::table.filter(medication_courses::user_id.is_null()))
+                .set(medication_courses::user_id.eq(user.id))
+                .execute(conn)?;
+        }
+
+        Ok(user)
+    })
+}
+
+pub fn get_user(conn: &mut SqliteConnection, user_id: i32) -> Result<User, Error> {
+    users::table
+        .find(user_id)
+        .first(conn)
+}
+
+/// Usernames are COLLATE NOCASE, so lookups ignore case.
+pub fn find_user_by_username(conn: &mut SqliteConnection, username: &str) -> Result<Option<User>, Error> {
+    users::table
+        .filter(users::username.eq(username.trim()))
+        .first::<User>(conn)
+        .optional()
+}
+
+/// Stores a new session and clears out any that have expired.
+pub fn create_session(conn: &mut SqliteConnection, new_session: &NewSession) -> Result<Session, Error> {
+    conn.transaction(|conn| {
+        diesel::delete(sessions::table.filter(sessions::expires_at.le(chrono::Utc::now().naive_utc())))
+            .execute(conn)?;
+
+        diesel::insert_into(sessions::table)
+            .values(new_session)
+            .execute(conn)?;
+
+        sessions::table
+            .filter(sessions::token_hash.eq(new_session.token_hash))
+            .first::<Session>(conn)
+    })
+}
+
+/// Looks up a live session by token hash; expired sessions are NotFound.
+pub fn find_session(conn: &mut SqliteConnection, token_hash: &str) -> Result<(Session, User), Error> {
+    sessions::table
+        .inner_join(users::table)
+        .filter(sessions::token_hash.eq(token_hash))
+        .filter(sessions::expires_at.gt(chrono::Utc::now().naive_utc()))
+        .select((sessions::all_columns, users::all_columns))
+        .first::<(Session, User)>(conn)
+}
+
+pub fn delete_session(conn: &mut SqliteConnection, session_id: i32) -> Result<usize, Error> {
+    diesel::delete(sessions::table.find(session_id))
+        .execute(conn)
+}
+
+type EpisodeQuery = diesel::dsl::Select<
+    diesel::dsl::LeftJoin<episodes::table, episode_symptoms::table>,
+    diesel::dsl::AsSelect<Episode, diesel::sqlite::Sqlite>,
+>;
+
+/// Episodes joined with their optional symptom checklist.
+fn episode_query() -> EpisodeQuery {
+    episodes::table
+        .left_join(episode_symptoms::table)
+        .select(Episode::as_select())
+}
 
+/// Confirms the episode belongs to the user; anyone else's is NotFound.
+fn owned_episode(conn: &mut SqliteConnection, user_id: i32, episode_id: i32) -> Result<i32, Error> {
     episodes::table
-        .order(episodes::id.desc())
-        .first::<Episode>(conn)
+        .filter(episodes::id.eq(episode_id))
+        .filter(episodes::user_id.eq(user_id))
+        .select(episodes::id)
+        .first::<i32>(conn)
+}
+
+/// Ids of every episode the user owns, as a subquery.
+fn user_episode_ids(user_id: i32) -> diesel::dsl::Select<diesel::dsl::Filter<episodes::table, diesel::dsl::Eq<episodes::user_id, i32>>, episodes::id> {
+    episodes::table
+        .filter(episodes::user_id.eq(user_id))
+        .select(episodes::id)
+}
+
+pub fn create_episode(conn: &mut SqliteConnection, user_id: i32, new_episode: &NewEpisode) -> Result<Episode, Error> {
+    conn.transaction(|conn| {
+        diesel::insert_into(episodes::table)
+            .values((new_episode, episodes::user_id.eq(user_id)))
+            .execute(conn)?;
+
+        // Still inside the write transaction, so this is the row we just inserted
+        let episode_id = episodes::table
+            .select(episodes::id)
+            .order(episodes::id.desc())
+            .first::<i32>(conn)?;
+
+        if let Some(raw) = &new_episode.triggers {
+            let resolved = resolve_triggers(conn, raw)?;
+            link_triggers(conn, episode_id, &resolved)?;
+        }
+
+        if let Some(checklist) = &new_episode.symptom_checklist {
+            save_symptom_checklist(conn, episode_id, checklist)?;
+        }
+
+        get_episode_by_id(conn, user_id, episode_id)
+    })
 }
 
-pub fn get_all_episodes(conn: &mut SqliteConnection) -> Result<Vec<Episode>, Error> {
+/// Stores a batch of episodes all-or-nothing, each exactly as `create_episode` would.
+pub fn import_episodes(conn: &mut SqliteConnection, user_id: i32, new_episodes: &[NewEpisode]) -> Result<usize, Error> {
+    conn.transaction(|conn| {
+        for new_episode in new_episodes {
+            create_episode(conn, user_id, new_episode)?;
+        }
+        Ok(new_episodes.len())
+    })
+}
+
+/// `(id, timestamp, severity)` of the user's episodes between two instants, inclusive,
+/// for spotting re-imported episodes.
+pub fn get_episode_keys(conn: &mut SqliteConnection, user_id: i32, from: chrono::NaiveDateTime, to: chrono::NaiveDateTime) -> Result<Vec<(i32, chrono::NaiveDateTime, i32)>, Error> {
     episodes::table
+        .filter(episodes::user_id.eq(user_id))
+        .filter(episodes::timestamp.between(from, to))
+        .select((episodes::id, episodes::timestamp, episodes::severity))
+        .load(conn)
+}
+
+pub fn get_all_episodes(conn: &mut SqliteConnection, user_id: i32) -> Result<Vec<Episode>, Error> {
+    get_filtered_episodes(conn, user_id, &EpisodeFilter::default())
+}
+
+/// `%text%` for LIKE, with the user's own wildcards escaped.
+fn like_pattern(text: &str) -> String {
+    let escaped = text.trim()
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+/// The user's episodes matching the filter, as a query for loading or selecting ids from.
+fn filtered_episode_query<'a>(user_id: i32, filter: &EpisodeFilter) -> diesel::dsl::IntoBoxed<'a, EpisodeQuery, diesel::sqlite::Sqlite> {
+    let mut query = episode_query()
+        .filter(episodes::user_id.eq(user_id))
+        .into_boxed();
+
+    if let Some(from) = filter.from {
+        query = query.filter(episodes::timestamp.ge(from.first()));
+    }
+    if let Some(to) = filter.to {
+        query = query.filter(episodes::timestamp.le(to.last()));
+    }
+    if let Some(min) = filter.min_severity {
+        query = query.filter(episodes::severity.ge(min));
+    }
+    if let Some(max) = filter.max_severity {
+        query = query.filter(episodes::severity.le(max));
+    }
+    let trigger_names = filter.trigger_names();
+    if !trigger_names.is_empty() {
+        let tagged = episode_triggers::table
+            .inner_join(triggers::table)
+            .filter(triggers::name.eq_any(trigger_names))
+            .select(episode_triggers::episode_id);
+        query = query.filter(episodes::id.eq_any(tagged));
+    }
+    if let Some(location) = filter.location.as_deref().filter(|l| !l.trim().is_empty()) {
+        query = query.filter(episodes::location.like(like_pattern(location)).escape('\\'));
+    }
+    if let Some(medication) = filter.medication.as_deref().map(str::trim).filter(|m| !m.is_empty()) {
+        let taken = medication_intakes::table
+            .inner_join(medications::table)
+            .filter(medications::name.eq(medication.to_string()))
+            .select(medication_intakes::episode_id);
+        query = query.filter(
+            episodes::id.eq_any(taken)
+                .or(episodes::medications_taken.like(like_pattern(medication)).escape('\\').assume_not_null()),
+        );
+    }
+    match filter.has_notes {
+        Some(true) => query = query.filter(episodes::notes.is_not_null().and(episodes::notes.ne("").assume_not_null())),
+        Some(false) => query = query.filter(episodes::notes.is_null().or(episodes::notes.eq("").assume_not_null())),
+        None => {}
+    }
+
+    query
+}
+
+/// The user's episodes matching the filter, newest first.
+pub fn get_filtered_episodes(conn: &mut SqliteConnection, user_id: i32, filter: &EpisodeFilter) -> Result<Vec<Episode>, Error> {
+    filtered_episode_query(user_id, filter)
         .order(episodes::timestamp.desc())
         .load::<Episode>(conn)
 }
 
-pub fn get_episode_by_id(conn: &mut SqliteConnection, episode_id: i32) -> Result<Episode, Error> {
-    episodes::table
-        .find(episode_id)
+/// The next batch of filtered episodes, newest first, after the `(timestamp, id)`
+/// of the previous batch's last row. Exports walk the whole history this way
+/// without holding it all in memory.
+pub fn get_episode_batch(conn: &mut SqliteConnection, user_id: i32, filter: &EpisodeFilter, after: Option<(chrono::NaiveDateTime, i32)>, limit: i64) -> Result<Vec<Episode>, Error> {
+    let mut query = filtered_episode_query(user_id, filter);
+
+    if let Some((timestamp, id)) = after {
+        query = query.filter(
+            episodes::timestamp.lt(timestamp)
+                .or(episodes::timestamp.eq(timestamp).and(episodes::id.lt(id)))
+        );
+    }
+
+    query
+        .order((episodes::timestamp.desc(), episodes::id.desc()))
+        .limit(limit)
+        .load::<Episode>(conn)
+}
+
+diesel::define_sql_function!(fn ifnull(x: diesel::sql_types::Nullable<diesel::sql_types::Integer>, y: diesel::sql_types::Integer) -> diesel::sql_types::Integer);
+
+/// One page of the user's episodes, filtered and sorted as requested. Paging is
+/// keyset-based: the cursor holds the sort key of the last row, so each page is
+/// an index range rather than an OFFSET scan.
+pub fn list_episodes(conn: &mut SqliteConnection, user_id: i32, filter: &EpisodeFilter, params: &EpisodeListQuery) -> Result<EpisodePage, Error> {
+    let sort = params.sort();
+    let order = params.order();
+    let limit = params.limit();
+    let cursor = params.cursor()
+        .map_err(|e| Error::QueryBuilderError(e.into()))?;
+
+    let mut query = filtered_episode_query(user_id, filter);
+
+    // Ties on the sort field fall back to timestamp, then id, so the order is total
+    let duration = ifnull(episodes::duration_minutes, -1);
+    query = match (sort, order) {
+        (EpisodeSort::Timestamp, SortOrder::Asc) => query.order((episodes::timestamp.asc(), episodes::id.asc())),
+        (EpisodeSort::Timestamp, SortOrder::Desc) => query.order((episodes::timestamp.desc(), episodes::id.desc())),
+        (EpisodeSort::Severity, SortOrder::Asc) => query.order((episodes::severity.asc(), episodes::timestamp.asc(), episodes::id.asc())),
+        (EpisodeSort::Severity, SortOrder::Desc) => query.order((episodes::severity.desc(), episodes::timestamp.desc(), episodes::id.desc())),
+        (EpisodeSort::Duration, SortOrder::Asc) => query.order((duration.asc(), episodes::timestamp.asc(), episodes::id.asc())),
+        (EpisodeSort::Duration, SortOrder::Desc) => query.order((duration.desc(), episodes::timestamp.desc(), episodes::id.desc())),
+    };
+
+    if let Some(c) = cursor {
+        let (ts, id, value) = (c.timestamp, c.id, c.value);
+        query = match (sort, order) {
+            (EpisodeSort::Timestamp, SortOrder::Asc) => query.filter(
+                episodes::timestamp.gt(ts).or(episodes::timestamp.eq(ts).and(episodes::id.gt(id))),
+            ),
+            (EpisodeSort::Timestamp, SortOrder::Desc) => query.filter(
+                episodes::timestamp.lt(ts).or(episodes::timestamp.eq(ts).and(episodes::id.lt(id))),
+            ),
+            (EpisodeSort::Severity, SortOrder::Asc) => query.filter(
+                episodes::severity.gt(value).or(episodes::severity.eq(value).and(
+                    episodes::timestamp.gt(ts).or(episodes::timestamp.eq(ts).and(episodes::id.gt(id))),
+                )),
+            ),
+            (EpisodeSort::Severity, SortOrder::Desc) => query.filter(
+                episodes::severity.lt(value).or(episodes::severity.eq(value).and(
+                    episodes::timestamp.lt(ts).or(episodes::timestamp.eq(ts).and(episodes::id.lt(id))),
+                )),
+            ),
+            (EpisodeSort::Duration, SortOrder::Asc) => query.filter(
+                duration.gt(value).or(duration.eq(value).and(
+                    episodes::timestamp.gt(ts).or(episodes::timestamp.eq(ts).and(episodes::id.gt(id))),
+                )),
+            ),
+            (EpisodeSort::Duration, SortOrder::Desc) => query.filter(
+                duration.lt(value).or(duration.eq(value).and(
+                    episodes::timestamp.lt(ts).or(episodes::timestamp.eq(ts).and(episodes::id.lt(id))),
+                )),
+            ),
+        };
+    }
+
+    // One extra row tells us whether another page follows
+    let mut episodes = query
+        .limit(limit + 1)
+        .load::<Episode>(conn)?;
+
+    let next_cursor = if episodes.len() as i64 > limit {
+        episodes.truncate(limit as usize);
+        episodes.last().map(|last| EpisodeCursor::after(last, sort, order).encode())
+    } else {
+        None
+    };
+
+    Ok(EpisodePage { episodes, next_cursor })
+}
+
+/// Marks a match inside an FTS5 snippet. Private-use characters cannot occur in
+/// typed text, so they survive HTML escaping and are swapped for `<mark>` after.
+const MATCH_START: char = '\u{E000}';
+const MATCH_END: char = '\u{E001}';
+
+#[derive(QueryableByName)]
+struct SearchRow {
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    episode_id: i32,
+    #[diesel(sql_type = diesel::sql_types::Double)]
+    rank: f64,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    snippet: String,
+}
+
+/// Full-text search over the user's episodes, best match first. Symptoms,
+/// notes and activities weigh more than location and the AI analysis.
+pub fn search_episodes(conn: &mut SqliteConnection, user_id: i32, search: &EpisodeSearchQuery) -> Result<Vec<EpisodeSearchHit>, Error> {
+    let rows = diesel::sql_query(format!(
+        "SELECT episodes.id AS episode_id, \
+                bm25(episodes_fts, 2.0, 2.0, 2.0, 1.0, 0.5) AS rank, \
+                snippet(episodes_fts, -1, '{}', '{}', '…', 16) AS snippet \
+         FROM episodes_fts \
+         INNER JOIN episodes ON episodes.id = episodes_fts.rowid \
+         WHERE episodes_fts MATCH ? AND episodes.user_id = ? \
+         ORDER BY rank, episodes.id DESC \
+         LIMIT ?",
+        MATCH_START, MATCH_END
+    ))
+    .bind::<diesel::sql_types::Text, _>(search.fts_expression())
+    .bind::<diesel::sql_types::Integer, _>(user_id)
+    .bind::<diesel::sql_types::BigInt, _>(search.limit)
+    .load::<SearchRow>(conn)?;
+
+    let ids: Vec<i32> = rows.iter().map(|r| r.episode_id).collect();
+    let mut episodes: HashMap<i32, Episode> = episode_query()
+        .filter(episodes::id.eq_any(&ids))
+        .load::<Episode>(conn)?
+        .into_iter()
+        .map(|e| (e.id, e))
+        .collect();
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            let snippet = crate::html::escape_html(&row.snippet)
+                .replace(MATCH_START, "<mark>")
+                .replace(MATCH_END, "</mark>");
+            episodes.remove(&row.episode_id)
+                .map(|episode| EpisodeSearchHit { episode, snippet, rank: row.rank })
+        })
+        .collect())
+}
+
+pub fn get_episode_by_id(conn: &mut SqliteConnection, user_id: i32, episode_id: i32) -> Result<Episode, Error> {
+    episode_query()
+        .filter(episodes::id.eq(episode_id))
+        .filter(episodes::user_id.eq(user_id))
         .first(conn)
 }
 
+fn save_symptom_checklist(conn: &mut SqliteConnection, episode_id: i32, checklist: &SymptomChecklist) -> Result<(), Error> {
+    diesel::replace_into(episode_symptoms::table)
+        .values(&NewSymptomChecklist { episode_id, checklist: checklist.clone() })
+        .execute(conn)?;
+    Ok(())
+}
+
 pub fn update_episode(
     conn: &mut SqliteConnection,
+    user_id: i32,
     episode_id: i32,
-    episode_update: &EpisodeUpdate
+    episode_update: &EpisodeUpdate,
+    changed_by: &str,
 ) -> Result<Episode, Error> {
-    // For simplicity, we'll just get the current episode and return it
-    // In a real app, you'd want proper partial updates
-    episodes::table
-        .find(episode_id)
-        .first::<Episode>(conn)
+    conn.transaction(|conn| {
+        let current = get_episode_by_id(conn, user_id, episode_id)?;
+
+        // Resolve triggers up front so history records the canonical names
+        let resolved_triggers = match &episode_update.triggers {
+            Some(raw) => Some(resolve_triggers(conn, raw)?),
+            None => None,
+        };
+
+        let mut changes = changed_fields(&current, episode_update);
+        let has_column_changes = !changes.is_empty();
+
+        let mut triggers_changed = false;
+        if let Some(resolved) = &resolved_triggers {
+            let new_text = trigger_text(resolved);
+            if new_text != current.triggers {
+                changes.push(("triggers", current.triggers.clone(), new_text));
+                triggers_changed = true;
+            }
+        }
+
+        let mut checklist_changed = false;
+        if let Some(checklist) = &episode_update.symptom_checklist {
+            if current.symptom_checklist.as_ref() != Some(checklist) {
+                // History stores the checklist as JSON so the whole change is visible
+                let as_json = |c: &SymptomChecklist| serde_json::to_string(c).ok();
+                changes.push((
+                    "symptom_checklist",
+                    current.symptom_checklist.as_ref().and_then(as_json),
+                    as_json(checklist),
+                ));
+                checklist_changed = true;
+            }
+        }
+
+        let revisions: Vec<NewEpisodeRevision> = changes
+            .into_iter()
+            .map(|(field_name, old_value, new_value)| NewEpisodeRevision {
+                episode_id,
+                field_name: field_name.to_string(),
+                old_value,
+                new_value,
+                changed_by: changed_by.to_string(),
+            })
+            .collect();
+
+        // Nothing differs from the stored row, so skip the write entirely
+        if revisions.is_empty() {
+            return Ok(current);
+        }
+
+        if has_column_changes {
+            diesel::update(episodes::table.find(episode_id))
+                .set(episode_update)
+                .execute(conn)?;
+        }
+
+        if triggers_changed {
+            if let Some(resolved) = &resolved_triggers {
+                link_triggers(conn, episode_id, resolved)?;
+            }
+        }
+
+        if checklist_changed {
+            if let Some(checklist) = &episode_update.symptom_checklist {
+                save_symptom_checklist(conn, episode_id, checklist)?;
+            }
+        }
+
+        diesel::insert_into(episode_revisions::table)
+            .values(&revisions)
+            .execute(conn)?;
+
+        get_episode_by_id(conn, user_id, episode_id)
+    })
+}
+
+pub fn get_episode_history(conn: &mut SqliteConnection, user_id: i32, episode_id: i32) -> Result<Vec<EpisodeRevision>, Error> {
+    // Surface NotFound for unknown episodes rather than an empty history
+    owned_episode(conn, user_id, episode_id)?;
+
+    episode_revisions::table
+        .filter(episode_revisions::episode_id.eq(episode_id))
+        .order((episode_revisions::changed_at.asc(), episode_revisions::id.asc()))
+        .load::<EpisodeRevision>(conn)
+}
+
+/// Lists every column the update would change as (field, old value, new value).
+/// Triggers are diffed separately because they are stored as linked rows.
+fn changed_fields(current: &Episode, update: &EpisodeUpdate) -> Vec<(&'static str, Option<String>, Option<String>)> {
+    fn diff<T: ToString + PartialEq>(
+        changes: &mut Vec<(&'static str, Option<String>, Option<String>)>,
+        field: &'static str,
+        old: Option<&T>,
+        new: Option<&T>,
+    ) {
+        if let Some(new) = new {
+            if old != Some(new) {
+                changes.push((field, old.map(|v| v.to_string()), Some(new.to_string())));
+            }
+        }
+    }
+
+    let mut changes = Vec::new();
+    diff(&mut changes, "duration_minutes", current.duration_minutes.as_ref(), update.duration_minutes.as_ref());
+    diff(&mut changes, "severity", Some(&current.severity), update.severity.as_ref());
+    diff(&mut changes, "symptoms", current.symptoms.as_ref(), update.symptoms.as_ref());
+    diff(&mut changes, "location", current.location.as_ref(), update.location.as_ref());
+    diff(&mut changes, "activities_before", current.activities_before.as_ref(), update.activities_before.as_ref());
+    diff(&mut changes, "medications_taken", current.medications_taken.as_ref(), update.medications_taken.as_ref());
+    diff(&mut changes, "notes", current.notes.as_ref(), update.notes.as_ref());
+    diff(&mut changes, "ai_analysis", current.ai_analysis.as_ref(), update.ai_analysis.as_ref());
+    changes
+}
+
+pub fn delete_episode(conn: &mut SqliteConnection, user_id: i32, episode_id: i32) -> Result<usize, Error> {
+    conn.transaction(|conn| {
+        if owned_episode(conn, user_id, episode_id).optional()?.is_none() {
+            return Ok(0);
+        }
+
+        diesel::delete(episode_revisions::table.filter(episode_revisions::episode_id.eq(episode_id)))
+            .execute(conn)?;
+
+        diesel::delete(episode_triggers::table.filter(episode_triggers::episode_id.eq(episode_id)))
+            .execute(conn)?;
+
+        diesel::delete(episode_symptoms::table.find(episode_id))
+            .execute(conn)?;
+
+        diesel::delete(medication_intakes::table.filter(medication_intakes::episode_id.eq(episode_id)))
+            .execute(conn)?;
+
+        diesel::delete(episodes::table.find(episode_id))
+            .execute(conn)
+    })
+}
+
+/// Splits comma-separated trigger text into distinct names, dropping blanks
+/// and placeholders. Duplicates are compared case-insensitively.
+fn split_triggers(raw: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+
+    for name in raw.split(',').map(|t| t.trim()) {
+        if name.is_empty() || PLACEHOLDER_TRIGGERS.contains(&name.to_lowercase().as_str()) {
+            continue;
+        }
+        if !names.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+            names.push(name.to_string());
+        }
+    }
+
+    names
+}
+
+fn trigger_text(resolved: &[Trigger]) -> Option<String> {
+    if resolved.is_empty() {
+        None
+    } else {
+        Some(resolved.iter().map(|t| t.name.as_str()).collect::<Vec<_>>().join(", "))
+    }
+}
+
+/// Finds or creates a trigger row for every name in the raw text, in order.
+/// Names are first mapped onto the canonical vocabulary (synonyms and close
+/// misspellings), so "poor sleep" and "tird" both land on "Lack of sleep".
+fn resolve_triggers(conn: &mut SqliteConnection, raw: &str) -> Result<Vec<Trigger>, Error> {
+    let vocabulary = load_vocabulary(conn)?;
+    let mut resolved: Vec<Trigger> = Vec::new();
+
+    for name in split_triggers(raw) {
+        let trigger = match vocabulary.lookup(&name) {
+            Some(found) => triggers::table.find(found.trigger_id).first::<Trigger>(conn)?,
+            None => find_or_create_trigger(conn, &name, false)?,
+        };
+
+        // Two synonyms in one entry can map to the same canonical trigger
+        if !resolved.iter().any(|t| t.id == trigger.id) {
+            resolved.push(trigger);
+        }
+    }
+
+    Ok(resolved)
+}
+
+fn find_or_create_trigger(conn: &mut SqliteConnection, name: &str, canonical: bool) -> Result<Trigger, Error> {
+    // The name column is COLLATE NOCASE, so "stress" finds "Stress"
+    let existing = triggers::table
+        .filter(triggers::name.eq(name))
+        .first::<Trigger>(conn)
+        .optional()?;
+
+    match existing {
+        Some(trigger) => Ok(trigger),
+        None => {
+            diesel::insert_into(triggers::table)
+                .values(&NewTrigger { name, canonical })
+                .execute(conn)?;
+            triggers::table
+                .filter(triggers::name.eq(name))
+                .first::<Trigger>(conn)
+        }
+    }
+}
+
+fn load_vocabulary(conn: &mut SqliteConnection) -> Result<Vocabulary, Error> {
+    let canonical = triggers::table
+        .filter(triggers::canonical.eq(true))
+        .load::<Trigger>(conn)?;
+    let synonyms = trigger_synonyms::table.load::<TriggerSynonym>(conn)?;
+
+    Ok(Vocabulary::new(&canonical, &synonyms))
+}
+
+/// Replaces an episode's trigger links and rewrites its display text to match.
+fn link_triggers(conn: &mut SqliteConnection, episode_id: i32, resolved: &[Trigger]) -> Result<(), Error> {
+    diesel::delete(episode_triggers::table.filter(episode_triggers::episode_id.eq(episode_id)))
+        .execute(conn)?;
+
+    let links: Vec<NewEpisodeTrigger> = resolved
+        .iter()
+        .enumerate()
+        .map(|(position, trigger)| NewEpisodeTrigger {
+            episode_id,
+            trigger_id: trigger.id,
+            position: position as i32,
+        })
+        .collect();
+
+    diesel::insert_into(episode_triggers::table)
+        .values(&links)
+        .execute(conn)?;
+
+    diesel::update(episodes::table.find(episode_id))
+        .set(episodes::triggers.eq(trigger_text(resolved)))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Links an episode to the triggers named in comma-separated text.
+pub fn set_episode_triggers(conn: &mut SqliteConnection, episode_id: i32, raw: &str) -> Result<(), Error> {
+    let resolved = resolve_triggers(conn, raw)?;
+    link_triggers(conn, episode_id, &resolved)
+}
+
+/// Rebuilds episodes.triggers from the linked rows after a rename or merge.
+fn refresh_trigger_text(conn: &mut SqliteConnection, episode_ids: &[i32]) -> Result<(), Error> {
+    for &episode_id in episode_ids {
+        let linked = episode_triggers::table
+            .inner_join(triggers::table)
+            .filter(episode_triggers::episode_id.eq(episode_id))
+            .order(episode_triggers::position.asc())
+            .select(triggers::all_columns)
+            .load::<Trigger>(conn)?;
+
+        diesel::update(episodes::table.find(episode_id))
+            .set(episodes::triggers.eq(trigger_text(&linked)))
+            .execute(conn)?;
+    }
+
+    Ok(())
+}
+
+/// Triggers a user can see: the shared vocabulary plus anything on one of
+/// their own episodes, counted over their episodes only.
+pub fn list_triggers(conn: &mut SqliteConnection, user_id: i32) -> Result<Vec<TriggerSummary>, Error> {
+    let counts: HashMap<i32, i64> = episode_triggers::table
+        .filter(episode_triggers::episode_id.eq_any(user_episode_ids(user_id)))
+        .group_by(episode_triggers::trigger_id)
+        .select((episode_triggers::trigger_id, diesel::dsl::count_star()))
+        .load::<(i32, i64)>(conn)?
+        .into_iter()
+        .collect();
+
+    let mut summaries: Vec<TriggerSummary> = triggers::table
+        .load::<Trigger>(conn)?
+        .into_iter()
+        .filter_map(|t| {
+            let episode_count = counts.get(&t.id).copied().unwrap_or(0);
+            (t.canonical || episode_count > 0)
+                .then_some(TriggerSummary { id: t.id, name: t.name, canonical: t.canonical, episode_count })
+        })
+        .collect();
+    summaries.sort_by(|a, b| b.episode_count.cmp(&a.episode_count).then_with(|| a.name.cmp(&b.name)));
+
+    Ok(summaries)
+}
+
+/// How often each trigger was logged across the user's episodes matching the filter.
+pub fn get_trigger_frequency(conn: &mut SqliteConnection, user_id: i32, filter: &EpisodeFilter) -> Result<Vec<TriggerCount>, Error> {
+    let episode_ids = filtered_episode_query(user_id, filter)
+        .select(episodes::id)
+        .load::<i32>(conn)?;
+
+    trigger_frequency(conn, &episode_ids)
 }
 
-pub fn delete_episode(conn: &mut SqliteConnection, episode_id: i32) -> Result<usize, Error> {
-    diesel::delete(episodes::table.find(episode_id))
+/// Renames a trigger on the caller's episodes only. Their links move to a
+/// trigger with the new name, so other users' episodes keep the old one.
+pub fn rename_trigger(conn: &mut SqliteConnection, user_id: i32, trigger_id: i32, new_name: &str) -> Result<Trigger, Error> {
+    conn.transaction(|conn| {
+        let visible = list_triggers(conn, user_id)?;
+        if !visible.iter().any(|t| t.id == trigger_id) {
+            return Err(Error::NotFound);
+        }
+
+        // The name column is COLLATE NOCASE, so this also finds the trigger itself
+        // when only the case changes
+        let name = new_name.trim();
+        let existing = triggers::table
+            .filter(triggers::name.eq(name))
+            .first::<Trigger>(conn)
+            .optional()?;
+
+        match existing {
+            // Another of the caller's triggers already has the name; callers should merge instead
+            Some(other) if other.id != trigger_id && visible.iter().any(|t| t.id == other.id) => Err(name_taken()),
+            // A change of case can only be made in place, so only when no one else sees the trigger
+            Some(same) if same.id == trigger_id => {
+                if trigger_is_shared(conn, user_id, trigger_id)? {
+                    return Err(name_taken());
+                }
+                diesel::update(triggers::table.find(trigger_id))
+                    .set(triggers::name.eq(name))
+                    .execute(conn)?;
+                let episode_ids = episode_triggers::table
+                    .filter(episode_triggers::trigger_id.eq(trigger_id))
+                    .select(episode_triggers::episode_id)
+                    .load::<i32>(conn)?;
+                refresh_trigger_text(conn, &episode_ids)?;
+                triggers::table.find(trigger_id).first::<Trigger>(conn)
+            }
+            _ => {
+                let target = find_or_create_trigger(conn, name, false)?;
+                relink_user_triggers(conn, user_id, &[trigger_id], target.id)?;
+                triggers::table.find(target.id).first::<Trigger>(conn)
+            }
+        }
+    })
+}
+
+/// Folds every source trigger into the target on the caller's episodes.
+pub fn merge_triggers(conn: &mut SqliteConnection, user_id: i32, source_ids: &[i32], target_id: i32) -> Result<TriggerSummary, Error> {
+    conn.transaction(|conn| {
+        // Every trigger involved must be visible to the caller
+        let visible = list_triggers(conn, user_id)?;
+        let is_visible = |id: &i32| visible.iter().any(|t| t.id == *id);
+        if !is_visible(&target_id) || !source_ids.iter().all(is_visible) {
+            return Err(Error::NotFound);
+        }
+
+        relink_user_triggers(conn, user_id, source_ids, target_id)?;
+
+        list_triggers(conn, user_id)?
+            .into_iter()
+            .find(|t| t.id == target_id)
+            .ok_or(Error::NotFound)
+    })
+}
+
+fn name_taken() -> Error {
+    Error::DatabaseError(DatabaseErrorKind::UniqueViolation, Box::new("trigger name already in use".to_string()))
+}
+
+/// Whether anything beyond the caller's own episodes depends on the trigger:
+/// the vocabulary, or another user's episode.
+fn trigger_is_shared(conn: &mut SqliteConnection, user_id: i32, trigger_id: i32) -> Result<bool, Error> {
+    let canonical = triggers::table
+        .find(trigger_id)
+        .select(triggers::canonical)
+        .first::<bool>(conn)?;
+    let foreign_links = episode_triggers::table
+        .filter(episode_triggers::trigger_id.eq(trigger_id))
+        .filter(episode_triggers::episode_id.ne_all(user_episode_ids(user_id)))
+        .count()
+        .get_result::<i64>(conn)?;
+
+    Ok(canonical || foreign_links > 0)
+}
+
+/// Points the caller's links to any of `source_ids` at `target_id` instead and
+/// rewrites those episodes' trigger text. Other users' episodes are untouched,
+/// and a source trigger is deleted only once nothing refers to it.
+fn relink_user_triggers(conn: &mut SqliteConnection, user_id: i32, source_ids: &[i32], target_id: i32) -> Result<(), Error> {
+    let source_links = episode_triggers::table
+        .filter(episode_triggers::trigger_id.eq_any(source_ids))
+        .filter(episode_triggers::episode_id.eq_any(user_episode_ids(user_id)))
+        .select((episode_triggers::episode_id, episode_triggers::position))
+        .load::<(i32, i32)>(conn)?;
+
+    // Episodes already linked to the target keep their existing link
+    let relinked: Vec<NewEpisodeTrigger> = source_links
+        .iter()
+        .map(|&(episode_id, position)| NewEpisodeTrigger { episode_id, trigger_id: target_id, position })
+        .collect();
+    diesel::insert_or_ignore_into(episode_triggers::table)
+        .values(&relinked)
+        .execute(conn)?;
+
+    diesel::delete(
+        episode_triggers::table
+            .filter(episode_triggers::trigger_id.eq_any(source_ids))
+            .filter(episode_triggers::episode_id.eq_any(user_episode_ids(user_id))),
+    )
+    .execute(conn)?;
+
+    // Vocabulary terms stay for everyone, and so does anything still on an episode
+    diesel::delete(
+        triggers::table
+            .filter(triggers::id.eq_any(source_ids))
+            .filter(triggers::canonical.eq(false))
+            .filter(triggers::id.ne_all(episode_triggers::table.select(episode_triggers::trigger_id))),
+    )
+    .execute(conn)?;
+
+    let mut episode_ids: Vec<i32> = source_links.iter().map(|&(episode_id, _)| episode_id).collect();
+    episode_ids.sort_unstable();
+    episode_ids.dedup();
+    refresh_trigger_text(conn, &episode_ids)
+}
+
+/// Adds a term to the canonical vocabulary, promoting an existing trigger of
+/// the same name if there is one.
+pub fn create_vocabulary_term(conn: &mut SqliteConnection, name: &str) -> Result<Trigger, Error> {
+    conn.transaction(|conn| {
+        let trigger = find_or_create_trigger(conn, name.trim(), true)?;
+        diesel::update(triggers::table.find(trigger.id))
+            .set(triggers::canonical.eq(true))
+            .execute(conn)?;
+        triggers::table.find(trigger.id).first::<Trigger>(conn)
+    })
+}
+
+pub fn list_synonyms(conn: &mut SqliteConnection) -> Result<Vec<TriggerSynonym>, Error> {
+    trigger_synonyms::table
+        .order(trigger_synonyms::phrase.asc())
+        .load::<TriggerSynonym>(conn)
+}
+
+/// Records a synonym rule; its target joins the canonical vocabulary.
+pub fn create_synonym(conn: &mut SqliteConnection, synonym: &NewTriggerSynonym) -> Result<TriggerSynonym, Error> {
+    conn.transaction(|conn| {
+        let updated = diesel::update(triggers::table.find(synonym.trigger_id))
+            .set(triggers::canonical.eq(true))
+            .execute(conn)?;
+        if updated == 0 {
+            return Err(Error::NotFound);
+        }
+
+        let phrase = synonym.phrase.trim();
+        diesel::insert_into(trigger_synonyms::table)
+            .values(&NewTriggerSynonym { phrase: phrase.to_string(), trigger_id: synonym.trigger_id })
+            .execute(conn)?;
+
+        trigger_synonyms::table
+            .filter(trigger_synonyms::phrase.eq(phrase))
+            .first::<TriggerSynonym>(conn)
+    })
+}
+
+pub fn delete_synonym(conn: &mut SqliteConnection, synonym_id: i32) -> Result<usize, Error> {
+    diesel::delete(trigger_synonyms::table.find(synonym_id))
         .execute(conn)
 }
 
-pub fn get_episodes_by_severity(conn: &mut SqliteConnection, min_severity: i32) -> Result<Vec<Episode>, Error> {
-    episodes::table
-        .filter(episodes::severity.ge(min_severity))
-        .order(episodes::timestamp.desc())
+/// Shows how each non-canonical trigger would be folded into the vocabulary
+/// under the current rules, without changing anything. Only triggers on the
+/// user's own episodes are considered.
+pub fn preview_canonicalization(conn: &mut SqliteConnection, user_id: i32) -> Result<Vec<TriggerRemap>, Error> {
+    let vocabulary = load_vocabulary(conn)?;
+    let summaries = list_triggers(conn, user_id)?;
+
+    let remaps = summaries
+        .iter()
+        .filter(|t| !t.canonical)
+        .filter_map(|t| {
+            let found = vocabulary.lookup(&t.name)?;
+            let target = summaries.iter().find(|c| c.id == found.trigger_id)?;
+            Some(TriggerRemap {
+                trigger_id: t.id,
+                name: t.name.clone(),
+                episode_count: t.episode_count,
+                canonical_id: target.id,
+                canonical_name: target.name.clone(),
+                rule: found.rule,
+                distance: found.distance,
+            })
+        })
+        .collect();
+
+    Ok(remaps)
+}
+
+/// Applies the preview by merging every remapped trigger into its target.
+pub fn apply_canonicalization(conn: &mut SqliteConnection, user_id: i32) -> Result<Vec<TriggerRemap>, Error> {
+    conn.transaction(|conn| {
+        let remaps = preview_canonicalization(conn, user_id)?;
+
+        let mut targets: Vec<i32> = remaps.iter().map(|r| r.canonical_id).collect();
+        targets.sort_unstable();
+        targets.dedup();
+
+        for target_id in targets {
+            let sources: Vec<i32> = remaps
+                .iter()
+                .filter(|r| r.canonical_id == target_id)
+                .map(|r| r.trigger_id)
+                .collect();
+            merge_triggers(conn, user_id, &sources, target_id)?;
+        }
+
+        Ok(remaps)
+    })
+}
+
+pub fn list_medications(conn: &mut SqliteConnection) -> Result<Vec<Medication>, Error> {
+    medications::table
+        .order(medications::name.asc())
+        .load::<Medication>(conn)
+}
+
+pub fn get_medication(conn: &mut SqliteConnection, medication_id: i32) -> Result<Medication, Error> {
+    medications::table
+        .find(medication_id)
+        .first(conn)
+}
+
+pub fn create_medication(conn: &mut SqliteConnection, new_medication: &NewMedication) -> Result<Medication, Error> {
+    conn.transaction(|conn| {
+        diesel::insert_into(medications::table)
+            .values(new_medication)
+            .execute(conn)?;
+
+        medications::table
+            .order(medications::id.desc())
+            .first::<Medication>(conn)
+    })
+}
+
+pub fn update_medication(conn: &mut SqliteConnection, medication_id: i32, update: &MedicationUpdate) -> Result<Medication, Error> {
+    conn.transaction(|conn| {
+        let current = get_medication(conn, medication_id)?;
+        if update.is_empty() {
+            return Ok(current);
+        }
+
+        diesel::update(medications::table.find(medication_id))
+            .set(update)
+            .execute(conn)?;
+
+        get_medication(conn, medication_id)
+    })
+}
+
+/// Refuses to delete a medication that any user's intakes or courses still reference.
+pub fn delete_medication(conn: &mut SqliteConnection, medication_id: i32) -> Result<usize, Error> {
+    conn.transaction(|conn| {
+        let in_use = medication_intakes::table
+            .filter(medication_intakes::medication_id.eq(medication_id))
+            .count()
+            .get_result::<i64>(conn)?;
+        let courses = medication_courses::table
+            .filter(medication_courses::medication_id.eq(medication_id))
+            .count()
+            .get_result::<i64>(conn)?;
+
+        if in_use > 0 || courses > 0 {
+            return Err(Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::ForeignKeyViolation,
+                Box::new(format!("medication {} has {} recorded intakes and {} courses", medication_id, in_use, courses)),
+            ));
+        }
+
+        diesel::delete(medications::table.find(medication_id))
+            .execute(conn)
+    })
+}
+
+type IntakeQuery = diesel::dsl::Select<
+    diesel::dsl::InnerJoin<medication_intakes::table, medications::table>,
+    (
+        medication_intakes::id,
+        medication_intakes::episode_id,
+        medication_intakes::medication_id,
+        medications::name,
+        medication_intakes::dose,
+        medication_intakes::minutes_after_onset,
+        medication_intakes::perceived_effect,
+        medication_intakes::created_at,
+    ),
+>;
+
+/// Intake rows joined with the medication name.
+fn intake_query() -> IntakeQuery {
+    medication_intakes::table
+        .inner_join(medications::table)
+        .select((
+            medication_intakes::id,
+            medication_intakes::episode_id,
+            medication_intakes::medication_id,
+            medications::name,
+            medication_intakes::dose,
+            medication_intakes::minutes_after_onset,
+            medication_intakes::perceived_effect,
+            medication_intakes::created_at,
+        ))
+}
+
+pub fn list_intakes(conn: &mut SqliteConnection, user_id: i32, episode_id: i32) -> Result<Vec<MedicationIntake>, Error> {
+    // Surface NotFound for unknown episodes rather than an empty list
+    owned_episode(conn, user_id, episode_id)?;
+
+    intake_query()
+        .filter(medication_intakes::episode_id.eq(episode_id))
+        .order((medication_intakes::minutes_after_onset.asc(), medication_intakes::id.asc()))
+        .load::<MedicationIntake>(conn)
+}
+
+/// Intakes for some of the user's episodes, keyed by episode, for exports and reports.
+pub fn get_intakes_for_episodes(conn: &mut SqliteConnection, user_id: i32, episode_ids: &[i32]) -> Result<HashMap<i32, Vec<MedicationIntake>>, Error> {
+    let intakes = intake_query()
+        .filter(medication_intakes::episode_id.eq_any(user_episode_ids(user_id)))
+        .filter(medication_intakes::episode_id.eq_any(episode_ids))
+        .order((medication_intakes::episode_id.asc(), medication_intakes::minutes_after_onset.asc(), medication_intakes::id.asc()))
+        .load::<MedicationIntake>(conn)?;
+
+    let mut by_episode: HashMap<i32, Vec<MedicationIntake>> = HashMap::new();
+    for intake in intakes {
+        by_episode.entry(intake.episode_id).or_default().push(intake);
+    }
+    Ok(by_episode)
+}
+
+pub fn create_intake(conn: &mut SqliteConnection, user_id: i32, episode_id: i32, request: &IntakeRequest) -> Result<MedicationIntake, Error> {
+    conn.transaction(|conn| {
+        owned_episode(conn, user_id, episode_id)?;
+        get_medication(conn, request.medication_id)?;
+
+        diesel::insert_into(medication_intakes::table)
+            .values(&NewMedicationIntake {
+                episode_id,
+                medication_id: request.medication_id,
+                dose: request.dose.trim(),
+                minutes_after_onset: request.minutes_after_onset,
+                perceived_effect: request.perceived_effect,
+            })
+            .execute(conn)?;
+
+        intake_query()
+            .order(medication_intakes::id.desc())
+            .first::<MedicationIntake>(conn)
+    })
+}
+
+pub fn update_intake(conn: &mut SqliteConnection, user_id: i32, episode_id: i32, intake_id: i32, update: &IntakeUpdate) -> Result<MedicationIntake, Error> {
+    conn.transaction(|conn| {
+        owned_episode(conn, user_id, episode_id)?;
+
+        let scoped = medication_intakes::table
+            .filter(medication_intakes::id.eq(intake_id))
+            .filter(medication_intakes::episode_id.eq(episode_id));
+
+        if let Some(medication_id) = update.medication_id {
+            get_medication(conn, medication_id)?;
+        }
+
+        if !update.is_empty() && diesel::update(scoped).set(update).execute(conn)? == 0 {
+            return Err(Error::NotFound);
+        }
+
+        intake_query()
+            .filter(medication_intakes::id.eq(intake_id))
+            .filter(medication_intakes::episode_id.eq(episode_id))
+            .first::<MedicationIntake>(conn)
+    })
+}
+
+pub fn delete_intake(conn: &mut SqliteConnection, user_id: i32, episode_id: i32, intake_id: i32) -> Result<usize, Error> {
+    diesel::delete(
+        medication_intakes::table
+            .filter(medication_intakes::id.eq(intake_id))
+            .filter(medication_intakes::episode_id.eq(episode_id))
+            .filter(medication_intakes::episode_id.eq_any(user_episode_ids(user_id))),
+    )
+    .execute(conn)
+}
+
+/// Per-medication intake counts and mean perceived effect across the whole
+/// catalog, over the user's own intakes.
+pub fn get_medication_usage(conn: &mut SqliteConnection, user_id: i32) -> Result<Vec<MedicationUsageStats>, Error> {
+    let intakes = medication_intakes::table
+        .filter(medication_intakes::episode_id.eq_any(user_episode_ids(user_id)))
+        .select((medication_intakes::medication_id, medication_intakes::perceived_effect))
+        .load::<(i32, Option<i32>)>(conn)?;
+
+    medication_usage(conn, &intakes)
+}
+
+/// Summarizes (medication id, perceived effect) intake rows against the catalog.
+fn medication_usage(conn: &mut SqliteConnection, intakes: &[(i32, Option<i32>)]) -> Result<Vec<MedicationUsageStats>, Error> {
+    let catalog = list_medications(conn)?;
+
+    Ok(catalog
+        .into_iter()
+        .map(|medication| {
+            let doses: Vec<Option<i32>> = intakes
+                .iter()
+                .filter(|(id, _)| *id == medication.id)
+                .map(|(_, effect)| *effect)
+                .collect();
+            let effects: Vec<i32> = doses.iter().filter_map(|e| *e).collect();
+
+            MedicationUsageStats {
+                medication_id: medication.id,
+                name: medication.name,
+                drug_class: medication.drug_class,
+                strength: medication.strength,
+                usage: medication.usage,
+                intake_count: doses.len() as i64,
+                average_effect: if effects.is_empty() {
+                    None
+                } else {
+                    Some(effects.iter().sum::<i32>() as f32 / effects.len() as f32)
+                },
+            }
+        })
+        .collect())
+}
+
+pub fn list_courses(conn: &mut SqliteConnection, user_id: i32, medication_id: i32) -> Result<Vec<MedicationCourse>, Error> {
+    get_medication(conn, medication_id)?;
+
+    medication_courses::table
+        .filter(medication_courses::medication_id.eq(medication_id))
+        .filter(medication_courses::user_id.eq(user_id))
+        .order(medication_courses::started_on.asc())
+        .load::<MedicationCourse>(conn)
+}
+
+pub fn create_course(conn: &mut SqliteConnection, user_id: i32, medication_id: i32, request: &CourseRequest) -> Result<MedicationCourse, Error> {
+    conn.transaction(|conn| {
+        get_medication(conn, medication_id)?;
+
+        diesel::insert_into(medication_courses::table)
+            .values(&NewMedicationCourse {
+                user_id,
+                medication_id,
+                started_on: request.started_on,
+                stopped_on: request.stopped_on,
+                notes: request.notes.as_deref().map(str::trim).filter(|n| !n.is_empty()),
+            })
+            .execute(conn)?;
+
+        medication_courses::table
+            .order(medication_courses::id.desc())
+            .first::<MedicationCourse>(conn)
+    })
+}
+
+pub fn delete_course(conn: &mut SqliteConnection, user_id: i32, medication_id: i32, course_id: i32) -> Result<usize, Error> {
+    diesel::delete(
+        medication_courses::table
+            .filter(medication_courses::id.eq(course_id))
+            .filter(medication_courses::medication_id.eq(medication_id))
+            .filter(medication_courses::user_id.eq(user_id)),
+    )
+    .execute(conn)
+}
+
+/// Every course the user has recorded, across all medications.
+pub fn get_user_courses(conn: &mut SqliteConnection, user_id: i32) -> Result<Vec<MedicationCourse>, Error> {
+    medication_courses::table
+        .filter(medication_courses::user_id.eq(user_id))
+        .order((medication_courses::started_on.asc(), medication_courses::id.asc()))
+        .load::<MedicationCourse>(conn)
+}
+
+/// Before/after comparison for every started course of a preventive medication.
+pub fn get_medication_efficacy(conn: &mut SqliteConnection, user_id: i32, window: EfficacyWindow) -> Result<Vec<MedicationEfficacy>, Error> {
+    let all_episodes = get_all_episodes(conn, user_id)?;
+    medication_efficacy(conn, user_id, &all_episodes, window)
+}
+
+fn medication_efficacy(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    episodes: &[Episode],
+    window: EfficacyWindow,
+) -> Result<Vec<MedicationEfficacy>, Error> {
+    let courses = get_user_courses(conn, user_id)?;
+    if courses.is_empty() {
+        return Ok(vec![]);
+    }
+    let catalog = list_medications(conn)?;
+
+    Ok(crate::efficacy::analyze(&catalog, &courses, episodes, window, chrono::Utc::now().date_naive()))
+}
+
+pub fn create_share_link(conn: &mut SqliteConnection, new_link: &NewShareLink) -> Result<ShareLink, Error> {
+    conn.transaction(|conn| {
+        diesel::insert_into(share_links::table)
+            .values(new_link)
+            .execute(conn)?;
+
+        share_links::table
+            .order(share_links::id.desc())
+            .first::<ShareLink>(conn)
+    })
+}
+
+pub fn list_share_links(conn: &mut SqliteConnection, user_id: i32) -> Result<Vec<ShareLink>, Error> {
+    share_links::table
+        .filter(share_links::user_id.eq(user_id))
+        .order(share_links::created_at.desc())
+        .load::<ShareLink>(conn)
+}
+
+pub fn get_share_link(conn: &mut SqliteConnection, link_id: i32) -> Result<ShareLink, Error> {
+    share_links::table
+        .find(link_id)
+        .first(conn)
+}
+
+/// Revokes one of the user's links; revoking twice keeps the first timestamp.
+pub fn revoke_share_link(conn: &mut SqliteConnection, user_id: i32, link_id: i32) -> Result<usize, Error> {
+    conn.transaction(|conn| {
+        let scoped = share_links::table
+            .filter(share_links::id.eq(link_id))
+            .filter(share_links::user_id.eq(user_id));
+
+        let found = scoped.count().get_result::<i64>(conn)?;
+        diesel::update(scoped.filter(share_links::revoked_at.is_null()))
+            .set(share_links::revoked_at.eq(chrono::Utc::now().naive_utc()))
+            .execute(conn)?;
+
+        Ok(found as usize)
+    })
+}
+
+/// Bumps the failed PIN counter, revoking the link once it reaches `limit`.
+/// Returns true when this attempt locked the link.
+pub fn record_failed_pin(conn: &mut SqliteConnection, link_id: i32, limit: i32) -> Result<bool, Error> {
+    conn.transaction(|conn| {
+        diesel::update(share_links::table.find(link_id))
+            .set(share_links::failed_pin_attempts.eq(share_links::failed_pin_attempts + 1))
+            .execute(conn)?;
+
+        let link = get_share_link(conn, link_id)?;
+        if link.failed_pin_attempts >= limit && link.revoked_at.is_none() {
+            diesel::update(share_links::table.find(link_id))
+                .set(share_links::revoked_at.eq(chrono::Utc::now().naive_utc()))
+                .execute(conn)?;
+            return Ok(true);
+        }
+        Ok(false)
+    })
+}
+
+pub fn log_share_access(conn: &mut SqliteConnection, access: &NewShareAccess) -> Result<(), Error> {
+    diesel::insert_into(share_access_log::table)
+        .values(access)
+        .execute(conn)?;
+    Ok(())
+}
+
+pub fn get_share_access_log(conn: &mut SqliteConnection, user_id: i32, link_id: i32) -> Result<Vec<ShareAccess>, Error> {
+    // Surface NotFound for someone else's link rather than an empty log
+    share_links::table
+        .filter(share_links::id.eq(link_id))
+        .filter(share_links::user_id.eq(user_id))
+        .select(share_links::id)
+        .first::<i32>(conn)?;
+
+    share_access_log::table
+        .filter(share_access_log::share_link_id.eq(link_id))
+        .order((share_access_log::accessed_at.desc(), share_access_log::id.desc()))
+        .load::<ShareAccess>(conn)
+}
+
+/// Returns the named secret, generating and storing it on first use.
+pub fn get_or_create_secret(conn: &mut SqliteConnection, name: &str, generate: impl FnOnce() -> String) -> Result<String, Error> {
+    conn.transaction(|conn| {
+        let existing = server_secrets::table
+            .find(name)
+            .select(server_secrets::value)
+            .first::<String>(conn)
+            .optional()?;
+        if let Some(value) = existing {
+            return Ok(value);
+        }
+
+        let value = generate();
+        diesel::insert_into(server_secrets::table)
+            .values((server_secrets::name.eq(name), server_secrets::value.eq(&value)))
+            .execute(conn)?;
+        Ok(value)
+    })
+}
+
+pub fn create_calendar_feed(conn: &mut SqliteConnection, new_feed: &NewCalendarFeed) -> Result<CalendarFeed, Error> {
+    conn.transaction(|conn| {
+        diesel::insert_into(calendar_feeds::table)
+            .values(new_feed)
+            .execute(conn)?;
+
+        calendar_feeds::table
+            .filter(calendar_feeds::token_hash.eq(new_feed.token_hash))
+            .first::<CalendarFeed>(conn)
+    })
+}
+
+pub fn list_calendar_feeds(conn: &mut SqliteConnection, user_id: i32) -> Result<Vec<CalendarFeed>, Error> {
+    calendar_feeds::table
+        .filter(calendar_feeds::user_id.eq(user_id))
+        .order((calendar_feeds::created_at.desc(), calendar_feeds::id.desc()))
+        .load::<CalendarFeed>(conn)
+}
+
+pub fn delete_calendar_feed(conn: &mut SqliteConnection, user_id: i32, feed_id: i32) -> Result<usize, Error> {
+    diesel::delete(
+        calendar_feeds::table
+            .filter(calendar_feeds::id.eq(feed_id))
+            .filter(calendar_feeds::user_id.eq(user_id)),
+    )
+    .execute(conn)
+}
+
+/// Looks up a feed by token hash and records the fetch; unknown or deleted
+/// feeds are NotFound.
+pub fn fetch_calendar_feed(conn: &mut SqliteConnection, token_hash: &str) -> Result<CalendarFeed, Error> {
+    conn.transaction(|conn| {
+        let feed = calendar_feeds::table
+            .filter(calendar_feeds::token_hash.eq(token_hash))
+            .first::<CalendarFeed>(conn)?;
+
+        let now = chrono::Utc::now().naive_utc();
+        diesel::update(calendar_feeds::table.find(feed.id))
+            .set(calendar_feeds::last_fetched_at.eq(now))
+            .execute(conn)?;
+
+        Ok(CalendarFeed { last_fetched_at: Some(now), ..feed })
+    })
+}
+
+/// The user's episodes matching the filter, oldest first, for calendars.
+pub fn get_calendar_episodes(conn: &mut SqliteConnection, user_id: i32, filter: &EpisodeFilter) -> Result<Vec<Episode>, Error> {
+    filtered_episode_query(user_id, filter)
+        .order((episodes::timestamp.asc(), episodes::id.asc()))
         .load::<Episode>(conn)
 }
 
-pub fn get_analytics_data(conn: &mut SqliteConnection) -> Result<AnalyticsData, Error> {
-    let all_episodes = get_all_episodes(conn)?;
+/// Analytics over the user's episodes matching the filter.
+pub fn get_analytics_data(conn: &mut SqliteConnection, user_id: i32, filter: &EpisodeFilter) -> Result<AnalyticsData, Error> {
+    let all_episodes = get_filtered_episodes(conn, user_id, filter)?;
+    let episode_ids: Vec<i32> = all_episodes.iter().map(|e| e.id).collect();
+
+    let intakes = medication_intakes::table
+        .filter(medication_intakes::episode_id.eq_any(&episode_ids))
+        .select((medication_intakes::medication_id, medication_intakes::perceived_effect))
+        .load::<(i32, Option<i32>)>(conn)?;
+
+    // Efficacy windows are counted in days, so they need every episode in them
+    // rather than only those the filter lets through
+    let history = get_all_episodes(conn, user_id)?;
+    let medication_efficacy = medication_efficacy(conn, user_id, &history, EfficacyWindow::default())?;
 
     if all_episodes.is_empty() {
         return Ok(AnalyticsData {
@@ -71,6 +1424,9 @@ pub fn get_analytics_data(conn: &mut SqliteConnection) -> Result<AnalyticsData,
             average_severity: 0.0,
             severity_distribution: vec![],
             trigger_frequency: vec![],
+            symptom_frequency: vec![],
+            medication_usage: medication_usage(conn, &intakes)?,
+            medication_efficacy,
             monthly_trends: vec![],
             duration_stats: DurationStats {
                 average_minutes: 0.0,
@@ -97,23 +1453,12 @@ pub fn get_analytics_data(conn: &mut SqliteConnection) -> Result<AnalyticsData,
         .map(|(severity, count)| SeverityCount { severity, count })
         .collect();
 
-    // Trigger frequency
-    let mut trigger_counts = std::collections::HashMap::new();
-    for episode in &all_episodes {
-        if let Some(triggers) = &episode.triggers {
-            if !triggers.trim().is_empty() {
-                for trigger in triggers.split(',').map(|t| t.trim()) {
-                    if !trigger.is_empty() {
-                        *trigger_counts.entry(trigger.to_string()).or_insert(0) += 1;
-                    }
-                }
-            }
-        }
-    }
-    let trigger_frequency: Vec<TriggerCount> = trigger_counts
-        .into_iter()
-        .map(|(trigger, count)| TriggerCount { trigger, count })
-        .collect();
+    // Trigger frequency comes from the normalized trigger tables
+    let trigger_frequency = trigger_frequency(conn, &episode_ids)?;
+
+    let symptom_frequency = symptom_frequency(&all_episodes);
+
+    let medication_usage = medication_usage(conn, &intakes)?;
 
     // Monthly trends (simplified - group by month)
     let mut monthly_counts = std::collections::HashMap::new();
@@ -172,7 +1517,69 @@ pub fn get_analytics_data(conn: &mut SqliteConnection) -> Result<AnalyticsData,
         average_severity,
         severity_distribution,
         trigger_frequency,
+        symptom_frequency,
+        medication_usage,
+        medication_efficacy,
         monthly_trends,
         duration_stats,
     })
-}
\ No newline at end of file
+}
+
+/// How many of the given episodes link to each trigger, most frequent first.
+fn trigger_frequency(conn: &mut SqliteConnection, episode_ids: &[i32]) -> Result<Vec<TriggerCount>, Error> {
+    let mut frequency: Vec<TriggerCount> = episode_triggers::table
+        .inner_join(triggers::table)
+        .filter(episode_triggers::episode_id.eq_any(episode_ids))
+        .group_by((triggers::id, triggers::name))
+        .select((triggers::name, diesel::dsl::count_star()))
+        .load::<(String, i64)>(conn)?
+        .into_iter()
+        .map(|(trigger, count)| TriggerCount { trigger, count })
+        .collect();
+    frequency.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.trigger.cmp(&b.trigger)));
+
+    Ok(frequency)
+}
+
+/// Counts checklist symptoms across episodes that have a checklist recorded.
+fn symptom_frequency(episodes: &[Episode]) -> Vec<SymptomCount> {
+    use crate::models::VertigoType;
+
+    let checklists: Vec<&SymptomChecklist> = episodes
+        .iter()
+        .filter_map(|e| e.symptom_checklist.as_ref())
+        .collect();
+
+    if checklists.is_empty() {
+        return vec![];
+    }
+
+    let count = |predicate: &dyn Fn(&SymptomChecklist) -> bool| {
+        checklists.iter().filter(|c| predicate(c)).count() as i64
+    };
+
+    let mut frequency: Vec<SymptomCount> = [
+        ("Rotational vertigo", count(&|c| c.vertigo_type == Some(VertigoType::Rotational))),
+        ("Non-rotational vertigo", count(&|c| c.vertigo_type == Some(VertigoType::NonRotational))),
+        ("Nausea", count(&|c| c.nausea)),
+        ("Vomiting", count(&|c| c.vomiting)),
+        ("Tinnitus", count(&|c| c.tinnitus)),
+        ("Aural fullness", count(&|c| c.aural_fullness)),
+        ("Hearing loss", count(&|c| c.hearing_loss_side.is_some())),
+        ("Headache", count(&|c| c.headache)),
+        ("Visual aura", count(&|c| c.visual_aura)),
+        ("Imbalance", count(&|c| c.imbalance)),
+        ("Nystagmus", count(&|c| c.nystagmus_direction.is_some())),
+    ]
+    .into_iter()
+    .filter(|(_, count)| *count > 0)
+    .map(|(symptom, count)| SymptomCount {
+        symptom: symptom.to_string(),
+        count,
+        percentage: count as f32 * 100.0 / checklists.len() as f32,
+    })
+    .collect();
+
+    frequency.sort_by_key(|s| std::cmp::Reverse(s.count));
+    frequency
+}
@@ -0,0 +1,154 @@
+use chrono::{Duration, NaiveDate};
+
+use crate::models::{
+    EfficacyWindow, Episode, MeanComparison, Medication, MedicationCourse, MedicationEfficacy,
+    MedicationUsage, RateComparison, WindowStats,
+};
+
+/// z-score for a two-sided 95% interval (normal approximation).
+const Z_95: f64 = 1.96;
+
+/// Compares episode frequency, severity and duration before and after each
+/// course of a preventive medication started.
+pub fn analyze(
+    medications: &[Medication],
+    courses: &[MedicationCourse],
+    episodes: &[Episode],
+    window: EfficacyWindow,
+    today: NaiveDate,
+) -> Vec<MedicationEfficacy> {
+    let mut results: Vec<MedicationEfficacy> = courses
+        .iter()
+        .filter(|course| course.started_on <= today)
+        .filter_map(|course| {
+            let medication = medications
+                .iter()
+                .find(|m| m.id == course.medication_id && m.usage == MedicationUsage::Preventive)?;
+
+            let start = course.started_on;
+            let before_from = start - Duration::days(window.before_days);
+
+            // The after window stops at the course end (inclusive) or today, whichever is first
+            let mut after_to = start + Duration::days(window.after_days);
+            if let Some(stopped) = course.stopped_on {
+                after_to = after_to.min(stopped + Duration::days(1));
+            }
+            after_to = after_to.min(today + Duration::days(1));
+
+            let (before, before_severity, before_duration) = window_stats(episodes, before_from, start);
+            let (after, after_severity, after_duration) = window_stats(episodes, start, after_to);
+
+            Some(MedicationEfficacy {
+                medication_id: medication.id,
+                name: medication.name.clone(),
+                course_id: course.id,
+                started_on: course.started_on,
+                stopped_on: course.stopped_on,
+                frequency: compare_rates(&before, &after),
+                severity: compare_means(&before_severity, &after_severity),
+                duration: compare_means(&before_duration, &after_duration),
+                before,
+                after,
+            })
+        })
+        .collect();
+
+    results.sort_by(|a, b| a.name.cmp(&b.name).then(a.started_on.cmp(&b.started_on)));
+    results
+}
+
+/// Stats for episodes whose date falls in [from, to), plus the raw samples.
+fn window_stats(episodes: &[Episode], from: NaiveDate, to: NaiveDate) -> (WindowStats, Vec<f64>, Vec<f64>) {
+    let in_window: Vec<&Episode> = episodes
+        .iter()
+        .filter(|e| {
+            let date = e.timestamp.date();
+            date >= from && date < to
+        })
+        .collect();
+
+    let severities: Vec<f64> = in_window.iter().map(|e| e.severity as f64).collect();
+    let durations: Vec<f64> = in_window.iter().filter_map(|e| e.duration_minutes).map(|d| d as f64).collect();
+    let days = (to - from).num_days().max(0);
+
+    let stats = WindowStats {
+        from,
+        to,
+        days,
+        episode_count: in_window.len() as i64,
+        episodes_per_30_days: if days > 0 { in_window.len() as f64 * 30.0 / days as f64 } else { 0.0 },
+        mean_severity: mean(&severities),
+        mean_duration_minutes: mean(&durations),
+    };
+
+    (stats, severities, durations)
+}
+
+/// Poisson rate ratio (after / before) with a log-normal confidence interval.
+fn compare_rates(before: &WindowStats, after: &WindowStats) -> RateComparison {
+    if before.days == 0 || after.days == 0 {
+        return RateComparison { rate_ratio: None, ci_low: None, ci_high: None };
+    }
+
+    // Haldane correction keeps the ratio finite when either window had no episodes
+    let (mut a, mut b) = (after.episode_count as f64, before.episode_count as f64);
+    if a == 0.0 || b == 0.0 {
+        a += 0.5;
+        b += 0.5;
+    }
+
+    let ratio = (a / after.days as f64) / (b / before.days as f64);
+    let se = (1.0 / a + 1.0 / b).sqrt();
+
+    RateComparison {
+        rate_ratio: Some(ratio),
+        ci_low: Some(ratio * (-Z_95 * se).exp()),
+        ci_high: Some(ratio * (Z_95 * se).exp()),
+    }
+}
+
+/// Difference in means (after - before) with Cohen's d and a Welch-style interval.
+fn compare_means(before: &[f64], after: &[f64]) -> MeanComparison {
+    let (Some(mean_before), Some(mean_after)) = (mean(before), mean(after)) else {
+        return MeanComparison { difference: None, effect_size: None, ci_low: None, ci_high: None };
+    };
+
+    let difference = mean_after - mean_before;
+    let (var_before, var_after) = (variance(before), variance(after));
+
+    let effect_size = match (var_before, var_after) {
+        (Some(vb), Some(va)) => {
+            let (nb, na) = (before.len() as f64, after.len() as f64);
+            let pooled = (((nb - 1.0) * vb + (na - 1.0) * va) / (nb + na - 2.0)).sqrt();
+            (pooled > 0.0).then(|| difference / pooled)
+        }
+        _ => None,
+    };
+
+    let (ci_low, ci_high) = match (var_before, var_after) {
+        (Some(vb), Some(va)) => {
+            let se = (vb / before.len() as f64 + va / after.len() as f64).sqrt();
+            (Some(difference - Z_95 * se), Some(difference + Z_95 * se))
+        }
+        _ => (None, None),
+    };
+
+    MeanComparison { difference: Some(difference), effect_size, ci_low, ci_high }
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<f64>() / values.len() as f64)
+    }
+}
+
+/// Sample variance; needs at least two values.
+fn variance(values: &[f64]) -> Option<f64> {
+    if values.len() < 2 {
+        return None;
+    }
+    let m = mean(values)?;
+    Some(values.iter().map(|v| (v - m).powi(2)).sum::<f64>() / (values.len() - 1) as f64)
+}
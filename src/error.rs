@@ -0,0 +1,285 @@
+use axum::{
+    extract::Request,
+    http::{header::HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use serde::Serialize;
+use std::fmt;
+use utoipa::ToSchema;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// A problem with one field of the request.
+#[derive(Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Every way a request can fail. Each variant has a fixed status and `code`,
+/// so clients can branch on the code instead of parsing messages.
+#[derive(Debug)]
+pub enum AppError {
+    /// 422: the request was well-formed but its values are not acceptable
+    Validation { message: String, fields: Vec<FieldError> },
+    /// 400: the body or query string could not be parsed at all
+    BadRequest(String),
+    /// 415
+    UnsupportedMediaType(String),
+    /// 401
+    Unauthorized(String),
+    /// 403: signed in, but the account may not do this
+    Forbidden(String),
+    /// 404
+    NotFound(String),
+    /// 409
+    Conflict(String),
+    /// 410: a share link that existed but can no longer be used
+    Gone(String),
+    /// 502: the AI provider failed or answered with an error
+    AiUpstream(String),
+    /// 503: the connection pool is exhausted or the database stayed locked
+    DatabaseBusy,
+    /// 500: details are logged, never sent to the client
+    Internal(String),
+}
+
+/// The JSON body of every error response.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    /// Stable machine-readable code, e.g. `validation_failed`
+    pub code: String,
+    pub message: String,
+    /// Per-field problems; empty unless `code` is `validation_failed`
+    pub fields: Vec<FieldError>,
+    pub request_id: Option<String>,
+}
+
+impl AppError {
+    pub fn validation(message: impl Into<String>) -> Self {
+        AppError::Validation { message: message.into(), fields: Vec::new() }
+    }
+
+    pub fn not_found(what: &str) -> Self {
+        AppError::NotFound(format!("{} not found", what))
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::Validation { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Gone(_) => StatusCode::GONE,
+            AppError::AiUpstream(_) => StatusCode::BAD_GATEWAY,
+            AppError::DatabaseBusy => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Validation { .. } => "validation_failed",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::UnsupportedMediaType(_) => "unsupported_media_type",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::Gone(_) => "gone",
+            AppError::AiUpstream(_) => "ai_upstream_failure",
+            AppError::DatabaseBusy => "database_busy",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// The message shown to the client.
+    pub fn message(&self) -> &str {
+        match self {
+            AppError::Validation { message, .. } => message,
+            AppError::BadRequest(message)
+            | AppError::UnsupportedMediaType(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::NotFound(message)
+            | AppError::Conflict(message)
+            | AppError::Gone(message)
+            | AppError::AiUpstream(message) => message,
+            AppError::DatabaseBusy => "the database is busy; retry shortly",
+            AppError::Internal(_) => "internal server error",
+        }
+    }
+
+    pub fn fields(&self) -> &[FieldError] {
+        match self {
+            AppError::Validation { fields, .. } => fields,
+            _ => &[],
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Internal(detail) => write!(f, "internal error: {}", detail),
+            other => write!(f, "{}: {}", other.code(), other.message()),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let request_id = current_request_id();
+
+        if let AppError::Internal(detail) = &self {
+            eprintln!("❌ [{}] {}", request_id.as_deref().unwrap_or("-"), detail);
+        }
+
+        let body = ErrorResponse {
+            code: self.code().to_string(),
+            message: self.message().to_string(),
+            fields: self.fields().to_vec(),
+            request_id,
+        };
+
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+impl From<DieselError> for AppError {
+    fn from(error: DieselError) -> Self {
+        match error {
+            DieselError::NotFound => AppError::NotFound("not found".to_string()),
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                AppError::Conflict("a record with the same name already exists".to_string())
+            }
+            DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => {
+                AppError::Conflict("the record is missing a reference or is still referenced".to_string())
+            }
+            // Validation should have caught these; the schema is the last line of defence
+            DieselError::DatabaseError(DatabaseErrorKind::CheckViolation, info) => AppError::validation(info.message().to_string()),
+            // SQLITE_BUSY surfaces only after busy_timeout has run out
+            DieselError::DatabaseError(_, info) if info.message().contains("database is locked") => AppError::DatabaseBusy,
+            other => AppError::Internal(other.to_string()),
+        }
+    }
+}
+
+impl From<validator::ValidationErrors> for AppError {
+    fn from(errors: validator::ValidationErrors) -> Self {
+        let mut fields: Vec<FieldError> = errors
+            .field_errors()
+            .into_iter()
+            .flat_map(|(field, errors)| {
+                errors.iter().map(move |error| FieldError { field: field_name(field, error), message: describe(error) })
+            })
+            .collect();
+        fields.sort_by(|a, b| a.field.cmp(&b.field));
+
+        let message = match fields.as_slice() {
+            [only] => format!("{} {}", only.field, only.message),
+            _ => format!("{} fields are invalid", fields.len()),
+        };
+        AppError::Validation { message, fields }
+    }
+}
+
+/// The field an error belongs to. Struct-level rules are collected under
+/// `__all__` and name the field they are about in a `field` parameter.
+fn field_name(field: &str, error: &validator::ValidationError) -> String {
+    match error.params.get("field").and_then(|f| f.as_str()) {
+        Some(named) if field == "__all__" => named.to_string(),
+        _ => field.to_string(),
+    }
+}
+
+/// A readable message for one failed rule; the derived rules carry only a code and parameters.
+fn describe(error: &validator::ValidationError) -> String {
+    if let Some(message) = &error.message {
+        return message.to_string();
+    }
+    let param = |name: &str| error.params.get(name).map(|v| v.to_string());
+
+    match error.code.as_ref() {
+        "range" => match (param("min"), param("max")) {
+            (Some(min), Some(max)) => format!("must be between {} and {}", min, max),
+            (Some(min), None) => format!("must be at least {}", min),
+            (None, Some(max)) => format!("must be at most {}", max),
+            (None, None) => "is out of range".to_string(),
+        },
+        "length" => match (param("min"), param("max")) {
+            (Some(min), Some(max)) => format!("must be {} to {} characters", min, max),
+            (Some(min), None) if min == "1" => "must not be empty".to_string(),
+            (Some(min), None) => format!("must be at least {} characters", min),
+            (None, Some(max)) => format!("must be at most {} characters", max),
+            (None, None) => "has an invalid length".to_string(),
+        },
+        code => format!("is invalid ({})", code),
+    }
+}
+
+impl From<diesel::r2d2::PoolError> for AppError {
+    fn from(_: diesel::r2d2::PoolError) -> Self {
+        AppError::DatabaseBusy
+    }
+}
+
+impl From<tokio::task::JoinError> for AppError {
+    fn from(error: tokio::task::JoinError) -> Self {
+        AppError::Internal(format!("blocking task failed: {}", error))
+    }
+}
+
+impl From<printpdf::Error> for AppError {
+    fn from(error: printpdf::Error) -> Self {
+        AppError::Internal(format!("PDF generation failed: {}", error))
+    }
+}
+
+impl From<csv::Error> for AppError {
+    fn from(error: csv::Error) -> Self {
+        AppError::Internal(format!("CSV export failed: {}", error))
+    }
+}
+
+impl From<reqwest::Error> for AppError {
+    fn from(error: reqwest::Error) -> Self {
+        AppError::AiUpstream(format!("AI provider request failed: {}", error.without_url()))
+    }
+}
+
+/// The id of the request being handled, if the request-id middleware is in place.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Tags every request with an id, echoed in the `x-request-id` response header
+/// and in error bodies. A sane id supplied by the caller is kept, so logs on
+/// both sides line up.
+pub async fn assign_request_id(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| (1..=64).contains(&id.len()) && id.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+
+    let mut response = REQUEST_ID.scope(request_id.clone(), next.run(request)).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
@@ -0,0 +1,139 @@
+use chrono::NaiveDateTime;
+use diesel::sqlite::SqliteConnection;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use tokio::sync::mpsc;
+
+use crate::database;
+use crate::error::AppError;
+use crate::models::{Episode, EpisodeFilter, ExportColumn, ExportDelimiter, ExportQuery, MedicationIntake};
+
+/// Episodes loaded per query while exporting.
+pub const EXPORT_BATCH_SIZE: i64 = 500;
+
+impl ExportDelimiter {
+    pub fn byte(&self) -> u8 {
+        match self {
+            ExportDelimiter::Comma => b',',
+            ExportDelimiter::Semicolon => b';',
+            ExportDelimiter::Tab => b'\t',
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportDelimiter::Tab => "text/tab-separated-values; charset=utf-8",
+            _ => "text/csv; charset=utf-8",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportDelimiter::Tab => "tsv",
+            _ => "csv",
+        }
+    }
+}
+
+/// One batch of episodes, newest first, with their intakes by episode id.
+pub type ExportBatch = (Vec<Episode>, HashMap<i32, Vec<MedicationIntake>>);
+
+/// The batch of the user's filtered episodes that follows the `(timestamp, id)`
+/// of the previous batch's last row.
+pub fn load_batch(conn: &mut SqliteConnection, user_id: i32, filter: &EpisodeFilter, after: Option<(NaiveDateTime, i32)>) -> Result<ExportBatch, diesel::result::Error> {
+    let episodes = database::get_episode_batch(conn, user_id, filter, after, EXPORT_BATCH_SIZE)?;
+    let ids: Vec<i32> = episodes.iter().map(|e| e.id).collect();
+    let intakes = database::get_intakes_for_episodes(conn, user_id, &ids)?;
+    Ok((episodes, intakes))
+}
+
+/// Writes the user's episodes as RFC 4180 CSV: CRLF line endings, and any
+/// field holding the delimiter, a quote or a line break is quoted with inner
+/// quotes doubled. Episodes come from `next_batch`, usually [`load_batch`],
+/// and are flushed to `out` after each one, so memory use does not grow with
+/// the history. A slow reader of `out` only ever waits between batches, so
+/// `next_batch` can take a connection for each call and give it back.
+pub fn write_csv<W: Write>(
+    query: &ExportQuery,
+    out: W,
+    mut next_batch: impl FnMut(Option<(NaiveDateTime, i32)>) -> Result<ExportBatch, AppError>,
+) -> Result<(), AppError> {
+    let columns = query.columns().map_err(AppError::validation)?;
+
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(query.delimiter().byte())
+        .terminator(csv::Terminator::CRLF)
+        .from_writer(out);
+
+    writer.write_record(columns.iter().map(ExportColumn::header))?;
+
+    let mut after = None;
+    loop {
+        let (episodes, mut intakes) = next_batch(after)?;
+
+        for episode in &episodes {
+            let episode_intakes = intakes.remove(&episode.id).unwrap_or_default();
+            writer.write_record(columns.iter().map(|column| field(episode, &episode_intakes, *column)))?;
+        }
+        writer.flush().map_err(csv::Error::from)?;
+
+        match episodes.last() {
+            Some(last) if episodes.len() as i64 == EXPORT_BATCH_SIZE => after = Some((last.timestamp, last.id)),
+            _ => return Ok(()),
+        }
+    }
+}
+
+fn field(episode: &Episode, intakes: &[MedicationIntake], column: ExportColumn) -> String {
+    let text = |value: &Option<String>| value.clone().unwrap_or_default();
+
+    match column {
+        ExportColumn::Id => episode.id.to_string(),
+        ExportColumn::Timestamp => episode.timestamp.to_string(),
+        ExportColumn::DurationMinutes => episode.duration_minutes.map_or(String::new(), |d| d.to_string()),
+        ExportColumn::Severity => episode.severity.to_string(),
+        ExportColumn::Symptoms => text(&episode.symptoms),
+        ExportColumn::Triggers => text(&episode.triggers),
+        ExportColumn::Location => text(&episode.location),
+        ExportColumn::ActivitiesBefore => text(&episode.activities_before),
+        ExportColumn::Medications => text(&episode.medications_taken),
+        ExportColumn::MedicationIntakes => intake_summary(intakes),
+        ExportColumn::Notes => text(&episode.notes),
+        ExportColumn::AiAnalysis => text(&episode.ai_analysis),
+    }
+}
+
+/// e.g. "Betahistine 16 mg @+30min effect 3/5; Meclizine 25 mg"
+fn intake_summary(intakes: &[MedicationIntake]) -> String {
+    intakes
+        .iter()
+        .map(|i| {
+            let mut entry = format!("{} {}", i.medication_name, i.dose);
+            if let Some(minutes) = i.minutes_after_onset {
+                entry.push_str(&format!(" @{:+}min", minutes));
+            }
+            if let Some(effect) = i.perceived_effect {
+                entry.push_str(&format!(" effect {}/5", effect));
+            }
+            entry
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Hands each flushed chunk to the response body. Once the client has gone
+/// away the send fails, which stops the export at its next write.
+pub struct ChannelWriter(pub mpsc::Sender<io::Result<Vec<u8>>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(Ok(buf.to_vec()))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "export download was abandoned"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
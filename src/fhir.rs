@@ -0,0 +1,231 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::models::{Episode, Medication, MedicationCourse, MedicationIntake, SymptomChecklist, User};
+
+pub const CONTENT_TYPE: &str = "application/fhir+json";
+
+const SNOMED: &str = "http://snomed.info/sct";
+const UCUM: &str = "http://unitsofmeasure.org";
+const OBSERVATION_CATEGORY: &str = "http://terminology.hl7.org/CodeSystem/observation-category";
+const STATEMENT_CATEGORY: &str = "http://terminology.hl7.org/CodeSystem/medication-statement-category";
+
+/// Identifier systems for our own records, so a receiving EHR can recognise re-sent resources.
+const USER_SYSTEM: &str = "urn:vertigo-logger:user";
+const EPISODE_SYSTEM: &str = "urn:vertigo-logger:episode";
+const INTAKE_SYSTEM: &str = "urn:vertigo-logger:medication-intake";
+const COURSE_SYSTEM: &str = "urn:vertigo-logger:medication-course";
+
+/// Labels machine-written text so nobody reading the record mistakes it for a clinician's.
+pub const AI_ANALYSIS_AUTHOR: &str = "Vertigo Logger AI analysis (not reviewed by a clinician)";
+
+/// Everything going into one export.
+pub struct FhirExport<'a> {
+    pub user: &'a User,
+    pub episodes: &'a [Episode],
+    pub intakes: &'a HashMap<i32, Vec<MedicationIntake>>,
+    pub courses: &'a [MedicationCourse],
+    pub catalog: &'a [Medication],
+}
+
+/// A FHIR R4 `collection` Bundle: the user as a Patient, each episode as a
+/// SNOMED-coded vertigo Observation, and each intake and medication course as
+/// a MedicationStatement. Entries reference each other by `urn:uuid` full URLs.
+pub fn bundle(export: &FhirExport, generated_at: DateTime<Utc>) -> Value {
+    let patient_url = urn();
+    let mut entries = vec![entry(&patient_url, patient(export.user))];
+
+    for episode in export.episodes {
+        let observation_url = urn();
+        entries.push(entry(&observation_url, observation(episode, &patient_url)));
+
+        for intake in export.intakes.get(&episode.id).into_iter().flatten() {
+            entries.push(entry(&urn(), intake_statement(intake, episode, &patient_url, &observation_url)));
+        }
+    }
+
+    for course in export.courses {
+        let medication = export.catalog.iter().find(|m| m.id == course.medication_id);
+        entries.push(entry(&urn(), course_statement(course, medication, &patient_url)));
+    }
+
+    json!({
+        "resourceType": "Bundle",
+        "type": "collection",
+        "timestamp": generated_at.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        "entry": entries,
+    })
+}
+
+fn urn() -> String {
+    format!("urn:uuid:{}", uuid::Uuid::new_v4())
+}
+
+fn entry(full_url: &str, resource: Value) -> Value {
+    json!({ "fullUrl": full_url, "resource": resource })
+}
+
+fn reference(url: &str) -> Value {
+    json!({ "reference": url })
+}
+
+fn snomed(code: &str, display: &str) -> Value {
+    json!({ "coding": [{ "system": SNOMED, "code": code, "display": display }] })
+}
+
+/// Timestamps are stored as naive UTC.
+fn date_time(timestamp: NaiveDateTime) -> String {
+    timestamp.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+fn date(date: NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+fn patient(user: &User) -> Value {
+    json!({
+        "resourceType": "Patient",
+        "identifier": [{ "system": USER_SYSTEM, "value": user.username }],
+    })
+}
+
+/// Our 1-5 scale onto the SNOMED severity qualifiers.
+fn severity_grade(severity: i32) -> Value {
+    match severity {
+        ..=2 => snomed("255604002", "Mild"),
+        3 => snomed("6736007", "Moderate"),
+        _ => snomed("24484000", "Severe"),
+    }
+}
+
+fn observation(episode: &Episode, patient_url: &str) -> Value {
+    let mut components = vec![json!({
+        "code": snomed("246112005", "Severity"),
+        "valueCodeableConcept": severity_grade(episode.severity),
+    })];
+    if let Some(minutes) = episode.duration_minutes {
+        components.push(json!({
+            "code": snomed("103335007", "Duration"),
+            "valueQuantity": { "value": minutes, "unit": "min", "system": UCUM, "code": "min" },
+        }));
+    }
+
+    let mut resource = json!({
+        "resourceType": "Observation",
+        "identifier": [{ "system": EPISODE_SYSTEM, "value": episode.id.to_string() }],
+        "status": "final",
+        "category": [{
+            "coding": [{ "system": OBSERVATION_CATEGORY, "code": "survey", "display": "Survey" }],
+        }],
+        "code": {
+            "coding": [{ "system": SNOMED, "code": "399153001", "display": "Vertigo" }],
+            "text": "Vertigo episode",
+        },
+        "subject": reference(patient_url),
+        // The patient's own 1-5 rating; the severity component carries the coded grade
+        "valueInteger": episode.severity,
+        "component": components,
+    });
+
+    match episode.duration_minutes {
+        Some(minutes) => {
+            let end = episode.timestamp + chrono::Duration::minutes(minutes.into());
+            resource["effectivePeriod"] = json!({ "start": date_time(episode.timestamp), "end": date_time(end) });
+        }
+        None => resource["effectiveDateTime"] = json!(date_time(episode.timestamp)),
+    }
+
+    let notes = episode_notes(episode, patient_url);
+    if !notes.is_empty() {
+        resource["note"] = json!(notes);
+    }
+    resource
+}
+
+fn episode_notes(episode: &Episode, patient_url: &str) -> Vec<Value> {
+    let labelled = [
+        ("Symptoms", episode.symptoms.clone()),
+        ("Symptom checklist", episode.symptom_checklist.as_ref().and_then(SymptomChecklist::summary)),
+        ("Triggers", episode.triggers.clone()),
+        ("Location", episode.location.clone()),
+        ("Activities before", episode.activities_before.clone()),
+        ("Medications taken", episode.medications_taken.clone()),
+        ("Notes", episode.notes.clone()),
+    ];
+
+    let mut notes: Vec<Value> = labelled
+        .into_iter()
+        .filter_map(|(label, text)| text.filter(|t| !t.trim().is_empty()).map(|t| (label, t)))
+        .map(|(label, text)| json!({ "authorReference": reference(patient_url), "text": format!("{}: {}", label, text) }))
+        .collect();
+
+    if let Some(analysis) = episode.ai_analysis.as_deref().filter(|a| !a.trim().is_empty()) {
+        notes.push(json!({
+            "authorString": AI_ANALYSIS_AUTHOR,
+            "text": format!("AI-generated analysis, not reviewed by a clinician: {}", analysis),
+        }));
+    }
+    notes
+}
+
+fn intake_statement(intake: &MedicationIntake, episode: &Episode, patient_url: &str, observation_url: &str) -> Value {
+    let mut resource = json!({
+        "resourceType": "MedicationStatement",
+        "identifier": [{ "system": INTAKE_SYSTEM, "value": intake.id.to_string() }],
+        "status": "completed",
+        "category": {
+            "coding": [{ "system": STATEMENT_CATEGORY, "code": "patientspecified", "display": "Patient Specified" }],
+        },
+        "medicationCodeableConcept": { "text": intake.medication_name },
+        "subject": reference(patient_url),
+        "dateAsserted": date_time(intake.created_at),
+        "reasonReference": [reference(observation_url)],
+        "dosage": [{ "text": intake.dose }],
+    });
+
+    // When the dose was taken is only known if the patient recorded it
+    if let Some(minutes) = intake.minutes_after_onset {
+        let taken_at = episode.timestamp + chrono::Duration::minutes(minutes.into());
+        resource["effectiveDateTime"] = json!(date_time(taken_at));
+    }
+
+    if let Some(effect) = intake.perceived_effect {
+        resource["note"] = json!([{
+            "authorReference": reference(patient_url),
+            "text": format!("Perceived effect: {} of 5", effect),
+        }]);
+    }
+    resource
+}
+
+fn course_statement(course: &MedicationCourse, medication: Option<&Medication>, patient_url: &str) -> Value {
+    let name = match medication {
+        Some(Medication { name, strength: Some(strength), .. }) => format!("{} {}", name, strength),
+        Some(medication) => medication.name.clone(),
+        None => format!("Medication {}", course.medication_id),
+    };
+
+    let mut period = json!({ "start": date(course.started_on) });
+    if let Some(stopped) = course.stopped_on {
+        period["end"] = json!(date(stopped));
+    }
+
+    let mut resource = json!({
+        "resourceType": "MedicationStatement",
+        "identifier": [{ "system": COURSE_SYSTEM, "value": course.id.to_string() }],
+        "status": if course.stopped_on.is_some() { "completed" } else { "active" },
+        "category": {
+            "coding": [{ "system": STATEMENT_CATEGORY, "code": "patientspecified", "display": "Patient Specified" }],
+        },
+        "medicationCodeableConcept": { "text": name },
+        "subject": reference(patient_url),
+        "effectivePeriod": period,
+        "dateAsserted": date_time(course.created_at),
+    });
+
+    if let Some(notes) = course.notes.as_deref().filter(|n| !n.trim().is_empty()) {
+        resource["note"] = json!([{ "authorReference": reference(patient_url), "text": notes }]);
+    }
+    resource
+}
@@ -1,189 +1,1572 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::Json,
-    Json as JsonExtractor,
+    extract::{ConnectInfo, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
 };
-use std::sync::{Arc, Mutex};
 
 use crate::ai_service::AIService;
-use crate::database::{self, DbConnection};
-use crate::models::{Episode, NewEpisode, EpisodeUpdate, AnalysisRequest, AnalysisResponse, AnalyticsData, PatternAnalysis};
+use crate::auth::{self, AdminUser, AuthUser};
+use crate::share::{self, Visitor};
+use crate::database::{self, DbConnection, DbPool};
+use crate::error::AppError;
+use crate::export::{self, ChannelWriter};
+use crate::fhir::{self, FhirExport};
+use crate::ical;
+use crate::extract::{Form, Json as JsonExtractor, Path, Query};
+use crate::import::{self, ImportFormat};
+use crate::models::{ShareLink, ShareRequest, NewShareLink, CreatedShare, ShareAccess, ShareUnlock, ShareResource, ShareOutcome, CalendarFeed, CalendarFeedRequest, NewCalendarFeed, CreatedCalendarFeed, EpisodeFilter, ReportQuery, ReportSection, ReportTemplate, ExportQuery, ImportQuery, ImportReport, User, Credentials, NewSession, SessionResponse, Episode, EpisodeListQuery, EpisodePage, EpisodeSearchQuery, EpisodeSearchHit, NewEpisode, EpisodeUpdate, EpisodeRevision, Trigger, TriggerSummary, TriggerRename, TriggerMerge, NewVocabularyTerm, TriggerSynonym, NewTriggerSynonym, TriggerRemap, Medication, NewMedication, MedicationUpdate, MedicationIntake, IntakeRequest, IntakeUpdate, MedicationCourse, CourseRequest, EfficacyWindow, MedicationEfficacy, AnalysisRequest, AnalysisResponse, AnalyticsData, PatternAnalysis};
 use crate::pdf_generator::PDFReportGenerator;
+use std::net::SocketAddr;
+use tokio_stream::wrappers::ReceiverStream;
+use validator::Validate;
 
-pub type AppState = Arc<Mutex<DbConnection>>;
+pub type AppState = DbPool;
 
+/// Runs Diesel work on a blocking worker thread with a pooled connection,
+/// so slow queries never stall the async executor.
+pub(crate) async fn with_conn<T, F>(db: &AppState, f: F) -> Result<T, AppError>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut DbConnection) -> Result<T, diesel::result::Error> + Send + 'static,
+{
+    let pool = db.clone();
+
+    tokio::task::spawn_blocking(move || {
+        // Pool exhaustion means we are overloaded, not broken
+        let mut conn = pool.get()?;
+
+        Ok(f(&mut conn)?)
+    })
+    .await?
+}
+
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "system",
+    responses(
+        (status = 200, description = "Server is up", body = String, content_type = "text/plain"),
+    ),
+    security(()),
+)]
 pub async fn health_check() -> &'static str {
     "OK"
 }
 
+/// Starts a session for the user and sets it as a cookie alongside the JSON body.
+fn start_session(conn: &mut DbConnection, user: User) -> Result<(String, SessionResponse), diesel::result::Error> {
+    let token = auth::new_session_token();
+    let ttl = auth::session_ttl();
+    let session = database::create_session(conn, &NewSession {
+        user_id: user.id,
+        token_hash: &auth::token_hash(&token),
+        expires_at: chrono::Utc::now().naive_utc() + ttl,
+    })?;
+
+    Ok((auth::session_cookie(&token, ttl), SessionResponse { token, expires_at: session.expires_at, user }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/auth/register",
+    tag = "auth",
+    request_body = Credentials,
+    responses(
+        (status = 200, description = "Account created and signed in", body = SessionResponse),
+        (status = 409, description = "The username is taken", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+    security(()),
+)]
+pub async fn register(
+    State(db): State<AppState>,
+    JsonExtractor(credentials): JsonExtractor<Credentials>,
+) -> Result<impl IntoResponse, AppError> {
+    credentials.validate()?;
+
+    // Argon2 is deliberately slow, so keep it off the async executor
+    let password = credentials.password.clone();
+    let password_hash = tokio::task::spawn_blocking(move || auth::hash_password(&password))
+        .await?
+        .map_err(|e| AppError::Internal(format!("password hashing failed: {}", e)))?;
+
+    // Taken usernames are a 409
+    let (cookie, session) = with_conn(&db, move |conn| {
+        let user = database::create_user(conn, &credentials.username, &password_hash)?;
+        start_session(conn, user)
+    }).await?;
+
+    Ok(([(header::SET_COOKIE, cookie)], Json(session)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/auth/login",
+    tag = "auth",
+    request_body = Credentials,
+    responses(
+        (status = 200, description = "Signed in; the token is also set as a cookie", body = SessionResponse),
+        (status = 401, description = "Invalid username or password", body = ErrorResponse),
+    ),
+    security(()),
+)]
+pub async fn login(
+    State(db): State<AppState>,
+    JsonExtractor(credentials): JsonExtractor<Credentials>,
+) -> Result<impl IntoResponse, AppError> {
+    let username = credentials.username.clone();
+    let user = with_conn(&db, move |conn| database::find_user_by_username(conn, &username)).await?;
+
+    // Argon2 is deliberately slow, so it runs off the async executor and after
+    // the connection is back in the pool. Unknown usernames are checked against
+    // a stand-in hash so they take as long as a wrong password.
+    let verified = tokio::task::spawn_blocking(move || {
+        let hash = user.as_ref().map_or(auth::dummy_password_hash(), |u| u.password_hash.as_str());
+        let matches = auth::verify_password(&credentials.password, hash);
+        user.filter(|_| matches)
+    })
+    .await?;
+
+    // Unknown usernames and wrong passwords look the same to the caller
+    let user = verified.ok_or_else(|| AppError::Unauthorized("invalid username or password".to_string()))?;
+    let (cookie, session) = with_conn(&db, move |conn| start_session(conn, user)).await?;
+
+    Ok(([(header::SET_COOKIE, cookie)], Json(session)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/auth/logout",
+    tag = "auth",
+    responses(
+        (status = 204, description = "Signed out"),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+    ),
+)]
+pub async fn logout(
+    State(db): State<AppState>,
+    user: AuthUser,
+) -> Result<impl IntoResponse, AppError> {
+    with_conn(&db, move |conn| database::delete_session(conn, user.session_id)).await?;
+
+    Ok(([(header::SET_COOKIE, auth::expired_session_cookie())], StatusCode::NO_CONTENT))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/auth/me",
+    tag = "auth",
+    responses(
+        (status = 200, description = "The signed-in user", body = User),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+    ),
+)]
+pub async fn current_user(
+    State(db): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<User>, AppError> {
+    let user = with_conn(&db, move |conn| database::get_user(conn, user.id)).await?;
+
+    Ok(Json(user))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/episodes",
+    tag = "episodes",
+    request_body = NewEpisode,
+    responses(
+        (status = 200, description = "The stored episode", body = Episode),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
 pub async fn create_episode(
     State(db): State<AppState>,
+    user: AuthUser,
     JsonExtractor(new_episode): JsonExtractor<NewEpisode>,
-) -> Result<Json<Episode>, StatusCode> {
-    let mut conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+) -> Result<Json<Episode>, AppError> {
+    new_episode.validate()?;
 
-    let episode = database::create_episode(&mut conn, &new_episode)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let episode = with_conn(&db, move |conn| database::create_episode(conn, user.id, &new_episode)).await?;
 
     Ok(Json(episode))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/episodes",
+    tag = "episodes",
+    params(EpisodeFilter, EpisodeListQuery),
+    responses(
+        (status = 200, description = "One page of episodes", body = EpisodePage),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
 pub async fn get_episodes(
     State(db): State<AppState>,
-) -> Result<Json<Vec<Episode>>, StatusCode> {
-    let mut conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    user: AuthUser,
+    Query(filter): Query<EpisodeFilter>,
+    Query(params): Query<EpisodeListQuery>,
+) -> Result<Json<EpisodePage>, AppError> {
+    filter.validate()?;
+    params.validate()?;
 
-    let episodes = database::get_all_episodes(&mut conn)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let page = with_conn(&db, move |conn| database::list_episodes(conn, user.id, &filter, &params)).await?;
 
-    Ok(Json(episodes))
+    Ok(Json(page))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/episodes/search",
+    tag = "episodes",
+    params(EpisodeSearchQuery),
+    responses(
+        (status = 200, description = "Matches, best first", body = [EpisodeSearchHit]),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
+pub async fn search_episodes(
+    State(db): State<AppState>,
+    user: AuthUser,
+    Query(search): Query<EpisodeSearchQuery>,
+) -> Result<Json<Vec<EpisodeSearchHit>>, AppError> {
+    search.validate()?;
+
+    let hits = with_conn(&db, move |conn| database::search_episodes(conn, user.id, &search)).await?;
+
+    Ok(Json(hits))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/episodes/{id}",
+    tag = "episodes",
+    params(("id" = i32, Path, description = "Episode id")),
+    responses(
+        (status = 200, description = "OK", body = Episode),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 404, description = "No such episode", body = ErrorResponse),
+    ),
+)]
 pub async fn get_episode(
     State(db): State<AppState>,
+    user: AuthUser,
     Path(id): Path<i32>,
-) -> Result<Json<Episode>, StatusCode> {
-    let mut conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let episode = database::get_episode_by_id(&mut conn, id)
-        .map_err(|e| match e {
-            diesel::result::Error::NotFound => StatusCode::NOT_FOUND,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        })?;
+) -> Result<Json<Episode>, AppError> {
+    let episode = with_conn(&db, move |conn| database::get_episode_by_id(conn, user.id, id)).await?;
 
     Ok(Json(episode))
 }
 
+#[utoipa::path(
+    put,
+    path = "/api/episodes/{id}",
+    tag = "episodes",
+    params(("id" = i32, Path, description = "Episode id")),
+    request_body = EpisodeUpdate,
+    responses(
+        (status = 200, description = "The updated episode", body = Episode),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 404, description = "No such episode", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
 pub async fn update_episode(
     State(db): State<AppState>,
+    user: AuthUser,
     Path(id): Path<i32>,
     JsonExtractor(episode_update): JsonExtractor<EpisodeUpdate>,
-) -> Result<Json<Episode>, StatusCode> {
-    let mut conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+) -> Result<Json<Episode>, AppError> {
+    episode_update.validate()?;
 
-    let episode = database::update_episode(&mut conn, id, &episode_update)
-        .map_err(|e| match e {
-            diesel::result::Error::NotFound => StatusCode::NOT_FOUND,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        })?;
+    // History records who made the change
+    let episode = with_conn(&db, move |conn| {
+        database::update_episode(conn, user.id, id, &episode_update, &user.username)
+    }).await?;
 
     Ok(Json(episode))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/episodes/{id}/history",
+    tag = "episodes",
+    params(("id" = i32, Path, description = "Episode id")),
+    responses(
+        (status = 200, description = "Revisions, oldest first", body = [EpisodeRevision]),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 404, description = "No such episode", body = ErrorResponse),
+    ),
+)]
+pub async fn get_episode_history(
+    State(db): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<EpisodeRevision>>, AppError> {
+    let history = with_conn(&db, move |conn| database::get_episode_history(conn, user.id, id)).await?;
+
+    Ok(Json(history))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/episodes/{id}",
+    tag = "episodes",
+    params(("id" = i32, Path, description = "Episode id")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 404, description = "No such episode", body = ErrorResponse),
+    ),
+)]
 pub async fn delete_episode(
     State(db): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, AppError> {
+    let rows_affected = with_conn(&db, move |conn| database::delete_episode(conn, user.id, id)).await?;
+
+    if rows_affected == 0 {
+        Err(AppError::not_found("episode"))
+    } else {
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/triggers",
+    tag = "triggers",
+    responses(
+        (status = 200, description = "Triggers with usage counts", body = [TriggerSummary]),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+    ),
+)]
+pub async fn list_triggers(
+    State(db): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<Vec<TriggerSummary>>, AppError> {
+    let triggers = with_conn(&db, move |conn| database::list_triggers(conn, user.id)).await?;
+
+    Ok(Json(triggers))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/triggers/{id}",
+    tag = "triggers",
+    params(("id" = i32, Path, description = "Trigger id")),
+    request_body = TriggerRename,
+    responses(
+        (status = 200, description = "The trigger your episodes now carry", body = Trigger),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 404, description = "No such trigger", body = ErrorResponse),
+        (status = 409, description = "Another of your triggers already has the name, or only the case changes on a trigger others use", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
+pub async fn rename_trigger(
+    State(db): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+    JsonExtractor(rename): JsonExtractor<TriggerRename>,
+) -> Result<Json<Trigger>, AppError> {
+    rename.validate()?;
+
+    // Only the caller's episodes change; renaming onto another of their triggers is a 409, merge those instead
+    let trigger = with_conn(&db, move |conn| database::rename_trigger(conn, user.id, id, &rename.name)).await?;
+
+    Ok(Json(trigger))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/triggers/merge",
+    tag = "triggers",
+    request_body = TriggerMerge,
+    responses(
+        (status = 200, description = "The merged trigger", body = TriggerSummary),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 404, description = "No such trigger", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
+pub async fn merge_triggers(
+    State(db): State<AppState>,
+    user: AuthUser,
+    JsonExtractor(merge): JsonExtractor<TriggerMerge>,
+) -> Result<Json<TriggerSummary>, AppError> {
+    merge.validate()?;
+
+    let merged = with_conn(&db, move |conn| {
+        database::merge_triggers(conn, user.id, &merge.source_ids, merge.target_id)
+    }).await?;
+
+    Ok(Json(merged))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/triggers",
+    tag = "triggers",
+    request_body = NewVocabularyTerm,
+    responses(
+        (status = 200, description = "The new vocabulary term", body = Trigger),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 403, description = "Only an admin can change it", body = ErrorResponse),
+        (status = 409, description = "The term already exists", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
+pub async fn create_vocabulary_term(
+    State(db): State<AppState>,
+    _admin: AdminUser,
+    JsonExtractor(term): JsonExtractor<NewVocabularyTerm>,
+) -> Result<Json<Trigger>, AppError> {
+    term.validate()?;
+
+    let trigger = with_conn(&db, move |conn| database::create_vocabulary_term(conn, &term.name)).await?;
+
+    Ok(Json(trigger))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/triggers/synonyms",
+    tag = "triggers",
+    responses(
+        (status = 200, description = "OK", body = [TriggerSynonym]),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+    ),
+)]
+pub async fn list_synonyms(
+    State(db): State<AppState>,
+    _user: AuthUser,
+) -> Result<Json<Vec<TriggerSynonym>>, AppError> {
+    let synonyms = with_conn(&db, database::list_synonyms).await?;
+
+    Ok(Json(synonyms))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/triggers/synonyms",
+    tag = "triggers",
+    request_body = NewTriggerSynonym,
+    responses(
+        (status = 200, description = "The new synonym", body = TriggerSynonym),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 403, description = "Only an admin can change it", body = ErrorResponse),
+        (status = 409, description = "The phrase is already mapped", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
+pub async fn create_synonym(
+    State(db): State<AppState>,
+    _admin: AdminUser,
+    JsonExtractor(synonym): JsonExtractor<NewTriggerSynonym>,
+) -> Result<Json<TriggerSynonym>, AppError> {
+    synonym.validate()?;
+
+    let created = with_conn(&db, move |conn| database::create_synonym(conn, &synonym)).await?;
+
+    Ok(Json(created))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/triggers/synonyms/{id}",
+    tag = "triggers",
+    params(("id" = i32, Path, description = "Synonym id")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 403, description = "Only an admin can change it", body = ErrorResponse),
+        (status = 404, description = "No such synonym", body = ErrorResponse),
+    ),
+)]
+pub async fn delete_synonym(
+    State(db): State<AppState>,
+    _admin: AdminUser,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, AppError> {
+    let rows_affected = with_conn(&db, move |conn| database::delete_synonym(conn, id)).await?;
+
+    if rows_affected == 0 {
+        Err(AppError::not_found("synonym"))
+    } else {
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/triggers/canonicalize",
+    tag = "triggers",
+    responses(
+        (status = 200, description = "Remaps that would be applied", body = [TriggerRemap]),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+    ),
+)]
+pub async fn preview_canonicalization(
+    State(db): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<Vec<TriggerRemap>>, AppError> {
+    let remaps = with_conn(&db, move |conn| database::preview_canonicalization(conn, user.id)).await?;
+
+    Ok(Json(remaps))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/triggers/canonicalize",
+    tag = "triggers",
+    responses(
+        (status = 200, description = "Remaps that were applied", body = [TriggerRemap]),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+    ),
+)]
+pub async fn apply_canonicalization(
+    State(db): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<Vec<TriggerRemap>>, AppError> {
+    let remaps = with_conn(&db, move |conn| database::apply_canonicalization(conn, user.id)).await?;
+
+    Ok(Json(remaps))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/medications",
+    tag = "medications",
+    responses(
+        (status = 200, description = "OK", body = [Medication]),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+    ),
+)]
+pub async fn list_medications(
+    State(db): State<AppState>,
+    _user: AuthUser,
+) -> Result<Json<Vec<Medication>>, AppError> {
+    let medications = with_conn(&db, database::list_medications).await?;
+
+    Ok(Json(medications))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/medications/{id}",
+    tag = "medications",
+    params(("id" = i32, Path, description = "Medication id")),
+    responses(
+        (status = 200, description = "OK", body = Medication),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 404, description = "No such medication", body = ErrorResponse),
+    ),
+)]
+pub async fn get_medication(
+    State(db): State<AppState>,
+    _user: AuthUser,
+    Path(id): Path<i32>,
+) -> Result<Json<Medication>, AppError> {
+    let medication = with_conn(&db, move |conn| database::get_medication(conn, id)).await?;
+
+    Ok(Json(medication))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/medications",
+    tag = "medications",
+    request_body = NewMedication,
+    responses(
+        (status = 200, description = "The new medication", body = Medication),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 403, description = "Only an admin can change it", body = ErrorResponse),
+        (status = 409, description = "A medication with the name exists", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
+pub async fn create_medication(
+    State(db): State<AppState>,
+    _admin: AdminUser,
+    JsonExtractor(new_medication): JsonExtractor<NewMedication>,
+) -> Result<Json<Medication>, AppError> {
+    new_medication.validate()?;
+
+    let medication = with_conn(&db, move |conn| database::create_medication(conn, &new_medication)).await?;
+
+    Ok(Json(medication))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/medications/{id}",
+    tag = "medications",
+    params(("id" = i32, Path, description = "Medication id")),
+    request_body = MedicationUpdate,
+    responses(
+        (status = 200, description = "The updated medication", body = Medication),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 403, description = "Only an admin can change it", body = ErrorResponse),
+        (status = 404, description = "No such medication", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
+pub async fn update_medication(
+    State(db): State<AppState>,
+    _admin: AdminUser,
+    Path(id): Path<i32>,
+    JsonExtractor(update): JsonExtractor<MedicationUpdate>,
+) -> Result<Json<Medication>, AppError> {
+    update.validate()?;
+
+    let medication = with_conn(&db, move |conn| database::update_medication(conn, id, &update)).await?;
+
+    Ok(Json(medication))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/medications/{id}",
+    tag = "medications",
+    params(("id" = i32, Path, description = "Medication id")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 403, description = "Only an admin can change it", body = ErrorResponse),
+        (status = 404, description = "No such medication", body = ErrorResponse),
+        (status = 409, description = "The medication has recorded intakes", body = ErrorResponse),
+    ),
+)]
+pub async fn delete_medication(
+    State(db): State<AppState>,
+    _admin: AdminUser,
     Path(id): Path<i32>,
-) -> Result<StatusCode, StatusCode> {
-    let mut conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+) -> Result<StatusCode, AppError> {
+    // Medications with recorded intakes are a 409
+    let rows_affected = with_conn(&db, move |conn| database::delete_medication(conn, id)).await?;
+
+    if rows_affected == 0 {
+        Err(AppError::not_found("medication"))
+    } else {
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/medications/{id}/courses",
+    tag = "medications",
+    params(("id" = i32, Path, description = "Medication id")),
+    responses(
+        (status = 200, description = "OK", body = [MedicationCourse]),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 404, description = "No such medication", body = ErrorResponse),
+    ),
+)]
+pub async fn list_courses(
+    State(db): State<AppState>,
+    user: AuthUser,
+    Path(medication_id): Path<i32>,
+) -> Result<Json<Vec<MedicationCourse>>, AppError> {
+    let courses = with_conn(&db, move |conn| database::list_courses(conn, user.id, medication_id)).await?;
+
+    Ok(Json(courses))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/medications/{id}/courses",
+    tag = "medications",
+    params(("id" = i32, Path, description = "Medication id")),
+    request_body = CourseRequest,
+    responses(
+        (status = 200, description = "The new course", body = MedicationCourse),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 404, description = "No such medication", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
+pub async fn create_course(
+    State(db): State<AppState>,
+    user: AuthUser,
+    Path(medication_id): Path<i32>,
+    JsonExtractor(request): JsonExtractor<CourseRequest>,
+) -> Result<Json<MedicationCourse>, AppError> {
+    request.validate()?;
+
+    let course = with_conn(&db, move |conn| database::create_course(conn, user.id, medication_id, &request)).await?;
+
+    Ok(Json(course))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/medications/{id}/courses/{course_id}",
+    tag = "medications",
+    params(("id" = i32, Path, description = "Medication id"), ("course_id" = i32, Path, description = "Course id")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 404, description = "No such course", body = ErrorResponse),
+    ),
+)]
+pub async fn delete_course(
+    State(db): State<AppState>,
+    user: AuthUser,
+    Path((medication_id, course_id)): Path<(i32, i32)>,
+) -> Result<StatusCode, AppError> {
+    let rows_affected = with_conn(&db, move |conn| database::delete_course(conn, user.id, medication_id, course_id)).await?;
+
+    if rows_affected == 0 {
+        Err(AppError::not_found("course"))
+    } else {
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/episodes/{id}/medications",
+    tag = "medications",
+    params(("id" = i32, Path, description = "Episode id")),
+    responses(
+        (status = 200, description = "OK", body = [MedicationIntake]),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 404, description = "No such episode", body = ErrorResponse),
+    ),
+)]
+pub async fn list_intakes(
+    State(db): State<AppState>,
+    user: AuthUser,
+    Path(episode_id): Path<i32>,
+) -> Result<Json<Vec<MedicationIntake>>, AppError> {
+    let intakes = with_conn(&db, move |conn| database::list_intakes(conn, user.id, episode_id)).await?;
 
-    let rows_affected = database::delete_episode(&mut conn, id)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(intakes))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/episodes/{id}/medications",
+    tag = "medications",
+    params(("id" = i32, Path, description = "Episode id")),
+    request_body = IntakeRequest,
+    responses(
+        (status = 200, description = "The recorded intake", body = MedicationIntake),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 404, description = "No such episode or medication", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
+pub async fn create_intake(
+    State(db): State<AppState>,
+    user: AuthUser,
+    Path(episode_id): Path<i32>,
+    JsonExtractor(request): JsonExtractor<IntakeRequest>,
+) -> Result<Json<MedicationIntake>, AppError> {
+    request.validate()?;
+
+    let intake = with_conn(&db, move |conn| database::create_intake(conn, user.id, episode_id, &request)).await?;
+
+    Ok(Json(intake))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/episodes/{id}/medications/{intake_id}",
+    tag = "medications",
+    params(("id" = i32, Path, description = "Episode id"), ("intake_id" = i32, Path, description = "Intake id")),
+    request_body = IntakeUpdate,
+    responses(
+        (status = 200, description = "The updated intake", body = MedicationIntake),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 404, description = "No such intake", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
+pub async fn update_intake(
+    State(db): State<AppState>,
+    user: AuthUser,
+    Path((episode_id, intake_id)): Path<(i32, i32)>,
+    JsonExtractor(update): JsonExtractor<IntakeUpdate>,
+) -> Result<Json<MedicationIntake>, AppError> {
+    update.validate()?;
+
+    let intake = with_conn(&db, move |conn| database::update_intake(conn, user.id, episode_id, intake_id, &update)).await?;
+
+    Ok(Json(intake))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/episodes/{id}/medications/{intake_id}",
+    tag = "medications",
+    params(("id" = i32, Path, description = "Episode id"), ("intake_id" = i32, Path, description = "Intake id")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 404, description = "No such intake", body = ErrorResponse),
+    ),
+)]
+pub async fn delete_intake(
+    State(db): State<AppState>,
+    user: AuthUser,
+    Path((episode_id, intake_id)): Path<(i32, i32)>,
+) -> Result<StatusCode, AppError> {
+    let rows_affected = with_conn(&db, move |conn| database::delete_intake(conn, user.id, episode_id, intake_id)).await?;
 
     if rows_affected == 0 {
-        Err(StatusCode::NOT_FOUND)
+        Err(AppError::not_found("intake"))
     } else {
         Ok(StatusCode::NO_CONTENT)
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/analyze",
+    tag = "analysis",
+    request_body = AnalysisRequest,
+    responses(
+        (status = 200, description = "The AI assessment", body = AnalysisResponse),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+        (status = 502, description = "The AI provider failed", body = ErrorResponse),
+    ),
+)]
 pub async fn analyze_episode(
+    _user: AuthUser,
     JsonExtractor(analysis_request): JsonExtractor<AnalysisRequest>,
-) -> Result<Json<AnalysisResponse>, StatusCode> {
-    let ai_service = AIService::new()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+) -> Result<Json<AnalysisResponse>, AppError> {
+    analysis_request.validate()?;
+
+    let ai_service = AIService::new()?;
 
-    let analysis = ai_service.analyze_episode(&analysis_request)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let analysis = ai_service.analyze_episode(&analysis_request).await?;
 
     Ok(Json(analysis))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/export",
+    tag = "episodes",
+    params(EpisodeFilter, ExportQuery),
+    responses(
+        (status = 200, description = "Episodes as RFC 4180 CSV, newest first, streamed as an attachment", body = String, content_type = "text/csv"),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
 pub async fn export_episodes(
     State(db): State<AppState>,
-) -> Result<String, StatusCode> {
-    let mut conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let episodes = database::get_all_episodes(&mut conn)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let mut csv = String::from("ID,Timestamp,Duration (min),Severity,Symptoms,Triggers,Location,Activities Before,Medications,Notes,AI Analysis\n");
-
-    for episode in episodes {
-        csv.push_str(&format!(
-            "{},{},{},{},{},{},{},{},{},{},{}\n",
-            episode.id,
-            episode.timestamp,
-            episode.duration_minutes.map_or("".to_string(), |d| d.to_string()),
-            episode.severity,
-            episode.symptoms.as_deref().unwrap_or(""),
-            episode.triggers.as_deref().unwrap_or(""),
-            episode.location.as_deref().unwrap_or(""),
-            episode.activities_before.as_deref().unwrap_or(""),
-            episode.medications_taken.as_deref().unwrap_or(""),
-            episode.notes.as_deref().unwrap_or(""),
-            episode.ai_analysis.as_deref().unwrap_or(""),
-        ));
-    }
+    user: AuthUser,
+    Query(filter): Query<EpisodeFilter>,
+    Query(query): Query<ExportQuery>,
+) -> Result<axum::response::Response, AppError> {
+    filter.validate()?;
+    query.validate()?;
+
+    // The first connection is checked out before responding, so a busy pool
+    // is still a clean 503
+    let pool = db.clone();
+    let mut first = Some(tokio::task::spawn_blocking(move || pool.get()).await??);
+
+    let delimiter = query.delimiter();
+    let (sender, receiver) = tokio::sync::mpsc::channel(4);
+
+    let pool = db.clone();
+    tokio::task::spawn_blocking(move || {
+        // Each batch takes a connection and gives it back before the rows are
+        // sent, so a stalled download never holds one
+        let next_batch = |after| {
+            let mut conn = match first.take() {
+                Some(conn) => conn,
+                None => pool.get()?,
+            };
+            Ok(export::load_batch(&mut conn, user.id, &filter, after)?)
+        };
+        let result = export::write_csv(&query, ChannelWriter(sender.clone()), next_batch);
 
-    Ok(csv)
+        // A download the client abandoned is not worth reporting
+        if let (Err(error), false) = (result, sender.is_closed()) {
+            // The status line is long gone; cutting the body short tells the client the file is incomplete
+            eprintln!("❌ export for user {} failed: {}", user.id, error);
+            let _ = sender.blocking_send(Err(std::io::Error::other(error.to_string())));
+        }
+    });
+
+    let filename = format!("vertigo-episodes-{}.{}",
+        chrono::Utc::now().format("%Y-%m-%d"), delimiter.extension());
+
+    Ok(axum::response::Response::builder()
+        .header(header::CONTENT_TYPE, delimiter.content_type())
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename))
+        .body(axum::body::Body::from_stream(ReceiverStream::new(receiver)))
+        .unwrap())
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/export/fhir",
+    tag = "episodes",
+    params(EpisodeFilter),
+    responses(
+        (status = 200, description = "A FHIR R4 collection Bundle of Patient, Observation and MedicationStatement resources", body = Object, content_type = "application/fhir+json"),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
+pub async fn export_fhir(
+    State(db): State<AppState>,
+    user: AuthUser,
+    Query(filter): Query<EpisodeFilter>,
+) -> Result<impl IntoResponse, AppError> {
+    filter.validate()?;
+
+    let bundle = with_conn(&db, move |conn| {
+        let account = database::get_user(conn, user.id)?;
+        let episodes = database::get_filtered_episodes(conn, user.id, &filter)?;
+        let ids: Vec<i32> = episodes.iter().map(|e| e.id).collect();
+        let intakes = database::get_intakes_for_episodes(conn, user.id, &ids)?;
+        let courses = database::get_user_courses(conn, user.id)?;
+        let catalog = database::list_medications(conn)?;
+
+        Ok(fhir::bundle(&FhirExport { user: &account, episodes: &episodes, intakes: &intakes, courses: &courses, catalog: &catalog }, chrono::Utc::now()))
+    }).await?;
+
+    let filename = format!("vertigo-fhir-{}.json", chrono::Utc::now().format("%Y-%m-%d"));
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, fhir::CONTENT_TYPE.to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+        ],
+        Json(bundle),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/import",
+    tag = "episodes",
+    params(ImportQuery),
+    request_body(content = Vec<NewEpisode>, description = "A JSON array of episodes, or CSV sent as text/csv", content_type = "application/json"),
+    responses(
+        (status = 200, description = "What was, or on a dry run would be, imported", body = ImportReport),
+        (status = 400, description = "The body could not be read as CSV or JSON", body = ErrorResponse),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 415, description = "Neither CSV nor JSON", body = ErrorResponse),
+        (status = 422, description = "Rows are invalid, or the mapping does not fit the file; nothing was imported", body = ErrorResponse),
+    ),
+)]
+pub async fn import_episodes(
+    State(db): State<AppState>,
+    user: AuthUser,
+    Query(query): Query<ImportQuery>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> Result<Json<ImportReport>, AppError> {
+    let content_type = headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok());
+    let format = ImportFormat::from_content_type(content_type, &query)?;
+
+    // Parsing and validating thousands of rows is CPU work too, so all of it runs off the executor
+    let pool = db.clone();
+    let report = tokio::task::spawn_blocking(move || {
+        let mut conn = pool.get()?;
+        import::import_episodes(&mut conn, user.id, format, &body, &query)
+    })
+    .await??;
+
+    Ok(Json(report))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/analytics",
+    tag = "analysis",
+    params(EpisodeFilter),
+    responses(
+        (status = 200, description = "OK", body = AnalyticsData),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
 pub async fn get_analytics(
     State(db): State<AppState>,
-) -> Result<Json<AnalyticsData>, StatusCode> {
-    let mut conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    user: AuthUser,
+    Query(filter): Query<EpisodeFilter>,
+) -> Result<Json<AnalyticsData>, AppError> {
+    filter.validate()?;
 
-    let analytics = database::get_analytics_data(&mut conn)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let analytics = with_conn(&db, move |conn| database::get_analytics_data(conn, user.id, &filter)).await?;
 
     Ok(Json(analytics))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/analytics/medications",
+    tag = "analysis",
+    params(EfficacyWindow),
+    responses(
+        (status = 200, description = "Before and after comparison per course", body = [MedicationEfficacy]),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
+pub async fn get_medication_efficacy(
+    State(db): State<AppState>,
+    user: AuthUser,
+    Query(window): Query<EfficacyWindow>,
+) -> Result<Json<Vec<MedicationEfficacy>>, AppError> {
+    window.validate()?;
+
+    let efficacy = with_conn(&db, move |conn| database::get_medication_efficacy(conn, user.id, window)).await?;
+
+    Ok(Json(efficacy))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/patterns",
+    tag = "analysis",
+    params(EpisodeFilter),
+    responses(
+        (status = 200, description = "OK", body = PatternAnalysis),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
 pub async fn get_patterns(
     State(db): State<AppState>,
-) -> Result<Json<PatternAnalysis>, StatusCode> {
-    let mut conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    user: AuthUser,
+    Query(filter): Query<EpisodeFilter>,
+) -> Result<Json<PatternAnalysis>, AppError> {
+    filter.validate()?;
 
-    let episodes = database::get_all_episodes(&mut conn)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (episodes, trigger_frequency) = with_conn(&db, move |conn| {
+        Ok((database::get_filtered_episodes(conn, user.id, &filter)?, database::get_trigger_frequency(conn, user.id, &filter)?))
+    }).await?;
 
-    let ai_service = AIService::new()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let ai_service = AIService::new()?;
 
-    let patterns = ai_service.analyze_patterns(&episodes)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let patterns = ai_service.analyze_patterns(&episodes, &trigger_frequency)?;
 
     Ok(Json(patterns))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/report/pdf",
+    tag = "analysis",
+    params(EpisodeFilter, ReportQuery),
+    responses(
+        (status = 200, description = "The report, laid out by the chosen template", body = Vec<u8>, content_type = "application/pdf"),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
 pub async fn generate_pdf_report(
     State(db): State<AppState>,
-) -> Result<axum::response::Response, StatusCode> {
-    let mut conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    user: AuthUser,
+    Query(filter): Query<EpisodeFilter>,
+    Query(report): Query<ReportQuery>,
+) -> Result<axum::response::Response, AppError> {
+    filter.validate()?;
+    report.validate()?;
+    let sections = report.sections()
+        .map_err(AppError::validation)?;
 
-    let episodes = database::get_all_episodes(&mut conn)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (episodes, analytics) = with_conn(&db, {
+        let filter = filter.clone();
+        move |conn| Ok((database::get_filtered_episodes(conn, user.id, &filter)?, database::get_analytics_data(conn, user.id, &filter)?))
+    }).await?;
 
-    let analytics = database::get_analytics_data(&mut conn)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    pdf_response(report.template(), sections, episodes, analytics, filter).await
+}
 
-    let ai_service = AIService::new()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+async fn pdf_response(
+    template: ReportTemplate,
+    sections: Vec<ReportSection>,
+    episodes: Vec<Episode>,
+    analytics: AnalyticsData,
+    filter: EpisodeFilter,
+) -> Result<axum::response::Response, AppError> {
+    // The connection is back in the pool before the CPU-heavy PDF build starts
+    let pdf_bytes = tokio::task::spawn_blocking(move || {
+        let ai_service = AIService::new()?;
 
-    let patterns = ai_service.analyze_patterns(&episodes)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let patterns = ai_service.analyze_patterns(&episodes, &analytics.trigger_frequency)?;
 
-    let pdf_bytes = PDFReportGenerator::generate_medical_report(&episodes, &analytics, &patterns)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        PDFReportGenerator::generate_report(template, &sections, &episodes, &analytics, &patterns, &filter)
+    })
+    .await??;
 
-    let filename = format!("vertigo-medical-report-{}.pdf",
-        chrono::Utc::now().format("%Y-%m-%d"));
+    let filename = format!("{}-{}.pdf",
+        template.definition().file_stem, chrono::Utc::now().format("%Y-%m-%d"));
 
     Ok(axum::response::Response::builder()
         .header("Content-Type", "application/pdf")
         .header("Content-Disposition", format!("attachment; filename=\"{}\"", filename))
         .body(axum::body::Body::from(pdf_bytes))
         .unwrap())
-}
\ No newline at end of file
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/shares",
+    tag = "shares",
+    request_body = ShareRequest,
+    responses(
+        (status = 200, description = "The link; the token is shown only here", body = CreatedShare),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
+pub async fn create_share(
+    State(db): State<AppState>,
+    user: AuthUser,
+    JsonExtractor(request): JsonExtractor<ShareRequest>,
+) -> Result<Json<CreatedShare>, AppError> {
+    request.validate()?;
+
+    let pin_hash = match request.pin.clone() {
+        Some(pin) => Some(
+            tokio::task::spawn_blocking(move || auth::hash_password(&pin))
+                .await?
+                .map_err(|e| AppError::Internal(format!("PIN hashing failed: {}", e)))?,
+        ),
+        None => None,
+    };
+
+    // Whole seconds, since the expiry is also carried in the signed token
+    let expires_at = chrono::Utc::now().naive_utc() + chrono::Duration::hours(request.expires_in_hours.unwrap_or(72));
+    let expires_at = chrono::Timelike::with_nanosecond(&expires_at, 0).unwrap_or(expires_at);
+
+    let created = with_conn(&db, move |conn| {
+        let link = database::create_share_link(conn, &NewShareLink {
+            user_id: user.id,
+            label: request.label.as_deref().map(str::trim).filter(|l| !l.is_empty()),
+            from_date: request.from,
+            to_date: request.to,
+            pin_hash: pin_hash.as_deref(),
+            expires_at,
+        })?;
+        let token = share::sign_token(&share::signing_key(conn)?, link.id, link.expires_at);
+
+        Ok(CreatedShare { url: format!("/share/{}", token), token, link })
+    }).await?;
+
+    Ok(Json(created))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/shares",
+    tag = "shares",
+    responses(
+        (status = 200, description = "OK", body = [ShareLink]),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+    ),
+)]
+pub async fn list_shares(
+    State(db): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<Vec<ShareLink>>, AppError> {
+    let links = with_conn(&db, move |conn| database::list_share_links(conn, user.id)).await?;
+
+    Ok(Json(links))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/shares/{id}",
+    tag = "shares",
+    params(("id" = i32, Path, description = "Share link id")),
+    responses(
+        (status = 204, description = "Revoked"),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 404, description = "No such share link", body = ErrorResponse),
+    ),
+)]
+pub async fn revoke_share(
+    State(db): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, AppError> {
+    let rows_affected = with_conn(&db, move |conn| database::revoke_share_link(conn, user.id, id)).await?;
+
+    if rows_affected == 0 {
+        Err(AppError::not_found("share link"))
+    } else {
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/shares/{id}/access-log",
+    tag = "shares",
+    params(("id" = i32, Path, description = "Share link id")),
+    responses(
+        (status = 200, description = "Accesses, newest first", body = [ShareAccess]),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 404, description = "No such share link", body = ErrorResponse),
+    ),
+)]
+pub async fn get_share_access_log(
+    State(db): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<ShareAccess>>, AppError> {
+    let log = with_conn(&db, move |conn| database::get_share_access_log(conn, user.id, id)).await?;
+
+    Ok(Json(log))
+}
+
+/// The visitor behind a share request. The PIN comes from the `X-Share-Pin`
+/// header and the grant from the link's cookie; neither is ever in the URL.
+fn visitor(headers: &HeaderMap, addr: SocketAddr, token: &str) -> Visitor {
+    let header_value = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let grant = token.split_once('.')
+        .and_then(|(link_id, _)| link_id.parse().ok())
+        .and_then(|link_id| auth::cookie_value(headers, &share::grant_cookie_name(link_id)));
+
+    Visitor {
+        pin: header_value("X-Share-Pin"),
+        grant,
+        client_ip: Some(addr.ip().to_string()),
+        user_agent: header_value(header::USER_AGENT.as_str()),
+    }
+}
+
+fn pin_form(pin_was_wrong: bool) -> Response {
+    let html = share::render_pin_form(pin_was_wrong);
+    (StatusCode::UNAUTHORIZED, SHARE_HEADERS, [(header::CONTENT_TYPE, "text/html; charset=utf-8")], html).into_response()
+}
+
+/// Resolves a share token for one resource. A wrong or missing PIN is 401;
+/// expired, revoked and locked links are 410.
+async fn open_share(db: &AppState, token: String, resource: ShareResource, visitor: Visitor) -> Result<ShareLink, AppError> {
+    let (link, outcome, _) = check_share(db, token, resource, visitor).await?;
+
+    share_granted(link, outcome)
+}
+
+/// Resolves a share token and logs the outcome, returning the signing key
+/// too. A PIN is hashed between two short connection checkouts, as at login,
+/// so guesses cannot tie up the pool.
+async fn check_share(db: &AppState, token: String, resource: ShareResource, visitor: Visitor) -> Result<(ShareLink, ShareOutcome, Vec<u8>), AppError> {
+    let (link, key) = with_conn(db, move |conn| share::resolve_token(conn, &token)).await?;
+
+    let (link, key, visitor, check) = tokio::task::spawn_blocking(move || {
+        let check = share::check_visitor(&key, &link, &visitor);
+        (link, key, visitor, check)
+    }).await?;
+
+    let logged = link.clone();
+    let outcome = with_conn(db, move |conn| share::record_access(conn, &logged, resource, &visitor, check)).await?;
+
+    Ok((link, outcome, key))
+}
+
+fn share_granted(link: ShareLink, outcome: ShareOutcome) -> Result<ShareLink, AppError> {
+    match outcome {
+        ShareOutcome::Granted => Ok(link),
+        ShareOutcome::WrongPin => Err(AppError::Unauthorized("a valid PIN is required for this link".to_string())),
+        ShareOutcome::Locked => Err(AppError::Gone("this link was locked after too many wrong PINs".to_string())),
+        ShareOutcome::Expired => Err(AppError::Gone("this link has expired".to_string())),
+        ShareOutcome::Revoked => Err(AppError::Gone("this link has been revoked".to_string())),
+    }
+}
+
+/// Shared responses carry patient data under a bearer URL, so keep them out
+/// of caches, search indexes and Referer headers.
+const SHARE_HEADERS: [(header::HeaderName, &str); 3] = [
+    (header::CACHE_CONTROL, "no-store"),
+    (header::REFERRER_POLICY, "no-referrer"),
+    (header::HeaderName::from_static("x-robots-tag"), "noindex"),
+];
+
+#[utoipa::path(
+    get,
+    path = "/share/{token}",
+    tag = "shared",
+    params(("token" = String, Path, description = "Signed share token")),
+    responses(
+        (status = 200, description = "Read-only episode page", body = String, content_type = "text/html"),
+        (status = 401, description = "PIN entry page; a PIN is required or the PIN was wrong", body = String, content_type = "text/html"),
+        (status = 410, description = "The link expired, was revoked or is locked", body = ErrorResponse),
+    ),
+    security(()),
+)]
+pub async fn share_view(
+    State(db): State<AppState>,
+    Path(token): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let visitor = visitor(&headers, addr, &token);
+    let pin_given = visitor.pin.is_some();
+    let (link, outcome, _) = check_share(&db, token.clone(), ShareResource::View, visitor).await?;
+
+    // People open the link in a browser, so ask for the PIN there
+    if outcome == ShareOutcome::WrongPin {
+        return Ok(pin_form(pin_given));
+    }
+    let link = share_granted(link, outcome)?;
+
+    let owner = link.user_id;
+    let filter = link.filter();
+    let episodes = with_conn(&db, move |conn| database::get_filtered_episodes(conn, owner, &filter)).await?;
+
+    // The grant cookie lets the browser follow these without the PIN
+    let html = share::render_episode_view(
+        &link,
+        &episodes,
+        &format!("/api/share/{}/analytics", token),
+        &format!("/api/share/{}/report/pdf", token),
+    );
+
+    Ok((SHARE_HEADERS, [(header::CONTENT_TYPE, "text/html; charset=utf-8")], html).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/share/{token}",
+    tag = "shared",
+    params(("token" = String, Path, description = "Signed share token")),
+    request_body(content = ShareUnlock, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 303, description = "PIN accepted; a short-lived grant cookie is set and the page reloads"),
+        (status = 401, description = "PIN entry page; the PIN was wrong", body = String, content_type = "text/html"),
+        (status = 410, description = "The link expired, was revoked or is locked", body = ErrorResponse),
+    ),
+    security(()),
+)]
+pub async fn share_unlock(
+    State(db): State<AppState>,
+    Path(token): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Form(form): Form<ShareUnlock>,
+) -> Result<Response, AppError> {
+    let mut visitor = visitor(&headers, addr, &token);
+    visitor.pin = Some(form.pin).filter(|pin| !pin.is_empty());
+    let pin_given = visitor.pin.is_some();
+    let (link, outcome, key) = check_share(&db, token.clone(), ShareResource::View, visitor).await?;
+
+    if outcome == ShareOutcome::WrongPin {
+        return Ok(pin_form(pin_given));
+    }
+    let link = share_granted(link, outcome)?;
+    let cookie = share::grant_cookie(&key, &link, chrono::Utc::now().naive_utc());
+
+    Ok((
+        StatusCode::SEE_OTHER,
+        SHARE_HEADERS,
+        [(header::SET_COOKIE, cookie), (header::LOCATION, format!("/share/{}", token))],
+    ).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/share/{token}/analytics",
+    tag = "shared",
+    params(("token" = String, Path, description = "Signed share token")),
+    responses(
+        (status = 200, description = "OK", body = AnalyticsData),
+        (status = 401, description = "A PIN is required or the PIN was wrong", body = ErrorResponse),
+        (status = 410, description = "The link expired, was revoked or is locked", body = ErrorResponse),
+    ),
+    security(()),
+)]
+pub async fn share_analytics(
+    State(db): State<AppState>,
+    Path(token): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let visitor = visitor(&headers, addr, &token);
+    let link = open_share(&db, token, ShareResource::Analytics, visitor).await?;
+
+    let filter = link.filter();
+    let analytics = with_conn(&db, move |conn| database::get_analytics_data(conn, link.user_id, &filter)).await?;
+
+    Ok((SHARE_HEADERS, Json(analytics)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/share/{token}/report/pdf",
+    tag = "shared",
+    params(("token" = String, Path, description = "Signed share token")),
+    responses(
+        (status = 200, description = "The medical report", body = Vec<u8>, content_type = "application/pdf"),
+        (status = 401, description = "A PIN is required or the PIN was wrong", body = ErrorResponse),
+        (status = 410, description = "The link expired, was revoked or is locked", body = ErrorResponse),
+    ),
+    security(()),
+)]
+pub async fn share_report(
+    State(db): State<AppState>,
+    Path(token): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let visitor = visitor(&headers, addr, &token);
+    let link = open_share(&db, token, ShareResource::Report, visitor).await?;
+
+    let filter = link.filter();
+    let (episodes, analytics) = with_conn(&db, {
+        let filter = filter.clone();
+        move |conn| Ok((database::get_filtered_episodes(conn, link.user_id, &filter)?, database::get_analytics_data(conn, link.user_id, &filter)?))
+    }).await?;
+
+    let template = ReportTemplate::Medical;
+    Ok((SHARE_HEADERS, pdf_response(template, template.default_sections(), episodes, analytics, filter).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/export/ics",
+    tag = "calendar",
+    params(EpisodeFilter),
+    responses(
+        (status = 200, description = "An iCalendar file with one event per episode", body = String, content_type = "text/calendar"),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
+pub async fn export_ics(
+    State(db): State<AppState>,
+    user: AuthUser,
+    Query(filter): Query<EpisodeFilter>,
+) -> Result<impl IntoResponse, AppError> {
+    filter.validate()?;
+
+    let episodes = with_conn(&db, move |conn| database::get_calendar_episodes(conn, user.id, &filter)).await?;
+    let ics = ical::calendar("Vertigo episodes", &episodes, chrono::Utc::now());
+    let filename = format!("vertigo-episodes-{}.ics", chrono::Utc::now().format("%Y-%m-%d"));
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, ical::CONTENT_TYPE.to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+        ],
+        ics,
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/calendar-feeds",
+    tag = "calendar",
+    request_body = CalendarFeedRequest,
+    responses(
+        (status = 200, description = "The feed; the token is shown only here", body = CreatedCalendarFeed),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+)]
+pub async fn create_calendar_feed(
+    State(db): State<AppState>,
+    user: AuthUser,
+    JsonExtractor(request): JsonExtractor<CalendarFeedRequest>,
+) -> Result<Json<CreatedCalendarFeed>, AppError> {
+    request.validate()?;
+
+    let token = auth::new_session_token();
+    let token_hash = auth::token_hash(&token);
+
+    let feed = with_conn(&db, move |conn| {
+        database::create_calendar_feed(conn, &NewCalendarFeed {
+            user_id: user.id,
+            label: request.label.as_deref().map(str::trim).filter(|l| !l.is_empty()),
+            token_hash: &token_hash,
+        })
+    }).await?;
+
+    Ok(Json(CreatedCalendarFeed { url: format!("/api/calendar/{}/episodes.ics", token), token, feed }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/calendar-feeds",
+    tag = "calendar",
+    responses(
+        (status = 200, description = "OK", body = [CalendarFeed]),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+    ),
+)]
+pub async fn list_calendar_feeds(
+    State(db): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<Vec<CalendarFeed>>, AppError> {
+    let feeds = with_conn(&db, move |conn| database::list_calendar_feeds(conn, user.id)).await?;
+
+    Ok(Json(feeds))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/calendar-feeds/{id}",
+    tag = "calendar",
+    params(("id" = i32, Path, description = "Calendar feed id")),
+    responses(
+        (status = 204, description = "Deleted; the feed URL stops working"),
+        (status = 401, description = "Not signed in", body = ErrorResponse),
+        (status = 404, description = "No such calendar feed", body = ErrorResponse),
+    ),
+)]
+pub async fn delete_calendar_feed(
+    State(db): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, AppError> {
+    let rows_affected = with_conn(&db, move |conn| database::delete_calendar_feed(conn, user.id, id)).await?;
+
+    if rows_affected == 0 {
+        Err(AppError::not_found("calendar feed"))
+    } else {
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/calendar/{token}/episodes.ics",
+    tag = "calendar",
+    params(("token" = String, Path, description = "Calendar feed token"), EpisodeFilter),
+    responses(
+        (status = 200, description = "The feed as iCalendar", body = String, content_type = "text/calendar"),
+        (status = 404, description = "No such calendar feed, or it was deleted", body = ErrorResponse),
+        (status = 422, description = "The request values are not acceptable", body = ErrorResponse),
+    ),
+    security(()),
+)]
+pub async fn calendar_feed(
+    State(db): State<AppState>,
+    Path(token): Path<String>,
+    Query(filter): Query<EpisodeFilter>,
+) -> Result<impl IntoResponse, AppError> {
+    filter.validate()?;
+
+    let token_hash = auth::token_hash(&token);
+    let (feed, episodes) = with_conn(&db, move |conn| {
+        let feed = database::fetch_calendar_feed(conn, &token_hash)?;
+        let episodes = database::get_calendar_episodes(conn, feed.user_id, &filter)?;
+        Ok((feed, episodes))
+    }).await?;
+
+    let ics = ical::calendar(feed.label.as_deref().unwrap_or("Vertigo episodes"), &episodes, chrono::Utc::now());
+
+    Ok((SHARE_HEADERS, [(header::CONTENT_TYPE, ical::CONTENT_TYPE)], ics))
+}
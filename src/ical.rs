@@ -0,0 +1,118 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+use crate::models::Episode;
+
+pub const CONTENT_TYPE: &str = "text/calendar; charset=utf-8";
+
+/// How often subscribed calendar apps are asked to refetch a feed.
+const REFRESH_INTERVAL: &str = "PT1H";
+
+/// Maximum octets per content line before folding (RFC 5545 section 3.1).
+const LINE_LIMIT: usize = 75;
+
+/// An RFC 5545 VCALENDAR with one VEVENT per episode, spanning its duration.
+/// Events are marked transparent so they never show the user as busy.
+pub fn calendar(name: &str, episodes: &[Episode], generated_at: DateTime<Utc>) -> String {
+    let mut ics = String::new();
+    let stamp = generated_at.format("%Y%m%dT%H%M%SZ").to_string();
+
+    line(&mut ics, "BEGIN:VCALENDAR");
+    line(&mut ics, "VERSION:2.0");
+    line(&mut ics, "PRODID:-//Vertigo Logger//Episodes//EN");
+    line(&mut ics, "CALSCALE:GREGORIAN");
+    line(&mut ics, "METHOD:PUBLISH");
+    line(&mut ics, &format!("X-WR-CALNAME:{}", escape(name)));
+    line(&mut ics, &format!("REFRESH-INTERVAL;VALUE=DURATION:{}", REFRESH_INTERVAL));
+    line(&mut ics, &format!("X-PUBLISHED-TTL:{}", REFRESH_INTERVAL));
+
+    for episode in episodes {
+        line(&mut ics, "BEGIN:VEVENT");
+        line(&mut ics, &format!("UID:episode-{}@vertigo-logger", episode.id));
+        line(&mut ics, &format!("DTSTAMP:{}", stamp));
+        line(&mut ics, &format!("DTSTART:{}", date_time(episode.timestamp)));
+        // Without a duration the event is a point in time, as RFC 5545 defines for a missing DTEND
+        if let Some(minutes) = episode.duration_minutes {
+            let end = episode.timestamp + chrono::Duration::minutes(minutes.into());
+            line(&mut ics, &format!("DTEND:{}", date_time(end)));
+        }
+        line(&mut ics, &format!("SUMMARY:{}", escape(&summary(episode))));
+        line(&mut ics, &format!("DESCRIPTION:{}", escape(&description(episode))));
+        if let Some(location) = present(&episode.location) {
+            line(&mut ics, &format!("LOCATION:{}", escape(location)));
+        }
+        line(&mut ics, "CATEGORIES:Vertigo");
+        line(&mut ics, "TRANSP:TRANSPARENT");
+        line(&mut ics, "END:VEVENT");
+    }
+
+    line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+/// Timestamps are stored as naive UTC.
+fn date_time(timestamp: NaiveDateTime) -> String {
+    timestamp.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn present(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+/// e.g. "Vertigo (severity 4/5): Stress, Bright lights"
+fn summary(episode: &Episode) -> String {
+    match present(&episode.triggers) {
+        Some(triggers) => format!("Vertigo (severity {}/5): {}", episode.severity, triggers),
+        None => format!("Vertigo (severity {}/5)", episode.severity),
+    }
+}
+
+fn description(episode: &Episode) -> String {
+    let mut lines = vec![format!("Severity: {}/5", episode.severity)];
+    if let Some(minutes) = episode.duration_minutes {
+        lines.push(format!("Duration: {} min", minutes));
+    }
+    for (label, value) in [
+        ("Triggers", &episode.triggers),
+        ("Symptoms", &episode.symptoms),
+        ("Activities before", &episode.activities_before),
+        ("Medications", &episode.medications_taken),
+        ("Notes", &episode.notes),
+    ] {
+        if let Some(value) = present(value) {
+            lines.push(format!("{}: {}", label, value));
+        }
+    }
+    lines.join("\n")
+}
+
+/// TEXT value escaping: backslash, semicolon, comma and line breaks.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Appends a content line, folded so no physical line exceeds 75 octets and
+/// no UTF-8 character is split across a fold.
+fn line(ics: &mut String, content: &str) {
+    let mut width = 0;
+    for c in content.chars() {
+        if width + c.len_utf8() > LINE_LIMIT {
+            ics.push_str("\r\n ");
+            // The leading space counts towards the continuation line
+            width = 1;
+        }
+        ics.push(c);
+        width += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
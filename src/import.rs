@@ -0,0 +1,340 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Timelike};
+use diesel::sqlite::SqliteConnection;
+use std::collections::{HashMap, HashSet};
+use validator::Validate;
+
+use crate::database;
+use crate::error::{AppError, FieldError};
+use crate::models::{ExportColumn, ExportDelimiter, ImportProblem, ImportQuery, ImportReport, NewEpisode};
+
+/// Most rows one import accepts; larger histories can be split across files.
+pub const MAX_IMPORT_ROWS: usize = 5_000;
+
+/// What the upload is, going by its Content-Type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Json,
+    Csv(ExportDelimiter),
+}
+
+impl ImportFormat {
+    pub fn from_content_type(content_type: Option<&str>, query: &ImportQuery) -> Result<Self, AppError> {
+        let mime = content_type.unwrap_or("").split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+
+        match mime.as_str() {
+            "application/json" => Ok(ImportFormat::Json),
+            "text/csv" => Ok(ImportFormat::Csv(query.delimiter.unwrap_or(ExportDelimiter::Comma))),
+            "text/tab-separated-values" => Ok(ImportFormat::Csv(query.delimiter.unwrap_or(ExportDelimiter::Tab))),
+            _ => Err(AppError::UnsupportedMediaType(
+                "send CSV as text/csv, or a JSON array of episodes as application/json".to_string(),
+            )),
+        }
+    }
+}
+
+/// One data row as read, before validation. `episode` is `None` when the row
+/// could not be read at all.
+struct ParsedRow {
+    episode: Option<NewEpisode>,
+    errors: Vec<FieldError>,
+}
+
+struct ParsedImport {
+    rows: Vec<ParsedRow>,
+    ignored_columns: Vec<String>,
+}
+
+fn field_error(field: &str, message: impl Into<String>) -> FieldError {
+    FieldError { field: field.to_string(), message: message.into() }
+}
+
+/// Checks every row of an upload and, unless it is a dry run, stores the valid
+/// ones in a single transaction. Any invalid row aborts the whole commit with
+/// a 422 naming each problem as `rows[N].field`; probable duplicates of
+/// existing episodes, or of earlier rows, are skipped unless allowed.
+pub fn import_episodes(conn: &mut SqliteConnection, user_id: i32, format: ImportFormat, body: &[u8], query: &ImportQuery) -> Result<ImportReport, AppError> {
+    query.validate()?;
+    let parsed = match format {
+        ImportFormat::Json => parse_json(body)?,
+        ImportFormat::Csv(delimiter) => parse_csv(body, delimiter, query)?,
+    };
+    let total_rows = parsed.rows.len();
+
+    let mut problems = Vec::new();
+    let mut accepted: Vec<(usize, NewEpisode)> = Vec::new();
+
+    for (index, row) in parsed.rows.into_iter().enumerate() {
+        let number = index + 1;
+        let mut errors = row.errors;
+
+        if let Some(episode) = &row.episode {
+            if episode.timestamp.is_none() && !errors.iter().any(|e| e.field == "timestamp") {
+                errors.push(field_error("timestamp", "is required"));
+            }
+            if let Err(invalid) = episode.validate() {
+                // A value that did not parse also fails its range check; report it once
+                let extra: Vec<FieldError> = AppError::from(invalid)
+                    .fields()
+                    .iter()
+                    .filter(|f| !errors.iter().any(|e| e.field == f.field))
+                    .cloned()
+                    .collect();
+                errors.extend(extra);
+            }
+        }
+
+        match row.episode {
+            Some(episode) if errors.is_empty() => accepted.push((number, episode)),
+            _ => problems.push(ImportProblem { row: number, errors, duplicate_of: None, duplicate_of_row: None }),
+        }
+    }
+    let invalid_rows = problems.len();
+
+    let duplicates = find_duplicates(conn, user_id, &accepted)?;
+    let duplicate_rows = duplicates.len();
+    let duplicate_numbers: HashSet<usize> = duplicates.iter().map(|p| p.row).collect();
+    problems.extend(duplicates);
+    problems.sort_by_key(|p| p.row);
+
+    let mut report = ImportReport {
+        dry_run: query.dry_run,
+        total_rows,
+        valid_rows: accepted.len(),
+        invalid_rows,
+        duplicate_rows,
+        imported: 0,
+        ignored_columns: parsed.ignored_columns,
+        problems,
+    };
+
+    if query.dry_run {
+        return Ok(report);
+    }
+
+    if invalid_rows > 0 {
+        let fields = report.problems
+            .iter()
+            .flat_map(|p| p.errors.iter().map(move |e| field_error(&format!("rows[{}].{}", p.row, e.field), e.message.clone())))
+            .collect();
+        return Err(AppError::Validation {
+            message: format!("{} of {} rows are invalid; nothing was imported", invalid_rows, total_rows),
+            fields,
+        });
+    }
+
+    let mut to_store: Vec<NewEpisode> = accepted
+        .into_iter()
+        .rev()
+        .filter(|(number, _)| query.allow_duplicates || !duplicate_numbers.contains(number))
+        .map(|(_, episode)| episode)
+        .collect();
+    // Oldest first, so ids follow the timeline. Walking the file backwards
+    // first means a newest-first export, ties broken by id, keeps its order.
+    to_store.sort_by_key(|episode| episode.timestamp);
+
+    report.imported = database::import_episodes(conn, user_id, &to_store)?;
+    Ok(report)
+}
+
+fn minute(timestamp: NaiveDateTime) -> NaiveDateTime {
+    timestamp.with_second(0).and_then(|t| t.with_nanosecond(0)).unwrap_or(timestamp)
+}
+
+/// Rows logged at the same minute with the same severity as an existing
+/// episode or an earlier row. Re-importing our own export hits every row.
+fn find_duplicates(conn: &mut SqliteConnection, user_id: i32, accepted: &[(usize, NewEpisode)]) -> Result<Vec<ImportProblem>, AppError> {
+    let keys: Vec<(usize, (NaiveDateTime, i32))> = accepted
+        .iter()
+        .filter_map(|(number, episode)| episode.timestamp.map(|t| (*number, (minute(t), episode.severity))))
+        .collect();
+
+    let (Some(first), Some(last)) = (keys.iter().map(|(_, (t, _))| *t).min(), keys.iter().map(|(_, (t, _))| *t).max()) else {
+        return Ok(Vec::new());
+    };
+    let end = last + chrono::Duration::minutes(1) - chrono::Duration::nanoseconds(1);
+
+    let mut existing: HashMap<(NaiveDateTime, i32), i32> = HashMap::new();
+    for (id, timestamp, severity) in database::get_episode_keys(conn, user_id, first, end)? {
+        existing.entry((minute(timestamp), severity)).or_insert(id);
+    }
+
+    let mut seen: HashMap<(NaiveDateTime, i32), usize> = HashMap::new();
+    let mut duplicates = Vec::new();
+    for (number, key) in keys {
+        let duplicate_of = existing.get(&key).copied();
+        let duplicate_of_row = match duplicate_of {
+            Some(_) => None,
+            None => seen.get(&key).copied(),
+        };
+
+        if duplicate_of.is_some() || duplicate_of_row.is_some() {
+            duplicates.push(ImportProblem { row: number, errors: Vec::new(), duplicate_of, duplicate_of_row });
+        } else {
+            seen.insert(key, number);
+        }
+    }
+    Ok(duplicates)
+}
+
+fn check_row_count(rows: usize) -> Result<(), AppError> {
+    if rows > MAX_IMPORT_ROWS {
+        return Err(AppError::validation(format!("an import may hold at most {} rows; split the file", MAX_IMPORT_ROWS)));
+    }
+    Ok(())
+}
+
+fn parse_json(body: &[u8]) -> Result<ParsedImport, AppError> {
+    let values: Vec<serde_json::Value> = serde_json::from_slice(body)
+        .map_err(|e| AppError::BadRequest(format!("body must be a JSON array of episodes: {}", e)))?;
+    check_row_count(values.len())?;
+
+    let rows = values
+        .into_iter()
+        .map(|value| match serde_json::from_value::<NewEpisode>(value) {
+            Ok(episode) => ParsedRow { episode: Some(episode), errors: Vec::new() },
+            Err(e) => ParsedRow { episode: None, errors: vec![field_error("episode", e.to_string())] },
+        })
+        .collect();
+
+    Ok(ParsedImport { rows, ignored_columns: Vec::new() })
+}
+
+fn parse_csv(body: &[u8], delimiter: ExportDelimiter, query: &ImportQuery) -> Result<ParsedImport, AppError> {
+    let mapping = query.mapping().map_err(AppError::validation)?;
+    // Spreadsheets often save UTF-8 with a byte order mark
+    let body = body.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(body);
+
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter.byte())
+        .flexible(true)
+        .from_reader(body);
+
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|e| AppError::BadRequest(format!("could not read the CSV header: {}", e)))?
+        .iter()
+        .map(|h| h.trim().to_string())
+        .collect();
+    let columns = map_headers(&headers, &mapping)?;
+
+    let ignored_columns = headers
+        .iter()
+        .zip(&columns)
+        .filter(|(_, column)| column.is_none())
+        .map(|(header, _)| header.clone())
+        .collect();
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        check_row_count(rows.len() + 1)?;
+        rows.push(match record {
+            Ok(record) if record.len() != headers.len() => ParsedRow {
+                episode: None,
+                errors: vec![field_error("episode", format!("has {} fields but the header has {}", record.len(), headers.len()))],
+            },
+            Ok(record) => episode_from_record(&record, &columns),
+            Err(e) => ParsedRow { episode: None, errors: vec![field_error("episode", e.to_string())] },
+        });
+    }
+
+    Ok(ParsedImport { rows, ignored_columns })
+}
+
+/// Which column each header fills. Explicit mappings win; other headers match
+/// our export's headers or column keys, ignoring case.
+fn map_headers(headers: &[String], mapping: &[(ExportColumn, String)]) -> Result<Vec<Option<ExportColumn>>, AppError> {
+    if let Some((_, missing)) = mapping.iter().find(|(_, header)| !headers.contains(header)) {
+        return Err(AppError::validation(format!("mapping names a column the file does not have: {}", missing)));
+    }
+
+    let columns: Vec<Option<ExportColumn>> = headers
+        .iter()
+        .map(|header| match mapping.iter().find(|(_, h)| h == header) {
+            Some((column, _)) => Some(*column),
+            None => ExportColumn::IMPORTABLE.into_iter().find(|column| {
+                !mapping.iter().any(|(mapped, _)| mapped == column)
+                    && (header.eq_ignore_ascii_case(column.header()) || header.eq_ignore_ascii_case(column.as_str()))
+            }),
+        })
+        .collect();
+
+    for column in ExportColumn::IMPORTABLE {
+        if columns.iter().filter(|c| **c == Some(column)).count() > 1 {
+            return Err(AppError::validation(format!("more than one column maps onto {}", column.as_str())));
+        }
+    }
+    for required in [ExportColumn::Timestamp, ExportColumn::Severity] {
+        if !columns.contains(&Some(required)) {
+            return Err(AppError::validation(format!(
+                "the file has no {} column; name one with mapping={}:Header",
+                required.as_str(),
+                required.as_str()
+            )));
+        }
+    }
+    Ok(columns)
+}
+
+fn episode_from_record(record: &csv::StringRecord, columns: &[Option<ExportColumn>]) -> ParsedRow {
+    let mut episode = NewEpisode {
+        timestamp: None,
+        duration_minutes: None,
+        severity: 0,
+        triggers: None,
+        symptoms: None,
+        location: None,
+        activities_before: None,
+        medications_taken: None,
+        notes: None,
+        symptom_checklist: None,
+    };
+    let mut errors = Vec::new();
+
+    for (value, column) in record.iter().zip(columns) {
+        let (Some(column), value) = (column, value.trim()) else {
+            continue;
+        };
+        let text = (!value.is_empty()).then(|| value.to_string());
+
+        match column {
+            ExportColumn::Timestamp => match parse_timestamp(value) {
+                Some(timestamp) => episode.timestamp = Some(timestamp),
+                None if value.is_empty() => {}
+                None => errors.push(field_error("timestamp", "is not a date and time like 2025-03-01 14:30")),
+            },
+            ExportColumn::DurationMinutes if value.is_empty() => {}
+            ExportColumn::DurationMinutes => match value.parse() {
+                Ok(minutes) => episode.duration_minutes = Some(minutes),
+                Err(_) => errors.push(field_error("duration_minutes", "must be a whole number of minutes")),
+            },
+            ExportColumn::Severity if value.is_empty() => errors.push(field_error("severity", "is required")),
+            ExportColumn::Severity => match value.parse() {
+                Ok(severity) => episode.severity = severity,
+                Err(_) => errors.push(field_error("severity", "must be a whole number from 1 to 5")),
+            },
+            ExportColumn::Symptoms => episode.symptoms = text,
+            ExportColumn::Triggers => episode.triggers = text,
+            ExportColumn::Location => episode.location = text,
+            ExportColumn::ActivitiesBefore => episode.activities_before = text,
+            ExportColumn::Medications => episode.medications_taken = text,
+            ExportColumn::Notes => episode.notes = text,
+            // Never mapped; see ExportColumn::IMPORTABLE
+            ExportColumn::Id | ExportColumn::MedicationIntakes | ExportColumn::AiAnalysis => {}
+        }
+    }
+
+    ParsedRow { episode: Some(episode), errors }
+}
+
+/// ISO dates and times as spreadsheets write them, including our own export's
+/// `2025-03-01 14:30:00.123`. Times with an offset are converted to UTC;
+/// a bare date means midnight.
+fn parse_timestamp(value: &str) -> Option<NaiveDateTime> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Some(timestamp.naive_utc());
+    }
+    ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok().map(|date| date.and_time(chrono::NaiveTime::MIN)))
+}
@@ -1,50 +1,80 @@
 use diesel::prelude::*;
 use diesel::sqlite::SqliteConnection;
-use std::env;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use std::fs;
 use std::path::Path;
 
-pub fn ensure_database_setup() -> Result<SqliteConnection, Box<dyn std::error::Error>> {
-    let database_url = env::var("DATABASE_URL")
-        .unwrap_or_else(|_| "vertigo.db".to_string());
+use crate::database::{self, DbPool, PoolSettings};
+
+/// Every directory under `migrations/`, compiled into the binary.
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+type MigrationError = Box<dyn std::error::Error + Send + Sync>;
+
+pub fn ensure_database_setup() -> Result<DbPool, Box<dyn std::error::Error>> {
+    let pool = open_pool()?;
+    let mut conn = pool.get()?;
+
+    // Seed demo data only for brand new databases, never for existing user logs
+    let is_new_database = !table_exists(&mut conn, "episodes")?;
+
+    let applied = run_migrations(&mut conn).map_err(|e| e.to_string())?;
+    for version in &applied {
+        println!("📊 Applied migration {}", version);
+    }
+
+    if is_new_database {
+        insert_demo_data(&mut conn)?;
+    }
+
+    Ok(pool)
+}
+
+pub fn open_pool() -> Result<DbPool, Box<dyn std::error::Error>> {
+    let settings = PoolSettings::from_env();
 
     // Create directory if it doesn't exist
-    if let Some(parent) = Path::new(&database_url).parent() {
+    if let Some(parent) = Path::new(&settings.database_url).parent() {
         fs::create_dir_all(parent)?;
     }
 
-    let mut conn = SqliteConnection::establish(&database_url)?;
-
-    // Run initial migration if needed
-    let migration_sql = include_str!("../migrations/001_create_episodes.sql");
+    Ok(database::create_pool(&settings)?)
+}
 
-    // Check if table exists
-    let table_exists = diesel::sql_query("SELECT name FROM sqlite_master WHERE type='table' AND name='episodes'")
-        .execute(&mut conn)
-        .map(|rows| rows > 0)
-        .unwrap_or(false);
+/// Applies all pending migrations and returns the versions that ran.
+pub fn run_migrations(conn: &mut SqliteConnection) -> Result<Vec<String>, MigrationError> {
+    let applied = conn.run_pending_migrations(MIGRATIONS)?;
+    Ok(applied.into_iter().map(|v| v.to_string()).collect())
+}
 
-    if !table_exists {
-        println!("📊 Creating database schema...");
-        diesel::sql_query(migration_sql).execute(&mut conn)?;
-        println!("✅ Database schema created successfully");
+/// Lists migrations embedded in this binary that the database has not applied yet.
+pub fn pending_migrations(conn: &mut SqliteConnection) -> Result<Vec<String>, MigrationError> {
+    let pending = conn.pending_migrations(MIGRATIONS)?;
+    Ok(pending.iter().map(|m| m.name().to_string()).collect())
+}
 
-        // Insert some demo data for production demo
-        insert_demo_data(&mut conn)?;
+fn table_exists(conn: &mut SqliteConnection, table: &str) -> QueryResult<bool> {
+    #[derive(QueryableByName)]
+    struct Count {
+        #[diesel(sql_type = diesel::sql_types::BigInt)]
+        count: i64,
     }
 
-    Ok(conn)
+    diesel::sql_query("SELECT COUNT(*) AS count FROM sqlite_master WHERE type = 'table' AND name = ?")
+        .bind::<diesel::sql_types::Text, _>(table)
+        .get_result::<Count>(conn)
+        .map(|row| row.count > 0)
 }
 
 fn insert_demo_data(conn: &mut SqliteConnection) -> Result<(), Box<dyn std::error::Error>> {
     println!("📋 Inserting demo episodes for production showcase...");
 
     let demo_episodes = vec![
-        (3, Some(45), "Spinning sensation, mild nausea", Some("Standing up quickly"), Some("Home"), Some("Reading for 2 hours"), None::<String>, Some("Moderate episode likely triggered by positional changes. Consider gradual movements."), "2025-09-17 14:30:00"),
+        (3, Some(45), "Spinning sensation, mild nausea", Some("Standing up quickly"), Some("Home"), Some("Reading for 2 hours"), None::<&str>, Some("Moderate episode likely triggered by positional changes. Consider gradual movements."), "2025-09-17 14:30:00"),
         (2, Some(20), "Light dizziness, balance issues", Some("Stress, lack of sleep"), Some("Office"), Some("Working on computer"), Some("Ibuprofen"), Some("Mild episode associated with stress. Consider stress management techniques."), "2025-09-19 10:15:00"),
         (4, Some(90), "Severe spinning, vomiting", Some("Unknown"), Some("Home"), Some("Sleeping"), Some("Dramamine"), Some("Severe episode with concerning duration. Recommend medical consultation."), "2025-09-21 07:45:00"),
-        (1, Some(15), "Brief dizziness", Some("Dehydration"), Some("Gym"), Some("Exercise"), None::<String>, Some("Mild episode likely due to dehydration. Ensure adequate hydration before exercise."), "2025-09-22 16:20:00"),
-        (3, Some(60), "Moderate spinning, headache", Some("Weather change"), Some("Car"), Some("Driving"), None::<String>, Some("Weather-related episode. Monitor atmospheric pressure changes."), "2025-09-23 09:10:00"),
+        (1, Some(15), "Brief dizziness", Some("Dehydration"), Some("Gym"), Some("Exercise"), None::<&str>, Some("Mild episode likely due to dehydration. Ensure adequate hydration before exercise."), "2025-09-22 16:20:00"),
+        (3, Some(60), "Moderate spinning, headache", Some("Weather change"), Some("Car"), Some("Driving"), None::<&str>, Some("Weather-related episode. Monitor atmospheric pressure changes."), "2025-09-23 09:10:00"),
     ];
 
     for (severity, duration, symptoms, triggers, location, activities, medications, ai_analysis, timestamp) in demo_episodes {
@@ -62,6 +92,12 @@ fn insert_demo_data(conn: &mut SqliteConnection) -> Result<(), Box<dyn std::erro
         .bind::<diesel::sql_types::Nullable<diesel::sql_types::Text>, _>(ai_analysis)
         .bind::<diesel::sql_types::Text, _>(timestamp)
         .execute(conn)?;
+
+        if let Some(triggers) = triggers {
+            let episode_id = diesel::select(diesel::dsl::sql::<diesel::sql_types::Integer>("last_insert_rowid()"))
+                .get_result::<i32>(conn)?;
+            database::set_episode_triggers(conn, episode_id, triggers)?;
+        }
     }
 
     println!("✅ Demo episodes inserted successfully");
@@ -0,0 +1,23 @@
+pub mod models;
+pub mod schema;
+pub mod database;
+pub mod handlers;
+pub mod ai_service;
+pub mod pdf_generator;
+pub mod pdf_charts;
+pub mod pdf_fonts;
+pub mod pdf_layout;
+pub mod pdf_text;
+pub mod init;
+pub mod trigger_vocabulary;
+pub mod efficacy;
+pub mod auth;
+pub mod share;
+pub mod html;
+pub mod error;
+pub mod extract;
+pub mod openapi;
+pub mod export;
+pub mod import;
+pub mod fhir;
+pub mod ical;
@@ -1,53 +1,106 @@
-mod models;
-mod schema;
-mod database;
-mod handlers;
-mod ai_service;
-mod pdf_generator;
-mod init;
-
 use axum::{
-    http::Method,
+    http::{header, HeaderName, Method},
+    middleware,
     routing::{get, post, put, delete},
     Router,
 };
-use std::sync::{Arc, Mutex};
 use tower::ServiceBuilder;
 use tower_http::{
     cors::{Any, CorsLayer},
     services::ServeDir,
 };
 
-use handlers::AppState;
+use std::net::SocketAddr;
+use vertigo_logger::error;
+use vertigo_logger::handlers::{self, AppState};
+use vertigo_logger::init;
+use vertigo_logger::openapi;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.iter().any(|a| a == "--check-migrations") {
+        return check_migrations();
+    }
+
     println!("🚀 Starting Enhanced Vertigo Logger Stage 2...");
 
-    let conn = init::ensure_database_setup()
+    let app_state: AppState = init::ensure_database_setup()
         .expect("Failed to initialize database");
 
-    println!("✅ Database connected successfully");
+    println!("✅ Database pool ready ({} connections max)", app_state.max_size());
 
-    let app_state: AppState = Arc::new(Mutex::new(conn));
+    if args.iter().any(|a| a == "--migrate-only") {
+        println!("✅ Migrations complete, exiting (--migrate-only)");
+        return Ok(());
+    }
 
     let cors = CorsLayer::new()
         .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
         .allow_origin(Any)
-        .allow_headers(Any);
+        .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION, HeaderName::from_static(error::REQUEST_ID_HEADER)])
+        .expose_headers([HeaderName::from_static(error::REQUEST_ID_HEADER)]);
 
     let api_routes = Router::new()
         .route("/health", get(handlers::health_check))
+        .route("/api/auth/register", post(handlers::register))
+        .route("/api/auth/login", post(handlers::login))
+        .route("/api/auth/logout", post(handlers::logout))
+        .route("/api/auth/me", get(handlers::current_user))
         .route("/api/episodes", get(handlers::get_episodes))
         .route("/api/episodes", post(handlers::create_episode))
+        .route("/api/episodes/search", get(handlers::search_episodes))
         .route("/api/episodes/:id", get(handlers::get_episode))
         .route("/api/episodes/:id", put(handlers::update_episode))
         .route("/api/episodes/:id", delete(handlers::delete_episode))
+        .route("/api/episodes/:id/history", get(handlers::get_episode_history))
+        .route("/api/episodes/:id/medications", get(handlers::list_intakes))
+        .route("/api/episodes/:id/medications", post(handlers::create_intake))
+        .route("/api/episodes/:id/medications/:intake_id", put(handlers::update_intake))
+        .route("/api/episodes/:id/medications/:intake_id", delete(handlers::delete_intake))
+        .route("/api/medications", get(handlers::list_medications))
+        .route("/api/medications", post(handlers::create_medication))
+        .route("/api/medications/:id", get(handlers::get_medication))
+        .route("/api/medications/:id", put(handlers::update_medication))
+        .route("/api/medications/:id", delete(handlers::delete_medication))
+        .route("/api/medications/:id/courses", get(handlers::list_courses))
+        .route("/api/medications/:id/courses", post(handlers::create_course))
+        .route("/api/medications/:id/courses/:course_id", delete(handlers::delete_course))
+        .route("/api/triggers", get(handlers::list_triggers))
+        .route("/api/triggers", post(handlers::create_vocabulary_term))
+        .route("/api/triggers/synonyms", get(handlers::list_synonyms))
+        .route("/api/triggers/synonyms", post(handlers::create_synonym))
+        .route("/api/triggers/synonyms/:id", delete(handlers::delete_synonym))
+        .route("/api/triggers/canonicalize", get(handlers::preview_canonicalization))
+        .route("/api/triggers/canonicalize", post(handlers::apply_canonicalization))
+        .route("/api/triggers/merge", post(handlers::merge_triggers))
+        .route("/api/triggers/:id", put(handlers::rename_trigger))
         .route("/api/analyze", post(handlers::analyze_episode))
         .route("/api/export", get(handlers::export_episodes))
+        .route("/api/export/fhir", get(handlers::export_fhir))
+        .route("/api/export/ics", get(handlers::export_ics))
+        .route("/api/import", post(handlers::import_episodes))
         .route("/api/analytics", get(handlers::get_analytics))
+        .route("/api/analytics/medications", get(handlers::get_medication_efficacy))
         .route("/api/patterns", get(handlers::get_patterns))
         .route("/api/report/pdf", get(handlers::generate_pdf_report))
+        .route("/api/shares", get(handlers::list_shares))
+        .route("/api/shares", post(handlers::create_share))
+        .route("/api/shares/:id", delete(handlers::revoke_share))
+        .route("/api/shares/:id/access-log", get(handlers::get_share_access_log))
+        .route("/share/:token", get(handlers::share_view))
+        .route("/share/:token", post(handlers::share_unlock))
+        .route("/api/share/:token/analytics", get(handlers::share_analytics))
+        .route("/api/share/:token/report/pdf", get(handlers::share_report))
+        .route("/api/calendar-feeds", get(handlers::list_calendar_feeds))
+        .route("/api/calendar-feeds", post(handlers::create_calendar_feed))
+        .route("/api/calendar-feeds/:id", delete(handlers::delete_calendar_feed))
+        .route("/api/calendar/:token/episodes.ics", get(handlers::calendar_feed))
+        .route("/api/openapi.json", get(openapi::openapi_json))
+        .route("/api/docs", get(openapi::api_explorer))
+        .route("/api/docs/:file", get(openapi::explorer_asset))
+        .layer(middleware::from_fn(error::assign_request_id))
         .with_state(app_state);
 
     let static_files = ServeDir::new("static").fallback(
@@ -65,16 +118,36 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let listener = tokio::net::TcpListener::bind(&bind_address)
         .await
-        .expect(&format!("Failed to bind to {}", bind_address));
+        .unwrap_or_else(|_| panic!("Failed to bind to {}", bind_address));
 
     println!("🌐 Server running on {}", bind_address);
     println!("📱 Web interface available at http://0.0.0.0:{}", port);
     println!("🔌 API endpoints available at http://0.0.0.0:{}/api/*", port);
     println!("🏥 Enhanced Vertigo Logger Stage 2 - Production Ready!");
 
-    axum::serve(listener, app)
+    // Share links log the client address of every access
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .expect("Server failed to start");
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Reports pending migrations without applying them; exits non-zero if any are pending.
+fn check_migrations() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = init::open_pool()?;
+    let mut conn = pool.get()?;
+
+    let pending = init::pending_migrations(&mut conn).map_err(|e| e.to_string())?;
+
+    if pending.is_empty() {
+        println!("✅ Database schema is up to date");
+        return Ok(());
+    }
+
+    println!("⏳ {} pending migration(s):", pending.len());
+    for name in &pending {
+        println!("   - {}", name);
+    }
+    std::process::exit(1);
+}
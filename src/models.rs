@@ -1,9 +1,220 @@
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
 use diesel::prelude::*;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Text;
+use diesel::sqlite::{Sqlite, SqliteValue};
 use serde::{Deserialize, Serialize};
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime};
+use utoipa::{IntoParams, ToSchema};
+use crate::error::FieldError;
+use crate::trigger_vocabulary::MatchRule;
+use validator::{Validate, ValidationError};
 
-#[derive(Queryable, Serialize, Debug)]
+/// Declares a closed set of values stored as TEXT and serialized as the same strings.
+macro_rules! text_enum {
+    ($name:ident { $($variant:ident => $text:literal),+ $(,)? }) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow, ToSchema)]
+        #[diesel(sql_type = Text)]
+        pub enum $name {
+            $(#[serde(rename = $text)] $variant),+
+        }
+
+        impl $name {
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $text),+
+                }
+            }
+        }
+
+        impl ToSql<Text, Sqlite> for $name {
+            fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+                out.set_value(self.as_str());
+                Ok(IsNull::No)
+            }
+        }
+
+        impl FromSql<Text, Sqlite> for $name {
+            fn from_sql(value: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+                let text = <String as FromSql<Text, Sqlite>>::from_sql(value)?;
+                match text.as_str() {
+                    $($text => Ok(Self::$variant),)+
+                    other => Err(format!("unrecognized {} value: {}", stringify!($name), other).into()),
+                }
+            }
+        }
+    };
+}
+
+text_enum!(VertigoType {
+    Rotational => "rotational",
+    NonRotational => "non_rotational",
+});
+
+text_enum!(HearingLossSide {
+    Left => "left",
+    Right => "right",
+    Bilateral => "bilateral",
+});
+
+text_enum!(NystagmusDirection {
+    Left => "left",
+    Right => "right",
+    Up => "up",
+    Down => "down",
+    Torsional => "torsional",
+});
+
+text_enum!(MedicationUsage {
+    Preventive => "preventive",
+    Rescue => "rescue",
+});
+
+text_enum!(EpisodeSort {
+    Timestamp => "timestamp",
+    Severity => "severity",
+    Duration => "duration",
+});
+
+text_enum!(SortOrder {
+    Asc => "asc",
+    Desc => "desc",
+});
+
+text_enum!(ExportDelimiter {
+    Comma => "comma",
+    Semicolon => "semicolon",
+    Tab => "tab",
+});
+
+text_enum!(ExportColumn {
+    Id => "id",
+    Timestamp => "timestamp",
+    DurationMinutes => "duration_minutes",
+    Severity => "severity",
+    Symptoms => "symptoms",
+    Triggers => "triggers",
+    Location => "location",
+    ActivitiesBefore => "activities_before",
+    Medications => "medications",
+    MedicationIntakes => "medication_intakes",
+    Notes => "notes",
+    AiAnalysis => "ai_analysis",
+});
+
+text_enum!(ReportTemplate {
+    Medical => "medical",
+    ClinicianSummary => "clinician_summary",
+    Diary => "diary",
+    Disability => "disability",
+});
+
+text_enum!(ReportSection {
+    Summary => "summary",
+    FunctionalImpact => "functional_impact",
+    SeverityChart => "severity_chart",
+    MonthlyChart => "monthly_chart",
+    TriggerChart => "trigger_chart",
+    TimeOfDayChart => "time_of_day_chart",
+    DurationChart => "duration_chart",
+    Triggers => "triggers",
+    Medications => "medications",
+    MedicationEfficacy => "medication_efficacy",
+    SeverityPatterns => "severity_patterns",
+    TimePatterns => "time_patterns",
+    RiskFactors => "risk_factors",
+    Recommendations => "recommendations",
+    RecentEpisodes => "recent_episodes",
+    Diary => "diary",
+    AbsenceLog => "absence_log",
+    Disclaimer => "disclaimer",
+    Appendix => "appendix",
+});
+
+text_enum!(ShareResource {
+    View => "view",
+    Analytics => "analytics",
+    Report => "report",
+});
+
+text_enum!(ShareOutcome {
+    Granted => "granted",
+    WrongPin => "wrong_pin",
+    Locked => "locked",
+    Expired => "expired",
+    Revoked => "revoked",
+});
+
+/// Clinician-oriented symptom checklist recorded alongside the free-text symptoms.
+#[derive(Queryable, Selectable, Insertable, AsChangeset, Serialize, Deserialize, Debug, Clone, Default, PartialEq, ToSchema)]
+#[diesel(table_name = crate::schema::episode_symptoms)]
+#[diesel(treat_none_as_null = true)]
+pub struct SymptomChecklist {
+    pub vertigo_type: Option<VertigoType>,
+    #[serde(default)]
+    pub nausea: bool,
+    #[serde(default)]
+    pub vomiting: bool,
+    #[serde(default)]
+    pub tinnitus: bool,
+    #[serde(default)]
+    pub aural_fullness: bool,
+    pub hearing_loss_side: Option<HearingLossSide>,
+    #[serde(default)]
+    pub headache: bool,
+    #[serde(default)]
+    pub visual_aura: bool,
+    #[serde(default)]
+    pub imbalance: bool,
+    pub nystagmus_direction: Option<NystagmusDirection>,
+}
+
+impl SymptomChecklist {
+    /// The findings in plain English, e.g. `rotational vertigo, nausea, hearing
+    /// loss (left)`, or `None` when nothing was ticked.
+    pub fn summary(&self) -> Option<String> {
+        let mut findings = Vec::new();
+        match self.vertigo_type {
+            Some(VertigoType::Rotational) => findings.push("rotational vertigo".to_string()),
+            Some(VertigoType::NonRotational) => findings.push("non-rotational vertigo".to_string()),
+            None => {}
+        }
+        for (present, name) in [
+            (self.nausea, "nausea"),
+            (self.vomiting, "vomiting"),
+            (self.tinnitus, "tinnitus"),
+            (self.aural_fullness, "aural fullness"),
+            (self.headache, "headache"),
+            (self.visual_aura, "visual aura"),
+            (self.imbalance, "imbalance"),
+        ] {
+            if present {
+                findings.push(name.to_string());
+            }
+        }
+        if let Some(side) = self.hearing_loss_side {
+            findings.push(format!("hearing loss ({})", side.as_str()));
+        }
+        if let Some(direction) = self.nystagmus_direction {
+            findings.push(format!("nystagmus ({})", direction.as_str()));
+        }
+
+        (!findings.is_empty()).then(|| findings.join(", "))
+    }
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::episode_symptoms)]
+pub struct NewSymptomChecklist {
+    pub episode_id: i32,
+    #[diesel(embed)]
+    pub checklist: SymptomChecklist,
+}
+
+#[derive(Queryable, Selectable, Serialize, Debug, ToSchema)]
 #[diesel(table_name = crate::schema::episodes)]
+#[diesel(check_for_backend(Sqlite))]
 pub struct Episode {
     pub id: i32,
     pub timestamp: NaiveDateTime,
@@ -17,79 +228,1386 @@ pub struct Episode {
     pub notes: Option<String>,
     pub ai_analysis: Option<String>,
     pub created_at: NaiveDateTime,
+    #[diesel(embed)]
+    pub symptom_checklist: Option<SymptomChecklist>,
+}
+
+/// Limits on free-text episode fields, in characters.
+pub const MAX_LOCATION_LEN: u64 = 200;
+pub const MAX_TEXT_LEN: u64 = 4_000;
+pub const MAX_NOTES_LEN: u64 = 20_000;
+/// Limits on the comma-separated trigger list.
+pub const MAX_TRIGGERS: usize = 20;
+pub const MAX_TRIGGER_LEN: usize = 60;
+/// One week; longer spells are logged as separate episodes.
+pub const MAX_DURATION_MINUTES: i32 = 10_080;
+
+/// Device clocks drift, so allow an episode to be stamped slightly ahead of the server.
+const CLOCK_SKEW_MINUTES: i64 = 5;
+
+/// An episode cannot have started after it was logged, and `created_at` is
+/// the time of the insert. Nothing predates the Unix epoch either.
+fn validate_episode_timestamp(timestamp: &NaiveDateTime) -> Result<(), ValidationError> {
+    let logged_at = chrono::Utc::now().naive_utc() + chrono::Duration::minutes(CLOCK_SKEW_MINUTES);
+    if *timestamp > logged_at {
+        return Err(ValidationError::new("future_timestamp").with_message("must not be in the future".into()));
+    }
+    if *timestamp < chrono::DateTime::UNIX_EPOCH.naive_utc() {
+        return Err(ValidationError::new("timestamp_too_old").with_message("must not be before 1970".into()));
+    }
+    Ok(())
+}
+
+fn validate_trigger_list(triggers: &str) -> Result<(), ValidationError> {
+    let names: Vec<&str> = triggers.split(',').map(str::trim).filter(|t| !t.is_empty()).collect();
+    if names.len() > MAX_TRIGGERS {
+        return Err(ValidationError::new("too_many_triggers")
+            .with_message(format!("must list at most {} triggers", MAX_TRIGGERS).into()));
+    }
+    if names.iter().any(|name| name.chars().count() > MAX_TRIGGER_LEN) {
+        return Err(ValidationError::new("trigger_too_long")
+            .with_message(format!("each trigger must be at most {} characters", MAX_TRIGGER_LEN).into()));
+    }
+    Ok(())
+}
+
+fn validate_not_blank(text: &str) -> Result<(), ValidationError> {
+    if text.trim().is_empty() {
+        return Err(ValidationError::new("blank").with_message("must not be empty".into()));
+    }
+    Ok(())
+}
+
+/// A rule spanning several fields, reported against `field`. Struct-level
+/// checks would otherwise surface under validator's `__all__` key.
+fn cross_field_error(field: &'static str, code: &'static str, message: impl Into<String>) -> ValidationError {
+    let mut error = ValidationError::new(code).with_message(message.into().into());
+    error.add_param("field".into(), &field);
+    error
+}
+
+/// Inclusive date or time bounds must not run backwards.
+fn validate_bounds<T: PartialOrd>(from: Option<T>, to: Option<T>) -> Result<(), ValidationError> {
+    match (from, to) {
+        (Some(from), Some(to)) if from > to => Err(cross_field_error("to", "reversed_range", "must not be before from")),
+        _ => Ok(()),
+    }
 }
 
-#[derive(Insertable, Deserialize, Debug)]
+#[derive(Insertable, Deserialize, Validate, Debug, ToSchema)]
 #[diesel(table_name = crate::schema::episodes)]
 pub struct NewEpisode {
+    #[validate(custom(function = "validate_episode_timestamp"))]
     pub timestamp: Option<NaiveDateTime>,
+    #[validate(range(min = 0, max = MAX_DURATION_MINUTES))]
     pub duration_minutes: Option<i32>,
+    #[validate(range(min = 1, max = 5))]
     pub severity: i32,
+    // Linked through episode_triggers; the column is rewritten from those links
+    #[diesel(skip_insertion)]
+    #[validate(custom(function = "validate_trigger_list"))]
     pub triggers: Option<String>,
+    #[validate(length(max = MAX_TEXT_LEN))]
     pub symptoms: Option<String>,
+    #[validate(length(max = MAX_LOCATION_LEN))]
     pub location: Option<String>,
+    #[validate(length(max = MAX_TEXT_LEN))]
     pub activities_before: Option<String>,
+    #[validate(length(max = MAX_TEXT_LEN))]
     pub medications_taken: Option<String>,
+    #[validate(length(max = MAX_NOTES_LEN))]
     pub notes: Option<String>,
+    #[diesel(skip_insertion)]
+    pub symptom_checklist: Option<SymptomChecklist>,
 }
 
-#[derive(Deserialize, Debug)]
+/// Sorting and paging for `GET /api/episodes`, alongside the [`EpisodeFilter`].
+#[derive(Deserialize, Validate, Debug, Default, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[validate(schema(function = "validate_list_cursor"))]
+pub struct EpisodeListQuery {
+    pub sort: Option<EpisodeSort>,
+    pub order: Option<SortOrder>,
+    #[validate(range(min = 1, max = EpisodeListQuery::MAX_LIMIT))]
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+fn validate_list_cursor(query: &EpisodeListQuery) -> Result<(), ValidationError> {
+    query.cursor()
+        .map(|_| ())
+        .map_err(|message| cross_field_error("cursor", "cursor", message))
+}
+
+impl EpisodeListQuery {
+    pub const DEFAULT_LIMIT: i64 = 50;
+    pub const MAX_LIMIT: i64 = 200;
+
+    pub fn sort(&self) -> EpisodeSort {
+        self.sort.unwrap_or(EpisodeSort::Timestamp)
+    }
+
+    pub fn order(&self) -> SortOrder {
+        self.order.unwrap_or(SortOrder::Desc)
+    }
+
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(Self::DEFAULT_LIMIT)
+    }
+
+    /// The decoded cursor, which must have been issued for the same sort and order.
+    pub fn cursor(&self) -> Result<Option<EpisodeCursor>, String> {
+        let Some(raw) = &self.cursor else { return Ok(None) };
+        let cursor = EpisodeCursor::decode(raw).ok_or("cursor is not valid")?;
+        if cursor.sort != self.sort() || cursor.order != self.order() {
+            return Err("cursor was issued for a different sort order".to_string());
+        }
+        Ok(Some(cursor))
+    }
+}
+
+/// Position after the last episode of a page: its sort key and id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EpisodeCursor {
+    pub sort: EpisodeSort,
+    pub order: SortOrder,
+    pub timestamp: NaiveDateTime,
+    /// Severity, or duration with missing durations as -1
+    pub value: i32,
+    pub id: i32,
+}
+
+impl EpisodeCursor {
+    const TIMESTAMP_FORMAT: &'static str = "%Y-%m-%dT%H:%M:%S%.f";
+
+    pub fn after(episode: &Episode, sort: EpisodeSort, order: SortOrder) -> Self {
+        let value = match sort {
+            EpisodeSort::Severity => episode.severity,
+            EpisodeSort::Duration => episode.duration_minutes.unwrap_or(-1),
+            EpisodeSort::Timestamp => 0,
+        };
+        EpisodeCursor { sort, order, timestamp: episode.timestamp, value, id: episode.id }
+    }
+
+    /// Opaque to clients: URL-safe base64 of `sort|order|timestamp|value|id`.
+    pub fn encode(&self) -> String {
+        use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+        let raw = format!(
+            "{}|{}|{}|{}|{}",
+            self.sort.as_str(), self.order.as_str(), self.timestamp.format(Self::TIMESTAMP_FORMAT), self.value, self.id
+        );
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    pub fn decode(encoded: &str) -> Option<Self> {
+        use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+        let raw = String::from_utf8(URL_SAFE_NO_PAD.decode(encoded).ok()?).ok()?;
+        let mut parts = raw.split('|');
+        let sort = serde_json::from_value(serde_json::Value::String(parts.next()?.to_string())).ok()?;
+        let order = serde_json::from_value(serde_json::Value::String(parts.next()?.to_string())).ok()?;
+        let cursor = EpisodeCursor {
+            sort,
+            order,
+            timestamp: NaiveDateTime::parse_from_str(parts.next()?, Self::TIMESTAMP_FORMAT).ok()?,
+            value: parts.next()?.parse().ok()?,
+            id: parts.next()?.parse().ok()?,
+        };
+        parts.next().is_none().then_some(cursor)
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct EpisodePage {
+    pub episodes: Vec<Episode>,
+    /// Pass back as `cursor` for the next page; absent on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// Query string for `GET /api/episodes/search`.
+#[derive(Deserialize, Validate, Debug, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EpisodeSearchQuery {
+    #[validate(custom(function = "validate_not_blank"), length(max = 200))]
+    pub q: String,
+    #[serde(default = "EpisodeSearchQuery::default_limit")]
+    #[validate(range(min = 1, max = 100))]
+    pub limit: i64,
+}
+
+impl EpisodeSearchQuery {
+    fn default_limit() -> i64 {
+        20
+    }
+
+    /// The search text as an FTS5 expression. Each word is quoted, so FTS5
+    /// operators typed by the user are searched for literally, and matched as a
+    /// prefix; every word must appear somewhere in the episode.
+    pub fn fts_expression(&self) -> String {
+        self.q
+            .split_whitespace()
+            .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct EpisodeSearchHit {
+    pub episode: Episode,
+    /// HTML-escaped excerpt of the best-matching field, with matches in `<mark>`
+    pub snippet: String,
+    /// BM25 score; lower is a better match
+    pub rank: f64,
+}
+
+#[derive(Deserialize, Validate, Debug, ToSchema)]
 pub struct AnalysisRequest {
+    #[validate(length(min = 1, max = MAX_TEXT_LEN))]
     pub symptoms: String,
+    #[validate(custom(function = "validate_trigger_list"))]
     pub triggers: Option<String>,
+    #[validate(range(min = 1, max = 5))]
     pub severity: Option<i32>,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, ToSchema)]
 pub struct AnalysisResponse {
     pub analysis: String,
     pub recommendations: Vec<String>,
     pub confidence: f32,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(AsChangeset, Deserialize, Validate, Debug, Default, ToSchema)]
+#[diesel(table_name = crate::schema::episodes)]
 pub struct EpisodeUpdate {
+    #[validate(range(min = 0, max = MAX_DURATION_MINUTES))]
     pub duration_minutes: Option<i32>,
+    #[validate(range(min = 1, max = 5))]
     pub severity: Option<i32>,
+    #[diesel(skip_update)]
+    #[validate(custom(function = "validate_trigger_list"))]
     pub triggers: Option<String>,
+    #[validate(length(max = MAX_TEXT_LEN))]
     pub symptoms: Option<String>,
+    #[validate(length(max = MAX_LOCATION_LEN))]
     pub location: Option<String>,
+    #[validate(length(max = MAX_TEXT_LEN))]
     pub activities_before: Option<String>,
+    #[validate(length(max = MAX_TEXT_LEN))]
     pub medications_taken: Option<String>,
+    #[validate(length(max = MAX_NOTES_LEN))]
     pub notes: Option<String>,
+    #[validate(length(max = MAX_NOTES_LEN))]
     pub ai_analysis: Option<String>,
+    // Replaces the whole checklist when present
+    #[diesel(skip_update)]
+    pub symptom_checklist: Option<SymptomChecklist>,
+}
+
+#[derive(Queryable, Serialize, Debug, ToSchema)]
+#[diesel(table_name = crate::schema::episode_revisions)]
+pub struct EpisodeRevision {
+    pub id: i32,
+    pub episode_id: i32,
+    pub field_name: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub changed_by: String,
+    pub changed_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::episode_revisions)]
+pub struct NewEpisodeRevision {
+    pub episode_id: i32,
+    pub field_name: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub changed_by: String,
+}
+
+#[derive(Queryable, Serialize, Debug, Clone, ToSchema)]
+#[diesel(table_name = crate::schema::triggers)]
+pub struct Trigger {
+    pub id: i32,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+    pub canonical: bool,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::triggers)]
+pub struct NewTrigger<'a> {
+    pub name: &'a str,
+    pub canonical: bool,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::episode_triggers)]
+pub struct NewEpisodeTrigger {
+    pub episode_id: i32,
+    pub trigger_id: i32,
+    pub position: i32,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct TriggerSummary {
+    pub id: i32,
+    pub name: String,
+    pub canonical: bool,
+    pub episode_count: i64,
+}
+
+#[derive(Deserialize, Validate, Debug, ToSchema)]
+pub struct NewVocabularyTerm {
+    #[validate(custom(function = "validate_not_blank"))]
+    pub name: String,
+}
+
+#[derive(Queryable, Serialize, Debug, ToSchema)]
+#[diesel(table_name = crate::schema::trigger_synonyms)]
+pub struct TriggerSynonym {
+    pub id: i32,
+    pub phrase: String,
+    pub trigger_id: i32,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Deserialize, Validate, Debug, ToSchema)]
+#[diesel(table_name = crate::schema::trigger_synonyms)]
+pub struct NewTriggerSynonym {
+    #[validate(custom(function = "validate_not_blank"))]
+    pub phrase: String,
+    pub trigger_id: i32,
+}
+
+/// A non-canonical trigger and the vocabulary term it would be folded into.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct TriggerRemap {
+    pub trigger_id: i32,
+    pub name: String,
+    pub episode_count: i64,
+    pub canonical_id: i32,
+    pub canonical_name: String,
+    pub rule: MatchRule,
+    pub distance: usize,
+}
+
+#[derive(Deserialize, Validate, Debug, ToSchema)]
+pub struct TriggerRename {
+    #[validate(custom(function = "validate_not_blank"))]
+    pub name: String,
+}
+
+#[derive(Deserialize, Validate, Debug, ToSchema)]
+#[validate(schema(function = "validate_merge_sources"))]
+pub struct TriggerMerge {
+    #[validate(length(min = 1))]
+    pub source_ids: Vec<i32>,
+    pub target_id: i32,
+}
+
+fn validate_merge_sources(merge: &TriggerMerge) -> Result<(), ValidationError> {
+    if merge.source_ids.contains(&merge.target_id) {
+        return Err(cross_field_error("source_ids", "includes_target", "must not include target_id"));
+    }
+    Ok(())
+}
+
+#[derive(Queryable, Serialize, Debug, Clone, ToSchema)]
+#[diesel(table_name = crate::schema::medications)]
+pub struct Medication {
+    pub id: i32,
+    pub name: String,
+    pub drug_class: Option<String>,
+    pub strength: Option<String>,
+    pub usage: MedicationUsage,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Deserialize, Validate, Debug, ToSchema)]
+#[diesel(table_name = crate::schema::medications)]
+pub struct NewMedication {
+    #[validate(custom(function = "validate_not_blank"))]
+    pub name: String,
+    pub drug_class: Option<String>,
+    pub strength: Option<String>,
+    pub usage: MedicationUsage,
+}
+
+#[derive(AsChangeset, Deserialize, Validate, Debug, Default, ToSchema)]
+#[diesel(table_name = crate::schema::medications)]
+pub struct MedicationUpdate {
+    #[validate(custom(function = "validate_not_blank"))]
+    pub name: Option<String>,
+    pub drug_class: Option<String>,
+    pub strength: Option<String>,
+    pub usage: Option<MedicationUsage>,
+}
+
+impl MedicationUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.drug_class.is_none() && self.strength.is_none() && self.usage.is_none()
+    }
+}
+
+/// One dose taken for an episode, joined with the medication's name.
+#[derive(Queryable, Serialize, Debug, Clone, ToSchema)]
+pub struct MedicationIntake {
+    pub id: i32,
+    pub episode_id: i32,
+    pub medication_id: i32,
+    pub medication_name: String,
+    pub dose: String,
+    pub minutes_after_onset: Option<i32>,
+    pub perceived_effect: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Deserialize, Validate, Debug, ToSchema)]
+pub struct IntakeRequest {
+    pub medication_id: i32,
+    #[validate(custom(function = "validate_not_blank"))]
+    pub dose: String,
+    pub minutes_after_onset: Option<i32>,
+    #[validate(range(min = 0, max = 5))]
+    pub perceived_effect: Option<i32>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::medication_intakes)]
+pub struct NewMedicationIntake<'a> {
+    pub episode_id: i32,
+    pub medication_id: i32,
+    pub dose: &'a str,
+    pub minutes_after_onset: Option<i32>,
+    pub perceived_effect: Option<i32>,
+}
+
+#[derive(AsChangeset, Deserialize, Validate, Debug, Default, ToSchema)]
+#[diesel(table_name = crate::schema::medication_intakes)]
+pub struct IntakeUpdate {
+    pub medication_id: Option<i32>,
+    #[validate(custom(function = "validate_not_blank"))]
+    pub dose: Option<String>,
+    pub minutes_after_onset: Option<i32>,
+    #[validate(range(min = 0, max = 5))]
+    pub perceived_effect: Option<i32>,
+}
+
+impl IntakeUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.medication_id.is_none() && self.dose.is_none() && self.minutes_after_onset.is_none() && self.perceived_effect.is_none()
+    }
+}
+
+#[derive(Queryable, Serialize, Debug, Clone, ToSchema)]
+#[diesel(table_name = crate::schema::medication_courses)]
+pub struct MedicationCourse {
+    pub id: i32,
+    pub medication_id: i32,
+    pub started_on: NaiveDate,
+    pub stopped_on: Option<NaiveDate>,
+    pub notes: Option<String>,
+    pub created_at: NaiveDateTime,
+    #[serde(skip_serializing)]
+    pub user_id: Option<i32>,
+}
+
+#[derive(Deserialize, Validate, Debug, ToSchema)]
+#[validate(schema(function = "validate_course_dates"))]
+pub struct CourseRequest {
+    pub started_on: NaiveDate,
+    pub stopped_on: Option<NaiveDate>,
+    pub notes: Option<String>,
+}
+
+fn validate_course_dates(course: &CourseRequest) -> Result<(), ValidationError> {
+    if course.stopped_on.is_some_and(|stopped| stopped < course.started_on) {
+        return Err(cross_field_error("stopped_on", "reversed_range", "must not be before started_on"));
+    }
+    Ok(())
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::medication_courses)]
+pub struct NewMedicationCourse<'a> {
+    pub user_id: i32,
+    pub medication_id: i32,
+    pub started_on: NaiveDate,
+    pub stopped_on: Option<NaiveDate>,
+    pub notes: Option<&'a str>,
+}
+
+/// Days of history compared on each side of a course start date.
+#[derive(Deserialize, Validate, Debug, Clone, Copy, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EfficacyWindow {
+    #[serde(default = "EfficacyWindow::default_days")]
+    #[validate(range(min = 7, max = 730))]
+    pub before_days: i64,
+    #[serde(default = "EfficacyWindow::default_days")]
+    #[validate(range(min = 7, max = 730))]
+    pub after_days: i64,
+}
+
+impl EfficacyWindow {
+    fn default_days() -> i64 {
+        90
+    }
+}
+
+impl Default for EfficacyWindow {
+    fn default() -> Self {
+        EfficacyWindow { before_days: Self::default_days(), after_days: Self::default_days() }
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct WindowStats {
+    pub from: NaiveDate,
+    /// Exclusive
+    pub to: NaiveDate,
+    pub days: i64,
+    pub episode_count: i64,
+    pub episodes_per_30_days: f64,
+    pub mean_severity: Option<f64>,
+    pub mean_duration_minutes: Option<f64>,
+}
+
+/// After/before ratio of episode rates with a 95% confidence interval.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct RateComparison {
+    pub rate_ratio: Option<f64>,
+    pub ci_low: Option<f64>,
+    pub ci_high: Option<f64>,
+}
+
+/// After minus before, Cohen's d, and a 95% confidence interval for the difference.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct MeanComparison {
+    pub difference: Option<f64>,
+    pub effect_size: Option<f64>,
+    pub ci_low: Option<f64>,
+    pub ci_high: Option<f64>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct MedicationEfficacy {
+    pub medication_id: i32,
+    pub name: String,
+    pub course_id: i32,
+    pub started_on: NaiveDate,
+    pub stopped_on: Option<NaiveDate>,
+    pub before: WindowStats,
+    pub after: WindowStats,
+    pub frequency: RateComparison,
+    pub severity: MeanComparison,
+    pub duration: MeanComparison,
+}
+
+#[derive(Queryable, Serialize, Debug, Clone, ToSchema)]
+#[diesel(table_name = crate::schema::users)]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    #[serde(skip_serializing)]
+    pub password_hash: String,
+    pub created_at: NaiveDateTime,
+    /// May change the trigger vocabulary and the medication catalog
+    pub is_admin: bool,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::users)]
+pub struct NewUser<'a> {
+    pub username: &'a str,
+    pub password_hash: &'a str,
+    pub is_admin: bool,
+}
+
+/// Body of the register and login requests.
+#[derive(Deserialize, Validate, ToSchema)]
+pub struct Credentials {
+    #[validate(custom(function = "validate_username"))]
+    pub username: String,
+    #[validate(length(min = 8))]
+    pub password: String,
+}
+
+fn validate_username(username: &str) -> Result<(), ValidationError> {
+    let username = username.trim();
+    if !(3..=32).contains(&username.len()) {
+        return Err(ValidationError::new("length").with_message("must be 3 to 32 characters".into()));
+    }
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')) {
+        return Err(ValidationError::new("username_chars").with_message("may only contain letters, digits, '.', '_' and '-'".into()));
+    }
+    Ok(())
+}
+
+#[derive(Queryable, Debug, Clone)]
+#[diesel(table_name = crate::schema::sessions)]
+pub struct Session {
+    pub id: i32,
+    pub user_id: i32,
+    pub token_hash: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::sessions)]
+pub struct NewSession<'a> {
+    pub user_id: i32,
+    pub token_hash: &'a str,
+    pub expires_at: NaiveDateTime,
+}
+
+/// Returned on login; the token is only ever shown here.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct SessionResponse {
+    pub token: String,
+    pub expires_at: NaiveDateTime,
+    pub user: User,
+}
+
+#[derive(Queryable, Serialize, Debug, Clone, ToSchema)]
+#[diesel(table_name = crate::schema::share_links)]
+pub struct ShareLink {
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub user_id: i32,
+    pub label: Option<String>,
+    pub from_date: Option<NaiveDate>,
+    pub to_date: Option<NaiveDate>,
+    #[serde(rename = "pin_protected", serialize_with = "serialize_is_some")]
+    #[schema(value_type = bool)]
+    pub pin_hash: Option<String>,
+    pub failed_pin_attempts: i32,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+impl ShareLink {
+    pub fn filter(&self) -> EpisodeFilter {
+        EpisodeFilter { from: self.from_date.map(DateBound::Date), to: self.to_date.map(DateBound::Date), ..Default::default() }
+    }
+}
+
+fn serialize_is_some<S: serde::Serializer>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bool(value.is_some())
+}
+
+#[derive(Deserialize, Validate, Debug, ToSchema)]
+#[validate(schema(function = "validate_share_dates"))]
+pub struct ShareRequest {
+    pub label: Option<String>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    /// Defaults to 72 hours
+    #[validate(range(min = 1, max = 720))]
+    pub expires_in_hours: Option<i64>,
+    #[validate(custom(function = "validate_pin"))]
+    pub pin: Option<String>,
+}
+
+fn validate_share_dates(request: &ShareRequest) -> Result<(), ValidationError> {
+    validate_bounds(request.from, request.to)
+}
+
+fn validate_pin(pin: &str) -> Result<(), ValidationError> {
+    if !(4..=12).contains(&pin.len()) || !pin.chars().all(|c| c.is_ascii_digit()) {
+        return Err(ValidationError::new("pin").with_message("must be 4 to 12 digits".into()));
+    }
+    Ok(())
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::share_links)]
+pub struct NewShareLink<'a> {
+    pub user_id: i32,
+    pub label: Option<&'a str>,
+    pub from_date: Option<NaiveDate>,
+    pub to_date: Option<NaiveDate>,
+    pub pin_hash: Option<&'a str>,
+    pub expires_at: NaiveDateTime,
+}
+
+/// The PIN form a browser posts to `/share/{token}`. API clients send the PIN
+/// in an `X-Share-Pin` header instead; it never goes in the URL.
+#[derive(Deserialize, Debug, Default, ToSchema)]
+pub struct ShareUnlock {
+    pub pin: String,
+}
+
+/// Returned once when a link is created; the token is not stored.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CreatedShare {
+    #[serde(flatten)]
+    pub link: ShareLink,
+    pub token: String,
+    pub url: String,
+}
+
+#[derive(Queryable, Serialize, Debug, Clone, ToSchema)]
+#[diesel(table_name = crate::schema::share_access_log)]
+pub struct ShareAccess {
+    pub id: i32,
+    pub share_link_id: i32,
+    pub accessed_at: NaiveDateTime,
+    pub resource: ShareResource,
+    pub outcome: ShareOutcome,
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::share_access_log)]
+pub struct NewShareAccess<'a> {
+    pub share_link_id: i32,
+    pub resource: ShareResource,
+    pub outcome: ShareOutcome,
+    pub client_ip: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+}
+
+/// A subscribable `.ics` feed of the user's episodes.
+#[derive(Queryable, Serialize, Debug, Clone, ToSchema)]
+#[diesel(table_name = crate::schema::calendar_feeds)]
+pub struct CalendarFeed {
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub user_id: i32,
+    pub label: Option<String>,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    /// When a calendar app last fetched the feed
+    pub last_fetched_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Deserialize, Validate, Debug, Default, ToSchema)]
+pub struct CalendarFeedRequest {
+    /// Also used as the calendar's display name
+    #[validate(length(max = 100))]
+    pub label: Option<String>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::calendar_feeds)]
+pub struct NewCalendarFeed<'a> {
+    pub user_id: i32,
+    pub label: Option<&'a str>,
+    pub token_hash: &'a str,
+}
+
+/// Returned once when a feed is created; the token is not stored.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CreatedCalendarFeed {
+    #[serde(flatten)]
+    pub feed: CalendarFeed,
+    pub token: String,
+    pub url: String,
+}
+
+/// One end of a date range in a query string: either a date, which covers the
+/// whole day, or a date and time such as `2025-03-01T09:30:00`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateBound {
+    Date(NaiveDate),
+    DateTime(NaiveDateTime),
+}
+
+impl DateBound {
+    /// The first moment the bound covers.
+    pub fn first(self) -> NaiveDateTime {
+        match self {
+            DateBound::Date(date) => date.and_time(chrono::NaiveTime::MIN),
+            DateBound::DateTime(at) => at,
+        }
+    }
+
+    /// The last moment the bound covers.
+    pub fn last(self) -> NaiveDateTime {
+        match self {
+            DateBound::Date(date) => date.and_hms_nano_opt(23, 59, 59, 999_999_999).unwrap(),
+            DateBound::DateTime(at) => at,
+        }
+    }
+}
+
+impl From<NaiveDate> for DateBound {
+    fn from(date: NaiveDate) -> Self {
+        DateBound::Date(date)
+    }
+}
+
+impl From<NaiveDateTime> for DateBound {
+    fn from(at: NaiveDateTime) -> Self {
+        DateBound::DateTime(at)
+    }
+}
+
+impl std::str::FromStr for DateBound {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+            return Ok(DateBound::Date(date));
+        }
+        text.parse::<NaiveDateTime>()
+            .map(DateBound::DateTime)
+            .map_err(|_| format!("expected a date such as 2025-03-01 or a date and time such as 2025-03-01T09:30:00, got {}", text))
+    }
+}
+
+impl std::fmt::Display for DateBound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DateBound::Date(date) => write!(f, "{}", date.format("%Y-%m-%d")),
+            DateBound::DateTime(at) => write!(f, "{}", at.format("%Y-%m-%dT%H:%M:%S%.f")),
+        }
+    }
+}
+
+impl Serialize for DateBound {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for DateBound {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Which episodes a request covers: the episode list, analytics, patterns,
+/// reports, exports and calendar feeds all take these same query parameters.
+/// Every part is optional; the default covers everything the user has logged.
+#[derive(Deserialize, Serialize, Validate, Debug, Clone, Default, PartialEq, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[validate(schema(function = "validate_filter_bounds"))]
+pub struct EpisodeFilter {
+    /// Inclusive; a date on its own counts from the start of that day
+    #[param(value_type = Option<String>, example = "2025-03-01")]
+    pub from: Option<DateBound>,
+    /// Inclusive; a date on its own counts to the end of that day
+    #[param(value_type = Option<String>, example = "2025-03-31T18:00:00")]
+    pub to: Option<DateBound>,
+    #[validate(range(min = 1, max = 5))]
+    pub min_severity: Option<i32>,
+    #[validate(range(min = 1, max = 5))]
+    pub max_severity: Option<i32>,
+    /// Comma-separated trigger names, ignoring case; an episode with any of them
+    /// is included. `trigger` is accepted too.
+    #[serde(alias = "trigger")]
+    pub triggers: Option<String>,
+    /// Substring of the location, ignoring case
+    pub location: Option<String>,
+    /// A recorded intake of this medication, or a mention in `medications_taken`
+    pub medication: Option<String>,
+    pub has_notes: Option<bool>,
+}
+
+fn validate_filter_bounds(filter: &EpisodeFilter) -> Result<(), ValidationError> {
+    if let (Some(min), Some(max)) = (filter.min_severity, filter.max_severity) {
+        if min > max {
+            return Err(cross_field_error("max_severity", "reversed_range", "must not be below min_severity"));
+        }
+    }
+    validate_bounds(filter.from.map(DateBound::first), filter.to.map(DateBound::last))
+}
+
+impl EpisodeFilter {
+    pub fn trigger_names(&self) -> Vec<String> {
+        self.triggers
+            .as_deref()
+            .unwrap_or("")
+            .split(',')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// The dates a report covers. Open ends fall back to the first episode and
+    /// to `today`, so the header always names a concrete period.
+    pub fn period(&self, episodes: &[Episode], today: NaiveDate) -> String {
+        let format = |date: NaiveDate| date.format("%B %d, %Y").to_string();
+        let first = self.from
+            .map(|from| from.first().date())
+            .or_else(|| episodes.iter().map(|e| e.timestamp.date()).min());
+        let to = self.to.map(|to| to.last().date());
+
+        match first {
+            Some(from) => format!("{} to {}", format(from), format(to.unwrap_or(today))),
+            None => match to {
+                Some(to) => format!("Up to {}", format(to)),
+                None => "No episodes recorded".to_string(),
+            },
+        }
+    }
+
+    /// Severity and trigger restrictions, for stating alongside the period.
+    pub fn describe_criteria(&self) -> Option<String> {
+        let mut criteria = Vec::new();
+        match (self.min_severity, self.max_severity) {
+            (Some(min), Some(max)) if min == max => criteria.push(format!("severity {}", min)),
+            (Some(min), Some(max)) => criteria.push(format!("severity {}-{}", min, max)),
+            (Some(min), None) => criteria.push(format!("severity {} or above", min)),
+            (None, Some(max)) => criteria.push(format!("severity {} or below", max)),
+            (None, None) => {}
+        }
+        let triggers = self.trigger_names();
+        if !triggers.is_empty() {
+            criteria.push(format!("triggers: {}", triggers.join(", ")));
+        }
+        if let Some(location) = self.location.as_deref().map(str::trim).filter(|l| !l.is_empty()) {
+            criteria.push(format!("location: {}", location));
+        }
+        if let Some(medication) = self.medication.as_deref().map(str::trim).filter(|m| !m.is_empty()) {
+            criteria.push(format!("medication: {}", medication));
+        }
+        match self.has_notes {
+            Some(true) => criteria.push("with notes".to_string()),
+            Some(false) => criteria.push("without notes".to_string()),
+            None => {}
+        }
+        (!criteria.is_empty()).then(|| criteria.join("; "))
+    }
+}
+
+/// File format for `GET /api/export`, alongside the [`EpisodeFilter`].
+#[derive(Deserialize, Validate, Debug, Clone, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    /// Defaults to comma
+    pub delimiter: Option<ExportDelimiter>,
+    /// Comma-separated column keys in output order, e.g. `timestamp,severity,notes`;
+    /// defaults to every column
+    #[validate(custom(function = "validate_export_columns"))]
+    pub columns: Option<String>,
+}
+
+impl ExportColumn {
+    /// Columns an import can fill in. The rest are derived or server-assigned,
+    /// so an import of our own export skips them.
+    pub const IMPORTABLE: [ExportColumn; 9] = [
+        ExportColumn::Timestamp,
+        ExportColumn::DurationMinutes,
+        ExportColumn::Severity,
+        ExportColumn::Symptoms,
+        ExportColumn::Triggers,
+        ExportColumn::Location,
+        ExportColumn::ActivitiesBefore,
+        ExportColumn::Medications,
+        ExportColumn::Notes,
+    ];
+
+    pub const ALL: [ExportColumn; 12] = [
+        ExportColumn::Id,
+        ExportColumn::Timestamp,
+        ExportColumn::DurationMinutes,
+        ExportColumn::Severity,
+        ExportColumn::Symptoms,
+        ExportColumn::Triggers,
+        ExportColumn::Location,
+        ExportColumn::ActivitiesBefore,
+        ExportColumn::Medications,
+        ExportColumn::MedicationIntakes,
+        ExportColumn::Notes,
+        ExportColumn::AiAnalysis,
+    ];
+
+    pub fn header(&self) -> &'static str {
+        match self {
+            ExportColumn::Id => "ID",
+            ExportColumn::Timestamp => "Timestamp",
+            ExportColumn::DurationMinutes => "Duration (min)",
+            ExportColumn::Severity => "Severity",
+            ExportColumn::Symptoms => "Symptoms",
+            ExportColumn::Triggers => "Triggers",
+            ExportColumn::Location => "Location",
+            ExportColumn::ActivitiesBefore => "Activities Before",
+            ExportColumn::Medications => "Medications",
+            ExportColumn::MedicationIntakes => "Medication Intakes",
+            ExportColumn::Notes => "Notes",
+            ExportColumn::AiAnalysis => "AI Analysis",
+        }
+    }
+}
+
+impl ExportQuery {
+    pub fn delimiter(&self) -> ExportDelimiter {
+        self.delimiter.unwrap_or(ExportDelimiter::Comma)
+    }
+
+    pub fn columns(&self) -> Result<Vec<ExportColumn>, String> {
+        match self.columns.as_deref() {
+            Some(keys) => export_columns(keys),
+            None => Ok(ExportColumn::ALL.to_vec()),
+        }
+    }
+}
+
+fn export_columns(keys: &str) -> Result<Vec<ExportColumn>, String> {
+    let mut columns = Vec::new();
+    for key in keys.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+        let column = ExportColumn::ALL.into_iter()
+            .find(|c| c.as_str() == key)
+            .ok_or_else(|| format!("unknown column: {}", key))?;
+        if columns.contains(&column) {
+            return Err(format!("column listed twice: {}", key));
+        }
+        columns.push(column);
+    }
+    if columns.is_empty() {
+        return Err("must name at least one column".to_string());
+    }
+    Ok(columns)
+}
+
+fn validate_export_columns(keys: &str) -> Result<(), ValidationError> {
+    export_columns(keys)
+        .map(|_| ())
+        .map_err(|message| ValidationError::new("columns").with_message(message.into()))
 }
 
-#[derive(Serialize, Debug)]
+/// What a report template prints: its sections in order, each with whether it
+/// is on unless the request toggles it, and optionally a page limit that
+/// sections give way to, in order, when they would run past it.
+#[derive(Debug)]
+pub struct TemplateDefinition {
+    pub name: &'static str,
+    /// Attachment file name before the date
+    pub file_stem: &'static str,
+    pub max_pages: Option<usize>,
+    pub sections: &'static [(ReportSection, bool)],
+}
+
+const MEDICAL_TEMPLATE: TemplateDefinition = TemplateDefinition {
+    name: "Vertigo Episode Medical Report",
+    file_stem: "vertigo-medical-report",
+    max_pages: None,
+    sections: &[
+        (ReportSection::Summary, true),
+        (ReportSection::FunctionalImpact, false),
+        (ReportSection::SeverityChart, true),
+        (ReportSection::MonthlyChart, true),
+        (ReportSection::TriggerChart, true),
+        (ReportSection::TimeOfDayChart, true),
+        (ReportSection::DurationChart, true),
+        (ReportSection::Triggers, true),
+        (ReportSection::Medications, true),
+        (ReportSection::MedicationEfficacy, true),
+        (ReportSection::SeverityPatterns, true),
+        (ReportSection::TimePatterns, true),
+        (ReportSection::RiskFactors, true),
+        (ReportSection::Recommendations, true),
+        (ReportSection::RecentEpisodes, true),
+        (ReportSection::Disclaimer, true),
+        (ReportSection::Appendix, true),
+    ],
+};
+
+const CLINICIAN_SUMMARY_TEMPLATE: TemplateDefinition = TemplateDefinition {
+    name: "Vertigo Clinician Summary",
+    file_stem: "vertigo-clinician-summary",
+    max_pages: Some(1),
+    sections: &[
+        (ReportSection::Summary, true),
+        (ReportSection::FunctionalImpact, false),
+        (ReportSection::Triggers, true),
+        (ReportSection::Medications, true),
+        (ReportSection::MedicationEfficacy, true),
+        (ReportSection::RiskFactors, true),
+        (ReportSection::RecentEpisodes, true),
+        (ReportSection::SeverityChart, false),
+        (ReportSection::MonthlyChart, false),
+        (ReportSection::TriggerChart, false),
+        (ReportSection::TimeOfDayChart, false),
+        (ReportSection::DurationChart, false),
+        (ReportSection::Disclaimer, true),
+    ],
+};
+
+const DIARY_TEMPLATE: TemplateDefinition = TemplateDefinition {
+    name: "Vertigo Episode Diary",
+    file_stem: "vertigo-episode-diary",
+    max_pages: None,
+    sections: &[
+        (ReportSection::Summary, true),
+        (ReportSection::SeverityChart, false),
+        (ReportSection::MonthlyChart, false),
+        (ReportSection::TriggerChart, false),
+        (ReportSection::TimeOfDayChart, false),
+        (ReportSection::DurationChart, false),
+        (ReportSection::Triggers, false),
+        (ReportSection::Medications, false),
+        (ReportSection::Diary, true),
+    ],
+};
+
+const DISABILITY_TEMPLATE: TemplateDefinition = TemplateDefinition {
+    name: "Vertigo Work Absence and Disability Log",
+    file_stem: "vertigo-disability-log",
+    max_pages: None,
+    sections: &[
+        (ReportSection::FunctionalImpact, true),
+        (ReportSection::Summary, false),
+        (ReportSection::MonthlyChart, true),
+        (ReportSection::DurationChart, true),
+        (ReportSection::SeverityChart, false),
+        (ReportSection::TimeOfDayChart, false),
+        (ReportSection::Medications, true),
+        (ReportSection::AbsenceLog, true),
+        (ReportSection::Disclaimer, true),
+    ],
+};
+
+impl ReportTemplate {
+    pub const ALL: [ReportTemplate; 4] = [
+        ReportTemplate::Medical,
+        ReportTemplate::ClinicianSummary,
+        ReportTemplate::Diary,
+        ReportTemplate::Disability,
+    ];
+
+    pub fn definition(&self) -> &'static TemplateDefinition {
+        match self {
+            ReportTemplate::Medical => &MEDICAL_TEMPLATE,
+            ReportTemplate::ClinicianSummary => &CLINICIAN_SUMMARY_TEMPLATE,
+            ReportTemplate::Diary => &DIARY_TEMPLATE,
+            ReportTemplate::Disability => &DISABILITY_TEMPLATE,
+        }
+    }
+
+    /// The sections printed when the request toggles none.
+    pub fn default_sections(&self) -> Vec<ReportSection> {
+        self.definition().sections.iter()
+            .filter(|(_, on)| *on)
+            .map(|(section, _)| *section)
+            .collect()
+    }
+}
+
+impl ReportSection {
+    pub fn title(&self) -> &'static str {
+        match self {
+            ReportSection::Summary => "Summary statistics",
+            ReportSection::FunctionalImpact => "Functional impact",
+            ReportSection::SeverityChart => "Severity over time",
+            ReportSection::MonthlyChart => "Episodes per month",
+            ReportSection::TriggerChart => "Trigger frequency",
+            ReportSection::TimeOfDayChart => "Time of day",
+            ReportSection::DurationChart => "Episode duration",
+            ReportSection::Triggers => "Identified triggers",
+            ReportSection::Medications => "Medications",
+            ReportSection::MedicationEfficacy => "Medication efficacy",
+            ReportSection::SeverityPatterns => "Severity patterns",
+            ReportSection::TimePatterns => "Time patterns",
+            ReportSection::RiskFactors => "Risk factors",
+            ReportSection::Recommendations => "Recommendations",
+            ReportSection::RecentEpisodes => "Recent episodes",
+            ReportSection::Diary => "Episode diary",
+            ReportSection::AbsenceLog => "Absence log",
+            ReportSection::Disclaimer => "Medical disclaimer",
+            ReportSection::Appendix => "Episode log appendix",
+        }
+    }
+}
+
+/// Query string for `GET /api/report/pdf`, alongside the [`EpisodeFilter`].
+#[derive(Deserialize, Validate, Debug, Clone, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[validate(schema(function = "validate_report_sections"))]
+pub struct ReportQuery {
+    /// Defaults to medical
+    pub template: Option<ReportTemplate>,
+    /// Comma-separated section keys to print that the template leaves off by default
+    pub include: Option<String>,
+    /// Comma-separated section keys to leave out
+    pub exclude: Option<String>,
+}
+
+fn validate_report_sections(report: &ReportQuery) -> Result<(), ValidationError> {
+    let template = report.template();
+    for (field, keys) in [("include", report.include.as_deref()), ("exclude", report.exclude.as_deref())] {
+        template_sections(template, keys).map_err(|message| cross_field_error(field, "sections", message))?;
+    }
+    report.sections()
+        .map(|_| ())
+        .map_err(|message| cross_field_error("exclude", "sections", message))
+}
+
+impl ReportQuery {
+    pub fn template(&self) -> ReportTemplate {
+        self.template.unwrap_or(ReportTemplate::Medical)
+    }
+
+    /// The template's sections with the toggles applied, in template order.
+    pub fn sections(&self) -> Result<Vec<ReportSection>, String> {
+        let template = self.template();
+        let include = template_sections(template, self.include.as_deref())?;
+        let exclude = template_sections(template, self.exclude.as_deref())?;
+        if let Some(section) = include.iter().find(|s| exclude.contains(s)) {
+            return Err(format!("section both included and excluded: {}", section.as_str()));
+        }
+
+        Ok(template.definition().sections.iter()
+            .filter(|(section, on)| (*on || include.contains(section)) && !exclude.contains(section))
+            .map(|(section, _)| *section)
+            .collect())
+    }
+}
+
+/// The sections a comma-separated toggle list names, each of which the template must have.
+fn template_sections(template: ReportTemplate, keys: Option<&str>) -> Result<Vec<ReportSection>, String> {
+    let mut sections = Vec::new();
+    for key in keys.unwrap_or("").split(',').map(str::trim).filter(|k| !k.is_empty()) {
+        let section = template.definition().sections.iter()
+            .map(|(section, _)| *section)
+            .find(|s| s.as_str() == key)
+            .ok_or_else(|| format!("the {} template has no section: {}", template.as_str(), key))?;
+        if !sections.contains(&section) {
+            sections.push(section);
+        }
+    }
+    Ok(sections)
+}
+
+/// Query string for `POST /api/import`.
+#[derive(Deserialize, Validate, Debug, Clone, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportQuery {
+    /// Check every row and report problems without storing anything
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Store rows that look like episodes already logged instead of skipping them
+    #[serde(default)]
+    pub allow_duplicates: bool,
+    /// CSV only; defaults to comma, or tab for `text/tab-separated-values`
+    pub delimiter: Option<ExportDelimiter>,
+    /// CSV only: `field:Header` pairs, comma-separated, for headers that are not
+    /// our own export's, e.g. `timestamp:Date,severity:Pain level`
+    #[validate(custom(function = "validate_import_mapping"))]
+    pub mapping: Option<String>,
+}
+
+impl ImportQuery {
+    /// The `mapping` pairs as (column, header).
+    pub fn mapping(&self) -> Result<Vec<(ExportColumn, String)>, String> {
+        import_mapping(self.mapping.as_deref().unwrap_or(""))
+    }
+}
+
+fn import_mapping(text: &str) -> Result<Vec<(ExportColumn, String)>, String> {
+    let mut pairs: Vec<(ExportColumn, String)> = Vec::new();
+    for pair in text.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (key, header) = pair.split_once(':')
+            .ok_or_else(|| format!("entry must look like field:Header, got: {}", pair))?;
+        let column = ExportColumn::IMPORTABLE.into_iter()
+            .find(|c| c.as_str() == key.trim())
+            .ok_or_else(|| format!("names a field that cannot be imported: {}", key.trim()))?;
+        if pairs.iter().any(|(c, _)| *c == column) {
+            return Err(format!("lists {} twice", column.as_str()));
+        }
+        pairs.push((column, header.trim().to_string()));
+    }
+    Ok(pairs)
+}
+
+fn validate_import_mapping(text: &str) -> Result<(), ValidationError> {
+    import_mapping(text)
+        .map(|_| ())
+        .map_err(|message| ValidationError::new("mapping").with_message(message.into()))
+}
+
+/// A row an import could not take as it is. Rows are numbered from 1, not
+/// counting a CSV header.
+#[derive(Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct ImportProblem {
+    pub row: usize,
+    /// Why the row is invalid; empty for a duplicate
+    pub errors: Vec<FieldError>,
+    /// An existing episode at the same minute with the same severity
+    pub duplicate_of: Option<i32>,
+    /// An earlier row of this import at the same minute with the same severity
+    pub duplicate_of_row: Option<usize>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub total_rows: usize,
+    /// Includes duplicates
+    pub valid_rows: usize,
+    pub invalid_rows: usize,
+    pub duplicate_rows: usize,
+    /// Always 0 on a dry run
+    pub imported: usize,
+    /// CSV headers that map onto nothing importable
+    pub ignored_columns: Vec<String>,
+    pub problems: Vec<ImportProblem>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
 pub struct AnalyticsData {
     pub total_episodes: i64,
     pub average_severity: f32,
     pub severity_distribution: Vec<SeverityCount>,
     pub trigger_frequency: Vec<TriggerCount>,
+    pub symptom_frequency: Vec<SymptomCount>,
+    pub medication_usage: Vec<MedicationUsageStats>,
+    pub medication_efficacy: Vec<MedicationEfficacy>,
     pub monthly_trends: Vec<MonthlyTrend>,
     pub duration_stats: DurationStats,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, ToSchema)]
 pub struct SeverityCount {
     pub severity: i32,
     pub count: i64,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, ToSchema)]
 pub struct TriggerCount {
     pub trigger: String,
     pub count: i64,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, ToSchema)]
+pub struct SymptomCount {
+    pub symptom: String,
+    pub count: i64,
+    /// Share of episodes that have a checklist recorded, 0-100
+    pub percentage: f32,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct MedicationUsageStats {
+    pub medication_id: i32,
+    pub name: String,
+    pub drug_class: Option<String>,
+    pub strength: Option<String>,
+    pub usage: MedicationUsage,
+    pub intake_count: i64,
+    pub average_effect: Option<f32>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
 pub struct MonthlyTrend {
     pub month: String,
     pub episode_count: i64,
     pub average_severity: f32,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, ToSchema)]
 pub struct DurationStats {
     pub average_minutes: f32,
     pub median_minutes: i32,
@@ -97,7 +1615,7 @@ pub struct DurationStats {
     pub min_minutes: i32,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, ToSchema)]
 pub struct PatternAnalysis {
     pub common_triggers: Vec<String>,
     pub severity_patterns: Vec<String>,
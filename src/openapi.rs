@@ -0,0 +1,224 @@
+use axum::http::header;
+use axum::response::{Html, IntoResponse, Json};
+use utoipa::openapi::{Content, ObjectBuilder, PathItemType, SchemaType};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::auth::SESSION_COOKIE;
+use crate::error::{AppError, ErrorResponse, FieldError};
+use crate::extract::Path;
+use crate::handlers;
+use crate::models::*;
+use crate::trigger_vocabulary::MatchRule;
+
+/// The API description, assembled from the `#[utoipa::path]` annotations on
+/// the handlers. Every route registered in `main.rs` must be listed here.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Vertigo Logger API",
+        description = "Log vertigo episodes, track medications and triggers, and share reports with clinicians.",
+    ),
+    paths(
+        handlers::health_check,
+        handlers::register,
+        handlers::login,
+        handlers::logout,
+        handlers::current_user,
+        handlers::get_episodes,
+        handlers::create_episode,
+        handlers::search_episodes,
+        handlers::get_episode,
+        handlers::update_episode,
+        handlers::delete_episode,
+        handlers::get_episode_history,
+        handlers::list_intakes,
+        handlers::create_intake,
+        handlers::update_intake,
+        handlers::delete_intake,
+        handlers::list_medications,
+        handlers::create_medication,
+        handlers::get_medication,
+        handlers::update_medication,
+        handlers::delete_medication,
+        handlers::list_courses,
+        handlers::create_course,
+        handlers::delete_course,
+        handlers::list_triggers,
+        handlers::create_vocabulary_term,
+        handlers::list_synonyms,
+        handlers::create_synonym,
+        handlers::delete_synonym,
+        handlers::preview_canonicalization,
+        handlers::apply_canonicalization,
+        handlers::merge_triggers,
+        handlers::rename_trigger,
+        handlers::analyze_episode,
+        handlers::export_episodes,
+        handlers::export_fhir,
+        handlers::export_ics,
+        handlers::import_episodes,
+        handlers::get_analytics,
+        handlers::get_medication_efficacy,
+        handlers::get_patterns,
+        handlers::generate_pdf_report,
+        handlers::list_shares,
+        handlers::create_share,
+        handlers::revoke_share,
+        handlers::get_share_access_log,
+        handlers::share_view,
+        handlers::share_unlock,
+        handlers::share_analytics,
+        handlers::share_report,
+        handlers::list_calendar_feeds,
+        handlers::create_calendar_feed,
+        handlers::delete_calendar_feed,
+        handlers::calendar_feed,
+        openapi_json,
+        api_explorer,
+        explorer_asset,
+    ),
+    components(schemas(
+        ErrorResponse, FieldError,
+        VertigoType, HearingLossSide, NystagmusDirection, MedicationUsage, EpisodeSort, SortOrder, ExportDelimiter, ExportColumn, ReportTemplate, ReportSection, ShareResource, ShareOutcome,
+        SymptomChecklist, Episode, NewEpisode, EpisodeUpdate, EpisodePage, EpisodeSearchHit, EpisodeRevision,
+        ImportReport, ImportProblem,
+        AnalysisRequest, AnalysisResponse,
+        Trigger, TriggerSummary, MatchRule, NewVocabularyTerm, TriggerSynonym, NewTriggerSynonym, TriggerRemap, TriggerRename, TriggerMerge,
+        Medication, NewMedication, MedicationUpdate, MedicationIntake, IntakeRequest, IntakeUpdate, MedicationCourse, CourseRequest,
+        WindowStats, RateComparison, MeanComparison, MedicationEfficacy,
+        User, Credentials, SessionResponse,
+        ShareLink, ShareRequest, CreatedShare, ShareAccess, ShareUnlock,
+        CalendarFeed, CalendarFeedRequest, CreatedCalendarFeed,
+        AnalyticsData, SeverityCount, TriggerCount, SymptomCount, MedicationUsageStats, MonthlyTrend, DurationStats, PatternAnalysis,
+    )),
+    modifiers(&SessionAuth, &CsvImport),
+    security(("bearer" = []), ("session_cookie" = [])),
+    tags(
+        (name = "auth", description = "Accounts and sessions"),
+        (name = "episodes", description = "Logging, listing and searching episodes"),
+        (name = "triggers", description = "Trigger vocabulary, synonyms and clean-up"),
+        (name = "medications", description = "Medications, courses and per-episode intakes"),
+        (name = "analysis", description = "Analytics, AI analysis and reports"),
+        (name = "shares", description = "Managing clinician share links"),
+        (name = "shared", description = "What a share link opens; no account needed"),
+        (name = "calendar", description = "iCalendar export and subscribable calendar feeds"),
+        (name = "system", description = "Health and API description"),
+    ),
+)]
+pub struct ApiDoc;
+
+/// Sessions are accepted as a bearer token or as the cookie set on login.
+struct SessionAuth;
+
+impl Modify for SessionAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("bearer", SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()));
+        components.add_security_scheme("session_cookie", SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new(SESSION_COOKIE))));
+    }
+}
+
+/// `#[utoipa::path]` takes one request content type; the import also accepts CSV.
+struct CsvImport;
+
+impl Modify for CsvImport {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let body = openapi.paths.paths.get_mut("/api/import")
+            .and_then(|item| item.operations.get_mut(&PathItemType::Post))
+            .and_then(|operation| operation.request_body.as_mut());
+
+        if let Some(body) = body {
+            let csv = Content::new(ObjectBuilder::new().schema_type(SchemaType::String).description(Some(
+                "Header row first. Our export's headers and column keys are recognised; map others with `mapping`.",
+            )));
+            body.content.insert("text/csv".to_string(), csv);
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/openapi.json",
+    tag = "system",
+    responses(
+        (status = 200, description = "This OpenAPI document", body = Object),
+    ),
+    security(()),
+)]
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/docs",
+    tag = "system",
+    responses(
+        (status = 200, description = "Interactive API explorer", body = String, content_type = "text/html"),
+    ),
+    security(()),
+)]
+pub async fn api_explorer() -> impl IntoResponse {
+    ([(header::CONTENT_SECURITY_POLICY, EXPLORER_CSP)], Html(EXPLORER_HTML))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/docs/{file}",
+    tag = "system",
+    params(("file" = String, Path, description = "swagger-ui-bundle.js, swagger-ui.css or explorer.js")),
+    responses(
+        (status = 200, description = "A script or stylesheet of the API explorer", body = String),
+        (status = 404, description = "No such file", body = ErrorResponse),
+    ),
+    security(()),
+)]
+pub async fn explorer_asset(Path(file): Path<String>) -> Result<impl IntoResponse, AppError> {
+    let (content_type, body) = match file.as_str() {
+        "swagger-ui-bundle.js" => ("text/javascript; charset=utf-8", SWAGGER_UI_BUNDLE),
+        "swagger-ui.css" => ("text/css; charset=utf-8", SWAGGER_UI_CSS),
+        "explorer.js" => ("text/javascript; charset=utf-8", EXPLORER_JS),
+        _ => return Err(AppError::NotFound(format!("no explorer file named {}", file))),
+    };
+
+    Ok(([(header::CONTENT_TYPE, content_type), (header::CACHE_CONTROL, "public, max-age=86400")], body))
+}
+
+/// Swagger UI 5.17.14 from swagger-ui-dist (Apache-2.0, see
+/// `assets/swagger-ui/LICENSE`), built into the binary so the explorer works
+/// offline and no third-party script runs next to the session cookie.
+const SWAGGER_UI_BUNDLE: &str = include_str!("../assets/swagger-ui/swagger-ui-bundle.js");
+const SWAGGER_UI_CSS: &str = include_str!("../assets/swagger-ui/swagger-ui.css");
+
+/// Only the explorer's own files may run. Swagger UI sets inline styles and
+/// draws some icons as data: URIs.
+const EXPLORER_CSP: &str = "default-src 'self'; script-src 'self'; style-src 'self' 'unsafe-inline'; \
+    img-src 'self' data:; connect-src 'self'; object-src 'none'; base-uri 'none'; frame-ancestors 'none'";
+
+/// Swagger UI pointed at `/api/openapi.json`. "Try it out" sends the session
+/// cookie, so a signed-in browser can call the API directly.
+const EXPLORER_JS: &str = r##"window.ui = SwaggerUIBundle({
+    url: "/api/openapi.json",
+    dom_id: "#explorer",
+    deepLinking: true,
+    persistAuthorization: true,
+    withCredentials: true,
+});
+"##;
+
+const EXPLORER_HTML: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <title>Vertigo Logger API</title>
+    <link rel="stylesheet" href="/api/docs/swagger-ui.css">
+</head>
+<body>
+    <div id="explorer"></div>
+    <script src="/api/docs/swagger-ui-bundle.js"></script>
+    <script src="/api/docs/explorer.js"></script>
+</body>
+</html>
+"##;
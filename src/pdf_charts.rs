@@ -0,0 +1,300 @@
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, Months, NaiveDate, NaiveDateTime, Timelike};
+
+use crate::models::{Episode, MonthlyTrend, TriggerCount};
+use crate::pdf_layout::{Align, FontStyle, Layout, Rgb, CONTENT_WIDTH, MARGIN_LEFT};
+use crate::pdf_text::wrap_limited;
+
+const TITLE_SIZE: f32 = 11.0;
+const LABEL_SIZE: f32 = 7.0;
+/// Above the plot for its title, below it for the x axis labels.
+const TITLE_SPACE: f32 = 9.0;
+const AXIS_SPACE: f32 = 8.0;
+/// Left of the plot for y axis labels.
+const LABEL_MARGIN: f32 = 10.0;
+const PLOT_HEIGHT: f32 = 40.0;
+const CHART_GAP: f32 = 8.0;
+
+const INK: Rgb = Rgb(0.25, 0.25, 0.25);
+const GRID: Rgb = Rgb(0.85, 0.85, 0.85);
+const BAR: Rgb = Rgb(0.20, 0.45, 0.70);
+const HEAT: Rgb = Rgb(0.75, 0.10, 0.10);
+const EMPTY_CELL: Rgb = Rgb(0.96, 0.96, 0.96);
+
+const TOP_TRIGGERS: usize = 10;
+const TRIGGER_ROW: f32 = 5.0;
+const TRIGGER_NAME_MARGIN: f32 = 40.0;
+
+const HEATMAP_ROW: f32 = 5.0;
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+/// Upper limit of each duration bin in minutes, exclusive. Roughly separates
+/// the seconds-long spells of BPPV from the hours-long attacks of Meniere's
+/// disease and the days-long vertigo of vestibular neuritis.
+const DURATION_BINS: [(i32, &str); 8] = [
+    (5, "< 5 min"),
+    (15, "5-15 min"),
+    (30, "15-30 min"),
+    (60, "30-60 min"),
+    (120, "1-2 h"),
+    (360, "2-6 h"),
+    (1440, "6-24 h"),
+    (i32::MAX, "24 h +"),
+];
+
+/// The area inside a chart's axes, in page millimetres.
+struct Plot {
+    left: f32,
+    bottom: f32,
+    width: f32,
+    height: f32,
+}
+
+impl Plot {
+    fn x(&self, fraction: f32) -> f32 {
+        self.left + self.width * fraction
+    }
+
+    fn y(&self, fraction: f32) -> f32 {
+        self.bottom + self.height * fraction
+    }
+
+    fn right(&self) -> f32 {
+        self.left + self.width
+    }
+
+    fn top(&self) -> f32 {
+        self.bottom + self.height
+    }
+
+    /// Baseline of the chart title.
+    fn title_y(&self) -> f32 {
+        self.top() + TITLE_SPACE - 4.0
+    }
+
+    /// Baseline of the labels under the x axis.
+    fn axis_label_y(&self) -> f32 {
+        self.bottom - 4.0
+    }
+}
+
+/// Each episode's severity, in time order.
+pub fn severity_over_time(layout: &mut Layout, episodes: &[Episode]) {
+    let mut points: Vec<(NaiveDateTime, i32)> = episodes.iter().map(|e| (e.timestamp, e.severity)).collect();
+    points.sort();
+    let (Some(&(first, _)), Some(&(last, _))) = (points.first(), points.last()) else {
+        return;
+    };
+
+    let plot = start(layout, "SEVERITY OVER TIME", LABEL_MARGIN, PLOT_HEIGHT);
+    for severity in 1..=5 {
+        let y = plot.y(severity as f32 / 5.0);
+        layout.path(vec![(plot.left, y), (plot.right(), y)], 0.2, GRID);
+        layout.label(&severity.to_string(), plot.left - 1.5, y - 1.0, LABEL_SIZE, FontStyle::Regular, Align::Right);
+    }
+
+    let span = (last - first).num_seconds() as f32;
+    let x = |timestamp: NaiveDateTime| {
+        if span > 0.0 { plot.x((timestamp - first).num_seconds() as f32 / span) } else { plot.x(0.5) }
+    };
+    let line: Vec<(f32, f32)> = points.iter().map(|&(t, severity)| (x(t), plot.y(severity as f32 / 5.0))).collect();
+    if line.len() > 1 {
+        layout.path(line.clone(), 0.4, BAR);
+    }
+    for (x, y) in line {
+        layout.rect(x - 0.6, y - 0.6, 1.2, 1.2, BAR);
+    }
+
+    let date = |timestamp: NaiveDateTime| timestamp.format("%d %b %Y").to_string();
+    if span > 0.0 {
+        let middle = first + (last - first) / 2;
+        layout.label(&date(first), plot.left, plot.axis_label_y(), LABEL_SIZE, FontStyle::Regular, Align::Left);
+        layout.label(&date(middle), plot.x(0.5), plot.axis_label_y(), LABEL_SIZE, FontStyle::Regular, Align::Center);
+        layout.label(&date(last), plot.right(), plot.axis_label_y(), LABEL_SIZE, FontStyle::Regular, Align::Right);
+    } else {
+        layout.label(&date(first), plot.x(0.5), plot.axis_label_y(), LABEL_SIZE, FontStyle::Regular, Align::Center);
+    }
+
+    axes(layout, &plot);
+    layout.gap(CHART_GAP);
+}
+
+/// Episode counts for every month from the first to the last, including
+/// months without episodes, which `monthly_trends` leaves out.
+pub fn monthly_episodes(layout: &mut Layout, trends: &[MonthlyTrend]) {
+    let counts: BTreeMap<NaiveDate, i64> = trends.iter()
+        .filter_map(|t| {
+            let month = NaiveDate::parse_from_str(&format!("{}-01", t.month), "%Y-%m-%d").ok()?;
+            Some((month, t.episode_count))
+        })
+        .collect();
+    let (Some(&first), Some(&last)) = (counts.keys().next(), counts.keys().next_back()) else {
+        return;
+    };
+
+    let mut values = Vec::new();
+    let mut month = Some(first);
+    while let Some(current) = month.filter(|m| *m <= last) {
+        values.push((current.format("%b %y").to_string(), counts.get(&current).copied().unwrap_or(0)));
+        month = current.checked_add_months(Months::new(1));
+    }
+
+    let plot = start(layout, "EPISODES PER MONTH", LABEL_MARGIN, PLOT_HEIGHT);
+    bars(layout, &plot, &values);
+    layout.gap(CHART_GAP);
+}
+
+/// The most frequent triggers as horizontal bars, most frequent first.
+pub fn trigger_frequency(layout: &mut Layout, triggers: &[TriggerCount]) {
+    let shown: Vec<&TriggerCount> = triggers.iter().filter(|t| t.count > 0).take(TOP_TRIGGERS).collect();
+    let Some(max) = shown.iter().map(|t| t.count).max() else {
+        return;
+    };
+
+    let title = if triggers.len() > TOP_TRIGGERS {
+        format!("TRIGGER FREQUENCY (TOP {})", TOP_TRIGGERS)
+    } else {
+        "TRIGGER FREQUENCY".to_string()
+    };
+    let mut plot = start(layout, &title, TRIGGER_NAME_MARGIN, TRIGGER_ROW * shown.len() as f32);
+    // Leave room for the count after the longest bar
+    plot.width -= 8.0;
+
+    let (step, axis_max) = ticks(max);
+    for value in (0..=axis_max).step_by(step as usize) {
+        let x = plot.x(value as f32 / axis_max as f32);
+        layout.path(vec![(x, plot.bottom), (x, plot.top())], 0.2, GRID);
+        layout.label(&value.to_string(), x, plot.axis_label_y(), LABEL_SIZE, FontStyle::Regular, Align::Center);
+    }
+
+    for (index, trigger) in shown.iter().enumerate() {
+        let y = plot.top() - TRIGGER_ROW * (index + 1) as f32;
+        let length = plot.width * trigger.count as f32 / axis_max as f32;
+        layout.rect(plot.left, y + 1.0, length, TRIGGER_ROW - 2.0, BAR);
+
+        let name = wrap_limited(&trigger.trigger, TRIGGER_NAME_MARGIN - 3.0, 8.0, FontStyle::Regular, 1);
+        if let Some(name) = name.first() {
+            layout.label(name, plot.left - 2.0, y + 1.6, 8.0, FontStyle::Regular, Align::Right);
+        }
+        layout.label(&trigger.count.to_string(), plot.left + length + 1.0, y + 1.6, LABEL_SIZE, FontStyle::Regular, Align::Left);
+    }
+
+    axes(layout, &plot);
+    layout.gap(CHART_GAP);
+}
+
+/// A weekday by hour-of-day grid, shaded by how many episodes started in each hour.
+pub fn time_of_day(layout: &mut Layout, episodes: &[Episode]) {
+    if episodes.is_empty() {
+        return;
+    }
+
+    let mut counts = [[0i64; 24]; 7];
+    for episode in episodes {
+        let day = episode.timestamp.weekday().num_days_from_monday() as usize;
+        counts[day][episode.timestamp.hour() as usize] += 1;
+    }
+    let busiest = counts.iter().flatten().copied().max().unwrap_or(0).max(1);
+
+    // Timestamps are stored as naive UTC
+    let plot = start(layout, "EPISODES BY WEEKDAY AND HOUR (UTC)", LABEL_MARGIN, HEATMAP_ROW * 7.0);
+    let cell_width = plot.width / 24.0;
+    layout.label(
+        &format!("Darker cells had more episodes, up to {}", busiest),
+        plot.right(), plot.title_y(), LABEL_SIZE, FontStyle::Regular, Align::Right,
+    );
+
+    for (day, hours) in counts.iter().enumerate() {
+        let y = plot.top() - HEATMAP_ROW * (day + 1) as f32;
+        for (hour, &count) in hours.iter().enumerate() {
+            let fill = if count == 0 { EMPTY_CELL } else { Rgb::WHITE.mix(HEAT, 0.15 + 0.85 * count as f32 / busiest as f32) };
+            layout.rect(plot.left + cell_width * hour as f32 + 0.2, y + 0.2, cell_width - 0.4, HEATMAP_ROW - 0.4, fill);
+        }
+        layout.label(WEEKDAYS[day], plot.left - 1.5, y + 1.6, LABEL_SIZE, FontStyle::Regular, Align::Right);
+    }
+    for hour in (0..24).step_by(3) {
+        layout.label(&format!("{:02}:00", hour), plot.left + cell_width * hour as f32, plot.axis_label_y(), LABEL_SIZE, FontStyle::Regular, Align::Left);
+    }
+
+    layout.gap(CHART_GAP);
+}
+
+/// How long episodes lasted, in bins from minutes to days.
+pub fn duration_histogram(layout: &mut Layout, episodes: &[Episode]) {
+    let durations: Vec<i32> = episodes.iter().filter_map(|e| e.duration_minutes).collect();
+    if durations.is_empty() {
+        return;
+    }
+
+    let mut values: Vec<(String, i64)> = DURATION_BINS.iter().map(|&(_, label)| (label.to_string(), 0)).collect();
+    for minutes in &durations {
+        let bin = DURATION_BINS.iter().position(|&(limit, _)| *minutes < limit).unwrap_or(DURATION_BINS.len() - 1);
+        values[bin].1 += 1;
+    }
+
+    let plot = start(layout, "EPISODE DURATION", LABEL_MARGIN, PLOT_HEIGHT);
+    bars(layout, &plot, &values);
+
+    let missing = episodes.len() - durations.len();
+    if missing > 0 {
+        layout.text(format!("{} of {} episodes had no duration recorded.", missing, episodes.len()), 9.0, FontStyle::Regular, 0.0);
+    }
+    layout.gap(CHART_GAP);
+}
+
+/// Reserves room for a titled chart, on a new page if it does not fit, and returns its plot area.
+fn start(layout: &mut Layout, title: &str, label_margin: f32, plot_height: f32) -> Plot {
+    let bottom = layout.reserve(TITLE_SPACE + plot_height + AXIS_SPACE);
+    let plot = Plot {
+        left: MARGIN_LEFT + label_margin,
+        bottom: bottom + AXIS_SPACE,
+        width: CONTENT_WIDTH - label_margin,
+        height: plot_height,
+    };
+    layout.label(title, MARGIN_LEFT, plot.title_y(), TITLE_SIZE, FontStyle::Bold, Align::Left);
+    plot
+}
+
+/// One vertical bar per value on a count axis, labelling at most twelve of them.
+fn bars(layout: &mut Layout, plot: &Plot, values: &[(String, i64)]) {
+    let max = values.iter().map(|(_, count)| *count).max().unwrap_or(0);
+    let (step, axis_max) = ticks(max);
+    for value in (0..=axis_max).step_by(step as usize) {
+        let y = plot.y(value as f32 / axis_max as f32);
+        layout.path(vec![(plot.left, y), (plot.right(), y)], 0.2, GRID);
+        layout.label(&value.to_string(), plot.left - 1.5, y - 1.0, LABEL_SIZE, FontStyle::Regular, Align::Right);
+    }
+
+    let slot = plot.width / values.len() as f32;
+    let label_every = values.len().div_ceil(12);
+    for (index, (label, count)) in values.iter().enumerate() {
+        let x = plot.left + slot * index as f32;
+        if *count > 0 {
+            layout.rect(x + slot * 0.15, plot.bottom, slot * 0.7, plot.height * *count as f32 / axis_max as f32, BAR);
+        }
+        if index % label_every == 0 {
+            layout.label(label, x + slot / 2.0, plot.axis_label_y(), LABEL_SIZE, FontStyle::Regular, Align::Center);
+        }
+    }
+
+    axes(layout, plot);
+}
+
+fn axes(layout: &mut Layout, plot: &Plot) {
+    layout.path(vec![(plot.left, plot.top()), (plot.left, plot.bottom), (plot.right(), plot.bottom)], 0.4, INK);
+}
+
+/// A round step (1, 2 or 5 times a power of ten) giving at most five steps,
+/// and the axis maximum: the first multiple of the step at or above `max`.
+fn ticks(max: i64) -> (i64, i64) {
+    let mut magnitude = 1;
+    loop {
+        for step in [magnitude, 2 * magnitude, 5 * magnitude] {
+            if max <= step * 5 {
+                return (step, (max.max(1) + step - 1) / step * step);
+            }
+        }
+        magnitude *= 10;
+    }
+}
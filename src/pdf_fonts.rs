@@ -0,0 +1,424 @@
+use printpdf::lopdf::{self, Dictionary, Document, Object, ObjectId, Stream};
+use printpdf::{FontData, FontMetrics, GlyphMetrics, IndirectFontRef, PdfDocumentReference};
+use rustybuzz::ttf_parser::GlyphId;
+use rustybuzz::{Face, UnicodeBuffer};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+use unicode_script::{Script, UnicodeScript};
+
+use crate::error::AppError;
+use crate::pdf_layout::FontStyle;
+
+/// The fonts bundled into the binary, in the order of `FontId`. Licences are
+/// next to the files in assets/fonts.
+const FONTS: [(&str, &[u8]); 4] = [
+    ("NotoSans-Regular", include_bytes!("../assets/fonts/NotoSans-Regular.ttf")),
+    ("NotoSans-Bold", include_bytes!("../assets/fonts/NotoSans-Bold.ttf")),
+    ("NotoEmoji-Regular", include_bytes!("../assets/fonts/NotoEmoji-Regular.ttf")),
+    ("DejaVuSans", include_bytes!("../assets/fonts/DejaVuSans.ttf")),
+];
+
+const NOTO_SANS: FontId = FontId(0);
+const NOTO_SANS_BOLD: FontId = FontId(1);
+const NOTO_EMOJI: FontId = FontId(2);
+const DEJAVU_SANS: FontId = FontId(3);
+
+/// Fonts tried in turn for each character. Noto Sans covers Latin, Greek,
+/// Cyrillic and Devanagari; the bold cut has no Devanagari, so bold text in
+/// that script falls back to the regular weight. DejaVu Sans picks up symbols
+/// such as arrows and check marks that neither Noto font has.
+const REGULAR_CHAIN: [FontId; 3] = [NOTO_SANS, NOTO_EMOJI, DEJAVU_SANS];
+const BOLD_CHAIN: [FontId; 4] = [NOTO_SANS_BOLD, NOTO_SANS, NOTO_EMOJI, DEJAVU_SANS];
+
+/// One of the bundled fonts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FontId(usize);
+
+impl FontId {
+    /// PostScript name, used for the embedded font.
+    pub fn name(self) -> &'static str {
+        FONTS[self.0].0
+    }
+
+    /// The whole font file.
+    pub fn data(self) -> &'static [u8] {
+        FONTS[self.0].1
+    }
+
+    /// Parsed once, on first use.
+    pub fn face(self) -> &'static Face<'static> {
+        &faces()[self.0]
+    }
+}
+
+fn faces() -> &'static [Face<'static>] {
+    static FACES: OnceLock<Vec<Face<'static>>> = OnceLock::new();
+    FACES.get_or_init(|| {
+        FONTS.iter()
+            .map(|(name, data)| Face::from_slice(data, 0).unwrap_or_else(|| panic!("bundled font {} is invalid", name)))
+            .collect()
+    })
+}
+
+/// A glyph placed by the shaper. Positions are in em, `x` from the start of
+/// the run and `y` above the baseline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glyph {
+    pub id: u16,
+    pub x: f32,
+    pub y: f32,
+    /// The text this glyph stands for when the PDF is copied from or
+    /// searched. Empty for glyphs whose text was given to another glyph of
+    /// the same cluster.
+    pub text: String,
+}
+
+/// A stretch of text set in one font, glyphs in visual order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    pub font: FontId,
+    pub glyphs: Vec<Glyph>,
+    /// Advance of the whole run, in em.
+    pub width: f32,
+}
+
+/// Width of `text` in em.
+pub fn width(text: &str, style: FontStyle) -> f32 {
+    shape(text, style).iter().map(|run| run.width).sum()
+}
+
+/// Splits `text` into runs by font and script and shapes each one, so
+/// ligatures, conjuncts and combining marks come out as the font intends.
+pub fn shape(text: &str, style: FontStyle) -> Vec<Run> {
+    let chain: &[FontId] = match style {
+        FontStyle::Regular => &REGULAR_CHAIN,
+        FontStyle::Bold => &BOLD_CHAIN,
+    };
+
+    segments(text, chain)
+        .into_iter()
+        .map(|(font, range)| shape_run(font, &text[range]))
+        .collect()
+}
+
+/// Byte ranges of `text` that share a font and a script. Combining marks,
+/// joiners and variation selectors stay with the character before them, as
+/// do spaces when that font has them.
+fn segments(text: &str, chain: &[FontId]) -> Vec<(FontId, std::ops::Range<usize>)> {
+    let mut segments: Vec<(FontId, Script, std::ops::Range<usize>)> = Vec::new();
+
+    for (at, c) in text.char_indices() {
+        let script = c.script();
+        let end = at + c.len_utf8();
+
+        if let Some((font, run_script, range)) = segments.last_mut() {
+            let inherits = script == Script::Inherited
+                || (c.is_whitespace() && font.face().glyph_index(c).is_some());
+            let same_script = matches!(script, Script::Common | Script::Inherited | Script::Unknown)
+                || *run_script == script
+                || *run_script == Script::Common;
+
+            if inherits || (same_script && covering_font(c, chain) == *font) {
+                if *run_script == Script::Common && !inherits {
+                    *run_script = script;
+                }
+                range.end = end;
+                continue;
+            }
+        }
+
+        segments.push((covering_font(c, chain), script, at..end));
+    }
+
+    segments.into_iter().map(|(font, _, range)| (font, range)).collect()
+}
+
+/// The first font of the chain with a glyph for `c`, or the first font, whose
+/// missing-glyph box then marks the character.
+fn covering_font(c: char, chain: &[FontId]) -> FontId {
+    chain.iter()
+        .copied()
+        .find(|font| font.face().glyph_index(c).is_some())
+        .unwrap_or(chain[0])
+}
+
+fn shape_run(font: FontId, text: &str) -> Run {
+    let face = font.face();
+    let mut buffer = UnicodeBuffer::new();
+    buffer.push_str(text);
+    buffer.guess_segment_properties();
+    let shaped = rustybuzz::shape(face, &[], buffer);
+
+    let units = face.units_per_em() as f32;
+    let infos = shaped.glyph_infos();
+    let positions = shaped.glyph_positions();
+
+    let mut glyphs = Vec::with_capacity(infos.len());
+    let mut pen = 0;
+    for (info, position) in infos.iter().zip(positions) {
+        glyphs.push(Glyph {
+            id: info.glyph_id as u16,
+            x: (pen + position.x_offset) as f32 / units,
+            y: position.y_offset as f32 / units,
+            text: String::new(),
+        });
+        pen += position.x_advance;
+    }
+
+    let clusters: Vec<usize> = infos.iter().map(|info| info.cluster as usize).collect();
+    assign_text(face, text, &clusters, &mut glyphs);
+
+    Run { font, glyphs, width: pen as f32 / units }
+}
+
+/// Gives each glyph the text it stands for. Where the glyphs of a cluster map
+/// one to one onto its characters in order, each glyph gets its own
+/// character; otherwise (ligatures, conjuncts, reordered vowel signs) the
+/// first glyph gets the whole cluster so the text still reads in order.
+fn assign_text(face: &Face, text: &str, clusters: &[usize], glyphs: &mut [Glyph]) {
+    let mut starts: Vec<usize> = clusters.to_vec();
+    starts.sort_unstable();
+    starts.dedup();
+
+    let mut i = 0;
+    while i < glyphs.len() {
+        let cluster = clusters[i];
+        let count = clusters[i..].iter().take_while(|&&c| c == cluster).count();
+        let end = starts.iter().copied().find(|&s| s > cluster).unwrap_or(text.len());
+        let cluster_text = &text[cluster..end];
+
+        let chars: Vec<char> = cluster_text.chars().collect();
+        let one_to_one = chars.len() == count
+            && chars.iter().zip(&glyphs[i..i + count]).all(|(&c, glyph)| face.glyph_index(c).map(|g| g.0) == Some(glyph.id));
+
+        for (k, glyph) in glyphs[i..i + count].iter_mut().enumerate() {
+            glyph.text = if one_to_one {
+                chars[k].to_string()
+            } else if k == 0 {
+                cluster_text.to_string()
+            } else {
+                String::new()
+            };
+        }
+        i += count;
+    }
+}
+
+/// Advance of a glyph in 1/1000 em, truncated the way printpdf writes it
+/// into the font's width table, so positioned text lines up with what a
+/// viewer computes.
+pub fn advance(font: FontId, glyph: u16) -> i64 {
+    let face = font.face();
+    let width = face.glyph_hor_advance(GlyphId(glyph)).unwrap_or(0);
+    (f32::from(width) * (1000.0 / face.units_per_em() as f32)) as i64
+}
+
+/// The glyphs a document draws, per font, numbered as the character codes
+/// written into the PDF. A glyph gets a code for each text it stands for, so
+/// one shared by several clusters still copies out as the right characters.
+#[derive(Debug, Default)]
+pub struct FontSubsets {
+    fonts: BTreeMap<FontId, Subset>,
+}
+
+#[derive(Debug)]
+struct Subset {
+    codes: HashMap<(u16, String), u16>,
+    /// Glyph id and text for each code
+    entries: Vec<(u16, String)>,
+}
+
+impl FontSubsets {
+    /// Records the glyphs of `run` and returns their character codes.
+    pub fn add(&mut self, run: &Run) -> Vec<u16> {
+        let subset = self.fonts.entry(run.font).or_insert_with(Subset::new);
+        run.glyphs.iter()
+            .map(|glyph| {
+                let key = (glyph.id, glyph.text.clone());
+                if let Some(&code) = subset.codes.get(&key) {
+                    return code;
+                }
+                let code = subset.entries.len() as u16;
+                subset.entries.push(key.clone());
+                subset.codes.insert(key, code);
+                code
+            })
+            .collect()
+    }
+
+    /// Cuts each font down to the glyphs it draws and adds it to `doc`.
+    /// They must be the only fonts in the document, as `finish` finds them
+    /// by the order they were added in.
+    pub fn embed(&self, doc: &PdfDocumentReference) -> Result<BTreeMap<FontId, IndirectFontRef>, AppError> {
+        let mut refs = BTreeMap::new();
+        for (&font, subset) in &self.fonts {
+            let mut glyphs: Vec<u16> = subset.entries.iter().map(|(glyph, _)| *glyph).collect();
+            glyphs.sort_unstable();
+            glyphs.dedup();
+
+            let bytes = subsetter::subset(font.data(), 0, subsetter::Profile::pdf(&glyphs))
+                .map_err(|e| AppError::Internal(format!("subsetting {} failed: {}", font.name(), e)))?;
+            let data = SubsetData { font, glyphs: subset.entries.iter().map(|(glyph, _)| *glyph).collect() };
+            refs.insert(font, doc.add_external_font_data(bytes, data)?);
+        }
+        Ok(refs)
+    }
+
+    /// Completes the font dictionaries printpdf wrote for `pdf`: character
+    /// codes are mapped to glyphs and to the text they stand for (printpdf
+    /// assumes codes are glyph ids with one character each), metrics are
+    /// scaled to the 1000 units PDF expects, and names get the subset tag.
+    pub fn finish(&self, pdf: &[u8]) -> Result<Vec<u8>, AppError> {
+        let mut doc = Document::load_mem(pdf).map_err(failed)?;
+        let subsets: Vec<(FontId, &Subset)> = self.fonts.iter().map(|(&font, subset)| (font, subset)).collect();
+
+        let type0: Vec<(ObjectId, usize)> = doc.objects.iter()
+            .filter_map(|(&id, object)| {
+                let dict = object.as_dict().ok()?;
+                if dict.get(b"Subtype").and_then(Object::as_name).ok()? != b"Type0" {
+                    return None;
+                }
+                let name = dict.get(b"BaseFont").and_then(Object::as_name_str).ok()?;
+                Some((id, name.strip_prefix('F')?.parse().ok()?))
+            })
+            .collect();
+
+        for (id, index) in type0 {
+            let Some(&(font, subset)) = subsets.get(index) else { continue };
+            let face = font.face();
+            let scale = 1000.0 / face.units_per_em() as f32;
+            let scaled = |units: i16| Object::Integer((f32::from(units) * scale).round() as i64);
+            let name = Object::Name(format!("{}+{}", subset.tag(), font.name()).into_bytes());
+
+            let to_unicode = doc.add_object(compressed(subset.to_unicode()).map_err(failed)?);
+            let cid_to_gid: Vec<u8> = subset.entries.iter().flat_map(|(glyph, _)| glyph.to_be_bytes()).collect();
+            let cid_to_gid = doc.add_object(compressed(cid_to_gid).map_err(failed)?);
+
+            let dict = doc.get_object_mut(id).and_then(Object::as_dict_mut).map_err(failed)?;
+            dict.set("BaseFont", name.clone());
+            dict.set("ToUnicode", Object::Reference(to_unicode));
+            let descendant = dict.get_mut(b"DescendantFonts")
+                .and_then(Object::as_array_mut)
+                .map_err(failed)?
+                .get_mut(0)
+                .ok_or_else(|| failed("no descendant font"))?
+                .as_dict_mut()
+                .map_err(failed)?;
+            descendant.set("BaseFont", name.clone());
+            descendant.set("CIDToGIDMap", Object::Reference(cid_to_gid));
+            let descriptor = descendant.get(b"FontDescriptor").and_then(Object::as_reference).map_err(failed)?;
+
+            let bbox = face.global_bounding_box();
+            let descriptor = doc.get_object_mut(descriptor).and_then(Object::as_dict_mut).map_err(failed)?;
+            descriptor.set("FontName", name);
+            descriptor.set("Ascent", scaled(face.ascender()));
+            descriptor.set("Descent", scaled(face.descender()));
+            descriptor.set("CapHeight", scaled(face.capital_height().unwrap_or(face.ascender())));
+            descriptor.set("FontBBox", vec![scaled(bbox.x_min), scaled(bbox.y_min), scaled(bbox.x_max), scaled(bbox.y_max)]);
+        }
+
+        let mut buf = Vec::new();
+        doc.save_to(&mut buf).map_err(failed)?;
+        Ok(buf)
+    }
+}
+
+impl Subset {
+    fn new() -> Self {
+        // Code 0 is the missing glyph, standing for nothing
+        let entry = (0, String::new());
+        Subset { codes: HashMap::from([(entry.clone(), 0)]), entries: vec![entry] }
+    }
+
+    /// Six capital letters naming this particular subset, as PDF requires
+    /// for the names of subset fonts. Derived from the glyphs so the same
+    /// report always gets the same tag.
+    fn tag(&self) -> String {
+        let mut hash: u32 = 2166136261;
+        for (glyph, _) in &self.entries {
+            for byte in glyph.to_be_bytes() {
+                hash = (hash ^ u32::from(byte)).wrapping_mul(16777619);
+            }
+        }
+        (0..6).map(|i| (b'A' + (hash >> (i * 5) & 31) as u8 % 26) as char).collect()
+    }
+
+    /// A CMap from character codes to the UTF-16 text each one stands for.
+    fn to_unicode(&self) -> Vec<u8> {
+        let mapped: Vec<(usize, &String)> = self.entries.iter()
+            .enumerate()
+            .filter(|(_, (_, text))| !text.is_empty())
+            .map(|(code, (_, text))| (code, text))
+            .collect();
+
+        let mut cmap = String::from(
+            "/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n\
+             /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+             /CMapName /Adobe-Identity-UCS def\n/CMapType 2 def\n\
+             1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n",
+        );
+        // A bfchar block may hold at most 100 entries
+        for block in mapped.chunks(100) {
+            cmap.push_str(&format!("{} beginbfchar\n", block.len()));
+            for (code, text) in block {
+                let utf16: String = text.encode_utf16().map(|unit| format!("{:04X}", unit)).collect();
+                cmap.push_str(&format!("<{:04X}> <{}>\n", code, utf16));
+            }
+            cmap.push_str("endbfchar\n");
+        }
+        cmap.push_str("endcmap\nCMapName currentdict /CMap defineresource pop\nend\nend\n");
+        cmap.into_bytes()
+    }
+}
+
+fn failed(error: impl std::fmt::Display) -> AppError {
+    AppError::Internal(format!("PDF font embedding failed: {}", error))
+}
+
+fn compressed(content: Vec<u8>) -> lopdf::Result<Stream> {
+    let mut stream = Stream::new(Dictionary::new(), content);
+    stream.compress()?;
+    Ok(stream)
+}
+
+/// What printpdf reads to write the font dictionaries of one subset, with
+/// character codes in place of glyph ids.
+#[derive(Debug, Clone)]
+struct SubsetData {
+    font: FontId,
+    /// Glyph id for each character code
+    glyphs: Vec<u16>,
+}
+
+impl FontData for SubsetData {
+    fn font_metrics(&self) -> FontMetrics {
+        let face = self.font.face();
+        FontMetrics {
+            ascent: face.ascender(),
+            descent: face.descender(),
+            units_per_em: face.units_per_em() as u16,
+        }
+    }
+
+    // Text is drawn from shaped glyphs, never through printpdf's own encoding
+    fn glyph_id(&self, _c: char) -> Option<u16> {
+        None
+    }
+
+    fn glyph_ids(&self) -> HashMap<u16, char> {
+        HashMap::new()
+    }
+
+    fn glyph_count(&self) -> u16 {
+        self.glyphs.len() as u16
+    }
+
+    fn glyph_metrics(&self, code: u16) -> Option<GlyphMetrics> {
+        let face = self.font.face();
+        let glyph = GlyphId(*self.glyphs.get(usize::from(code))?);
+        Some(GlyphMetrics {
+            width: u32::from(face.glyph_hor_advance(glyph)?),
+            height: (face.ascender() - face.descender()) as u32,
+        })
+    }
+}
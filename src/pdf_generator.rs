@@ -1,161 +1,471 @@
-use printpdf::{PdfDocument, Mm};
-use std::io::BufWriter;
+use std::collections::{BTreeMap, BTreeSet};
 
-use crate::models::{Episode, AnalyticsData, PatternAnalysis};
+use chrono::{Datelike, NaiveDate, Timelike, Weekday};
+
+use crate::error::AppError;
+use crate::models::{Episode, EpisodeFilter, AnalyticsData, PatternAnalysis, ReportSection, ReportTemplate, SymptomChecklist};
+use crate::pdf_charts;
+use crate::pdf_layout::{Column, FontStyle, Layout};
+
+/// Episodes listed in the summary table; the appendix lists all of them.
+const RECENT_EPISODES: usize = 5;
+/// Lines each summary table cell may wrap onto before it is ellipsized.
+const RECENT_EPISODE_LINES: usize = 2;
+
+/// Millimetres a page-limited report keeps free for naming the sections it left out.
+const OMITTED_NOTE_SPACE: f32 = 15.0;
+
+/// Severities from which an episode counts as severe in the functional impact figures.
+const SEVERE_FROM: i32 = 4;
+/// Onset hours, on weekdays, that count as working hours.
+const WORKING_HOURS: std::ops::Range<u32> = 9..17;
 
 pub struct PDFReportGenerator;
 
 impl PDFReportGenerator {
+    /// The full medical report, as the `medical` template prints it by default.
     pub fn generate_medical_report(
         episodes: &[Episode],
         analytics: &AnalyticsData,
-        patterns: &PatternAnalysis
-    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let (doc, page1, layer1) = PdfDocument::new("Vertigo Episode Medical Report", Mm(210.0), Mm(297.0), "Layer 1");
-        let current_layer = doc.get_page(page1).get_layer(layer1);
-
-        // Use built-in font
-        let font = doc.add_builtin_font(printpdf::BuiltinFont::HelveticaBold)?;
-        let font_regular = doc.add_builtin_font(printpdf::BuiltinFont::Helvetica)?;
+        patterns: &PatternAnalysis,
+        filter: &EpisodeFilter,
+    ) -> Result<Vec<u8>, AppError> {
+        let sections = ReportTemplate::Medical.default_sections();
+        Self::generate_report(ReportTemplate::Medical, &sections, episodes, analytics, patterns, filter)
+    }
 
-        let mut y_position = Mm(270.0);
+    /// A report with the template's title and page limit, printing `sections`
+    /// in the given order.
+    pub fn generate_report(
+        template: ReportTemplate,
+        sections: &[ReportSection],
+        episodes: &[Episode],
+        analytics: &AnalyticsData,
+        patterns: &PatternAnalysis,
+        filter: &EpisodeFilter,
+    ) -> Result<Vec<u8>, AppError> {
+        let definition = template.definition();
+        let report = Report { episodes, analytics, patterns, sections };
+        let mut layout = Layout::new();
 
         // Title
-        current_layer.use_text("VERTIGO EPISODE MEDICAL REPORT", 16.0, Mm(20.0), y_position, &font);
-        y_position -= Mm(15.0);
+        layout.text(definition.name.to_uppercase(), 16.0, FontStyle::Bold, 0.0);
+        layout.gap(6.0);
 
-        // Date
-        let today = chrono::Utc::now().format("%B %d, %Y").to_string();
-        current_layer.use_text(format!("Report Date: {}", today), 12.0, Mm(20.0), y_position, &font_regular);
-        y_position -= Mm(15.0);
+        // Date and the period covered
+        let today = chrono::Utc::now().date_naive();
+        layout.text(format!("Report Date: {}", today.format("%B %d, %Y")), 11.0, FontStyle::Regular, 0.0);
+        layout.text(format!("Period: {}", filter.period(episodes, today)), 11.0, FontStyle::Regular, 0.0);
+        if let Some(criteria) = filter.describe_criteria() {
+            layout.text(format!("Episodes: {}", criteria), 11.0, FontStyle::Regular, 0.0);
+        }
+        layout.gap(9.0);
 
-        // Summary Statistics
-        current_layer.use_text("SUMMARY STATISTICS", 14.0, Mm(20.0), y_position, &font);
-        y_position -= Mm(10.0);
+        let Some(max_pages) = definition.max_pages else {
+            for &section in sections {
+                report.section(&mut layout, section);
+            }
+            return layout.render(definition.name);
+        };
 
-        current_layer.use_text(format!("Total Episodes: {}", analytics.total_episodes), 11.0, Mm(25.0), y_position, &font_regular);
-        y_position -= Mm(6.0);
+        // Each section is tried on a copy and kept only if the report still
+        // fits, with room left for the disclaimer and the note on what gave
+        // way. The disclaimer itself is never left out.
+        let mut disclaimer_due = sections.contains(&ReportSection::Disclaimer);
+        let mut omitted = Vec::new();
+        for &section in sections {
+            if section == ReportSection::Disclaimer {
+                report.section(&mut layout, section);
+                disclaimer_due = false;
+                continue;
+            }
 
-        current_layer.use_text(format!("Average Severity: {:.1}/5", analytics.average_severity), 11.0, Mm(25.0), y_position, &font_regular);
-        y_position -= Mm(6.0);
+            let mut attempt = layout.clone();
+            report.section(&mut attempt, section);
+            let mut finished = attempt.clone();
+            if disclaimer_due {
+                disclaimer(&mut finished);
+            }
+            if finished.page_count() <= max_pages && !finished.ensure_space(OMITTED_NOTE_SPACE) {
+                layout = attempt;
+            } else {
+                omitted.push(section.title());
+            }
+        }
 
-        current_layer.use_text(format!("Average Duration: {:.0} minutes", analytics.duration_stats.average_minutes), 11.0, Mm(25.0), y_position, &font_regular);
-        y_position -= Mm(6.0);
+        if !omitted.is_empty() {
+            layout.text(
+                format!("Left out to keep to {} page{}: {}.", max_pages, if max_pages == 1 { "" } else { "s" }, omitted.join(", ")),
+                9.0, FontStyle::Regular, 0.0,
+            );
+        }
 
-        current_layer.use_text(format!("Duration Range: {} - {} minutes", analytics.duration_stats.min_minutes, analytics.duration_stats.max_minutes), 11.0, Mm(25.0), y_position, &font_regular);
-        y_position -= Mm(15.0);
+        layout.render(definition.name)
+    }
+}
 
-        // Common Triggers
-        if !patterns.common_triggers.is_empty() {
-            current_layer.use_text("IDENTIFIED TRIGGERS", 14.0, Mm(20.0), y_position, &font);
-            y_position -= Mm(10.0);
+/// The data a report prints and which sections it prints.
+struct Report<'a> {
+    episodes: &'a [Episode],
+    analytics: &'a AnalyticsData,
+    patterns: &'a PatternAnalysis,
+    sections: &'a [ReportSection],
+}
 
-            for trigger in &patterns.common_triggers {
-                current_layer.use_text(format!("• {}", trigger), 11.0, Mm(25.0), y_position, &font_regular);
-                y_position -= Mm(6.0);
-            }
-            y_position -= Mm(5.0);
-        }
+impl Report<'_> {
+    fn section(&self, layout: &mut Layout, section: ReportSection) {
+        let (episodes, analytics, patterns) = (self.episodes, self.analytics, self.patterns);
 
-        // Severity Patterns
-        if !patterns.severity_patterns.is_empty() {
-            current_layer.use_text("SEVERITY PATTERNS", 14.0, Mm(20.0), y_position, &font);
-            y_position -= Mm(10.0);
+        match section {
+            ReportSection::Summary => summary(layout, analytics),
+            ReportSection::FunctionalImpact => functional_impact(layout, episodes),
+            // Charts are left out when they have nothing to show
+            ReportSection::SeverityChart => pdf_charts::severity_over_time(layout, episodes),
+            ReportSection::MonthlyChart => pdf_charts::monthly_episodes(layout, &analytics.monthly_trends),
+            ReportSection::TriggerChart => pdf_charts::trigger_frequency(layout, &analytics.trigger_frequency),
+            ReportSection::TimeOfDayChart => pdf_charts::time_of_day(layout, episodes),
+            ReportSection::DurationChart => pdf_charts::duration_histogram(layout, episodes),
+            ReportSection::Triggers => bullet_section(layout, "IDENTIFIED TRIGGERS", "•", &patterns.common_triggers),
+            ReportSection::Medications => medications(layout, analytics),
+            ReportSection::MedicationEfficacy => medication_efficacy(layout, analytics),
+            ReportSection::SeverityPatterns => bullet_section(layout, "SEVERITY PATTERNS", "•", &patterns.severity_patterns),
+            ReportSection::TimePatterns => bullet_section(layout, "TIME PATTERNS", "•", &patterns.time_patterns),
+            ReportSection::RiskFactors => bullet_section(layout, "RISK FACTORS", "!", &patterns.risk_factors),
+            ReportSection::Recommendations => bullet_section(layout, "RECOMMENDATIONS", "-", &patterns.recommendations),
+            ReportSection::RecentEpisodes => self.recent_episodes(layout),
+            ReportSection::Diary => diary(layout, episodes),
+            ReportSection::AbsenceLog => absence_log(layout, episodes),
+            ReportSection::Disclaimer => disclaimer(layout),
+            ReportSection::Appendix => appendix(layout, episodes),
+        }
+    }
 
-            for pattern in &patterns.severity_patterns {
-                current_layer.use_text(format!("• {}", pattern), 11.0, Mm(25.0), y_position, &font_regular);
-                y_position -= Mm(6.0);
-            }
-            y_position -= Mm(5.0);
+    fn recent_episodes(&self, layout: &mut Layout) {
+        if self.episodes.is_empty() {
+            return;
         }
 
-        // Time Patterns
-        if !patterns.time_patterns.is_empty() {
-            current_layer.use_text("TIME PATTERNS", 14.0, Mm(20.0), y_position, &font);
-            y_position -= Mm(10.0);
+        let mut recent: Vec<&Episode> = self.episodes.iter().collect();
+        recent.sort_by_key(|e| std::cmp::Reverse((e.timestamp, e.id)));
+        let rows: Vec<Vec<String>> = recent.into_iter().take(RECENT_EPISODES).map(episode_row).collect();
 
-            for pattern in &patterns.time_patterns {
-                current_layer.use_text(format!("• {}", pattern), 11.0, Mm(25.0), y_position, &font_regular);
-                y_position -= Mm(6.0);
-            }
-            y_position -= Mm(5.0);
+        // Long fields are cut short here; the appendix has them in full
+        let columns: Vec<Column> = episode_columns().into_iter().map(|c| c.max_lines(RECENT_EPISODE_LINES)).collect();
+        layout.table("RECENT EPISODES", &columns, &rows);
+        if self.sections.contains(&ReportSection::Appendix) {
+            layout.text(format!("All {} episodes are listed in the appendix.", self.episodes.len()), 9.0, FontStyle::Regular, 0.0);
         }
+        layout.gap(5.0);
+    }
+}
 
-        // Risk Factors
-        if !patterns.risk_factors.is_empty() {
-            current_layer.use_text("RISK FACTORS", 14.0, Mm(20.0), y_position, &font);
-            y_position -= Mm(10.0);
+fn summary(layout: &mut Layout, analytics: &AnalyticsData) {
+    layout.heading("SUMMARY STATISTICS");
+    layout.text(format!("Total Episodes: {}", analytics.total_episodes), 11.0, FontStyle::Regular, 5.0);
+    layout.text(format!("Average Severity: {:.1}/5", analytics.average_severity), 11.0, FontStyle::Regular, 5.0);
+    layout.text(format!("Average Duration: {:.0} minutes", analytics.duration_stats.average_minutes), 11.0, FontStyle::Regular, 5.0);
+    layout.text(format!("Duration Range: {} - {} minutes", analytics.duration_stats.min_minutes, analytics.duration_stats.max_minutes), 11.0, FontStyle::Regular, 5.0);
+    layout.gap(9.0);
+}
 
-            for risk in &patterns.risk_factors {
-                current_layer.use_text(format!("! {}", risk), 11.0, Mm(25.0), y_position, &font_regular);
-                y_position -= Mm(6.0);
+/// How much time episodes took and how disabling they were, overall and by month.
+fn functional_impact(layout: &mut Layout, episodes: &[Episode]) {
+    layout.heading("FUNCTIONAL IMPACT");
+    if episodes.is_empty() {
+        layout.text("No episodes were logged in this period.", 11.0, FontStyle::Regular, 5.0);
+        layout.gap(9.0);
+        return;
+    }
+
+    let totals = ImpactTotals::of(episodes.iter());
+    let untimed = episodes.len() - totals.timed;
+    layout.text(format!("Episodes: {} on {} days", episodes.len(), totals.days.len()), 11.0, FontStyle::Regular, 5.0);
+    layout.text(
+        format!("Time with symptoms: {}{}", duration_text(totals.minutes),
+            if untimed > 0 { format!(" ({} without a recorded duration)", untimed) } else { String::new() }),
+        11.0, FontStyle::Regular, 5.0,
+    );
+    if let Some(longest) = episodes.iter().filter(|e| e.duration_minutes.is_some()).max_by_key(|e| (e.duration_minutes, std::cmp::Reverse(e.timestamp))) {
+        layout.text(
+            format!("Longest episode: {} on {}", duration_text(longest.duration_minutes.unwrap_or(0).into()), longest.timestamp.format("%B %d, %Y")),
+            11.0, FontStyle::Regular, 5.0,
+        );
+    }
+    layout.text(format!("Severe episodes ({}-5/5): {} on {} days", SEVERE_FROM, totals.severe, totals.severe_days.len()), 11.0, FontStyle::Regular, 5.0);
+    layout.text(
+        format!("Starting during working hours (Mon-Fri {:02}:00-{:02}:00): {}", WORKING_HOURS.start, WORKING_HOURS.end,
+            episodes.iter().filter(|e| during_working_hours(e)).count()),
+        11.0, FontStyle::Regular, 5.0,
+    );
+    let disabling = episodes.iter()
+        .filter(|e| e.symptom_checklist.as_ref().is_some_and(|c| c.vomiting || c.imbalance))
+        .count();
+    layout.text(format!("With vomiting or imbalance: {}", disabling), 11.0, FontStyle::Regular, 5.0);
+    layout.gap(5.0);
+
+    let mut months: BTreeMap<(i32, u32), Vec<&Episode>> = BTreeMap::new();
+    for episode in episodes {
+        months.entry((episode.timestamp.year(), episode.timestamp.month())).or_default().push(episode);
+    }
+    let rows: Vec<Vec<String>> = months.into_iter()
+        .map(|((year, month), episodes)| {
+            let totals = ImpactTotals::of(episodes.iter().copied());
+            vec![
+                NaiveDate::from_ymd_opt(year, month, 1).map_or_else(String::new, |d| d.format("%B %Y").to_string()),
+                episodes.len().to_string(),
+                totals.days.len().to_string(),
+                duration_text(totals.minutes),
+                totals.severe.to_string(),
+            ]
+        })
+        .collect();
+    let columns = [
+        Column::new("Month", 40.0),
+        Column::new("Episodes", 25.0),
+        Column::new("Days affected", 30.0),
+        Column::new("Time with symptoms", 45.0),
+        Column::new("Severe", 30.0),
+    ];
+    layout.table("IMPACT BY MONTH", &columns, &rows);
+    layout.gap(5.0);
+}
+
+/// Counts shared by the functional impact summary and its monthly breakdown.
+struct ImpactTotals {
+    days: BTreeSet<NaiveDate>,
+    severe_days: BTreeSet<NaiveDate>,
+    severe: usize,
+    /// Episodes with a recorded duration, and the minutes they add up to
+    timed: usize,
+    minutes: i64,
+}
+
+impl ImpactTotals {
+    fn of<'a>(episodes: impl Iterator<Item = &'a Episode>) -> Self {
+        let mut totals = ImpactTotals { days: BTreeSet::new(), severe_days: BTreeSet::new(), severe: 0, timed: 0, minutes: 0 };
+        for episode in episodes {
+            let day = episode.timestamp.date();
+            totals.days.insert(day);
+            if episode.severity >= SEVERE_FROM {
+                totals.severe += 1;
+                totals.severe_days.insert(day);
+            }
+            if let Some(minutes) = episode.duration_minutes {
+                totals.timed += 1;
+                totals.minutes += i64::from(minutes);
             }
-            y_position -= Mm(5.0);
         }
+        totals
+    }
+}
 
-        // Recommendations
-        if !patterns.recommendations.is_empty() {
-            current_layer.use_text("RECOMMENDATIONS", 14.0, Mm(20.0), y_position, &font);
-            y_position -= Mm(10.0);
+fn during_working_hours(episode: &Episode) -> bool {
+    !matches!(episode.timestamp.weekday(), Weekday::Sat | Weekday::Sun)
+        && WORKING_HOURS.contains(&episode.timestamp.hour())
+}
 
-            for rec in patterns.recommendations.iter().take(8) { // Limit to prevent page overflow
-                current_layer.use_text(format!("- {}", rec), 11.0, Mm(25.0), y_position, &font_regular);
-                y_position -= Mm(6.0);
-            }
-            y_position -= Mm(10.0);
+fn medications(layout: &mut Layout, analytics: &AnalyticsData) {
+    if analytics.medication_usage.is_empty() {
+        return;
+    }
+
+    layout.heading("MEDICATIONS");
+    for medication in &analytics.medication_usage {
+        let details: Vec<&str> = [medication.drug_class.as_deref(), medication.strength.as_deref(), Some(medication.usage.as_str())]
+            .into_iter()
+            .flatten()
+            .collect();
+        let effect = medication.average_effect
+            .map_or("no effect ratings".to_string(), |e| format!("avg effect {:.1}/5", e));
+
+        layout.bullet(
+            "•",
+            &format!("{} ({}) - {} doses, {}", medication.name, details.join(", "), medication.intake_count, effect),
+            11.0, 5.0,
+        );
+    }
+    layout.gap(5.0);
+}
+
+fn medication_efficacy(layout: &mut Layout, analytics: &AnalyticsData) {
+    if analytics.medication_efficacy.is_empty() {
+        return;
+    }
+
+    layout.heading("MEDICATION EFFICACY");
+    for efficacy in &analytics.medication_efficacy {
+        layout.bullet(
+            "•",
+            &format!("{} (started {}) - {:.1} vs {:.1} episodes/30 days",
+                efficacy.name, efficacy.started_on, efficacy.before.episodes_per_30_days, efficacy.after.episodes_per_30_days),
+            11.0, 5.0,
+        );
+
+        if let (Some(ratio), Some(low), Some(high)) = (efficacy.frequency.rate_ratio, efficacy.frequency.ci_low, efficacy.frequency.ci_high) {
+            layout.text(format!("Rate ratio {:.2} (95% CI {:.2}-{:.2})", ratio, low, high), 10.0, FontStyle::Regular, 8.0);
         }
+    }
+    layout.gap(5.0);
+}
+
+/// Every episode oldest first, with every field the patient recorded.
+fn diary(layout: &mut Layout, episodes: &[Episode]) {
+    layout.heading("EPISODE DIARY");
+    if episodes.is_empty() {
+        layout.text("No episodes were logged in this period.", 11.0, FontStyle::Regular, 0.0);
+        return;
+    }
+
+    for episode in chronological(episodes) {
+        // Keep the date with the first lines of its entry
+        layout.ensure_space(20.0);
+        layout.text(episode.timestamp.format("%A, %B %d, %Y at %H:%M").to_string(), 11.0, FontStyle::Bold, 0.0);
 
-        // Recent Episodes (simplified)
-        if !episodes.is_empty() && y_position > Mm(60.0) {
-            current_layer.use_text("RECENT EPISODES", 14.0, Mm(20.0), y_position, &font);
-            y_position -= Mm(10.0);
-
-            // Table headers
-            current_layer.use_text("Date", 10.0, Mm(20.0), y_position, &font);
-            current_layer.use_text("Severity", 10.0, Mm(60.0), y_position, &font);
-            current_layer.use_text("Duration", 10.0, Mm(100.0), y_position, &font);
-            current_layer.use_text("Triggers", 10.0, Mm(140.0), y_position, &font);
-            y_position -= Mm(8.0);
-
-            // Show last 5 episodes to fit on page
-            for episode in episodes.iter().take(5) {
-                let date = episode.timestamp.format("%m/%d/%y").to_string();
-                let severity = format!("{}/5", episode.severity);
-                let duration = episode.duration_minutes.map_or("--".to_string(), |d| format!("{}min", d));
-                let triggers = episode.triggers.as_deref().unwrap_or("--")
-                    .chars().take(15).collect::<String>(); // Truncate to fit
-
-                current_layer.use_text(date, 9.0, Mm(20.0), y_position, &font_regular);
-                current_layer.use_text(severity, 9.0, Mm(60.0), y_position, &font_regular);
-                current_layer.use_text(duration, 9.0, Mm(100.0), y_position, &font_regular);
-                current_layer.use_text(triggers, 9.0, Mm(140.0), y_position, &font_regular);
-                y_position -= Mm(6.0);
-
-                if y_position < Mm(40.0) {
-                    break;
-                }
+        let duration = episode.duration_minutes.map_or(String::new(), |d| format!(", {}", duration_text(d.into())));
+        layout.text(format!("Severity {}/5 ({}){}", episode.severity, severity_label(episode.severity), duration), 10.0, FontStyle::Regular, 5.0);
+
+        let checklist = episode.symptom_checklist.as_ref().and_then(SymptomChecklist::summary);
+        for (label, value) in [
+            ("Symptoms", episode.symptoms.as_deref()),
+            ("Checklist", checklist.as_deref()),
+            ("Triggers", episode.triggers.as_deref()),
+            ("Location", episode.location.as_deref()),
+            ("Before", episode.activities_before.as_deref()),
+            ("Medications", episode.medications_taken.as_deref()),
+            ("Notes", episode.notes.as_deref()),
+        ] {
+            if let Some(value) = value.filter(|v| !v.trim().is_empty()) {
+                layout.text(format!("{}: {}", label, value), 10.0, FontStyle::Regular, 5.0);
             }
         }
+        layout.gap(4.0);
+    }
+}
 
-        // Medical Disclaimer
-        y_position -= Mm(10.0);
-        if y_position > Mm(30.0) {
-            current_layer.use_text("MEDICAL DISCLAIMER", 12.0, Mm(20.0), y_position, &font);
-            y_position -= Mm(8.0);
-
-            current_layer.use_text("This report is for informational purposes only and should", 10.0, Mm(20.0), y_position, &font_regular);
-            y_position -= Mm(5.0);
-            current_layer.use_text("not replace professional medical advice. Always consult", 10.0, Mm(20.0), y_position, &font_regular);
-            y_position -= Mm(5.0);
-            current_layer.use_text("healthcare providers for proper diagnosis and treatment.", 10.0, Mm(20.0), y_position, &font_regular);
-        }
+/// One row per episode with what an employer or insurer asks about: when it
+/// started, how long it lasted and what it stopped the patient doing.
+fn absence_log(layout: &mut Layout, episodes: &[Episode]) {
+    if episodes.is_empty() {
+        layout.heading("ABSENCE LOG");
+        layout.text("No episodes were logged in this period.", 11.0, FontStyle::Regular, 0.0);
+        return;
+    }
+
+    let columns = [
+        Column::new("Date", 30.0),
+        Column::new("Start", 14.0),
+        Column::new("Duration", 18.0),
+        Column::new("Severity", 26.0),
+        Column::new("Functional signs", 38.0),
+        Column::new("Activity / place", 44.0),
+    ];
+    let rows: Vec<Vec<String>> = chronological(episodes)
+        .map(|episode| {
+            let signs: Vec<&str> = episode.symptom_checklist.as_ref()
+                .map(|c| [(c.vomiting, "vomiting"), (c.imbalance, "imbalance"), (c.nausea, "nausea"), (c.visual_aura, "visual aura"), (c.headache, "headache")]
+                    .into_iter()
+                    .filter_map(|(present, name)| present.then_some(name))
+                    .collect())
+                .unwrap_or_default();
+            let place: Vec<&str> = [episode.activities_before.as_deref(), episode.location.as_deref()]
+                .into_iter()
+                .flatten()
+                .filter(|v| !v.trim().is_empty())
+                .collect();
+
+            vec![
+                episode.timestamp.format("%a %Y-%m-%d").to_string(),
+                episode.timestamp.format("%H:%M").to_string(),
+                episode.duration_minutes.map_or("--".to_string(), |d| duration_text(d.into())),
+                format!("{}/5 {}", episode.severity, severity_label(episode.severity)),
+                if signs.is_empty() { "--".to_string() } else { signs.join(", ") },
+                if place.is_empty() { "--".to_string() } else { place.join(" at ") },
+            ]
+        })
+        .collect();
+    layout.table("ABSENCE LOG", &columns, &rows);
+    layout.gap(5.0);
+}
 
-        // Generate PDF bytes
-        let mut buf = Vec::new();
-        let mut writer = BufWriter::new(&mut buf);
-        doc.save(&mut writer)?;
-        drop(writer);
+fn disclaimer(layout: &mut Layout) {
+    layout.ensure_space(25.0);
+    layout.text("MEDICAL DISCLAIMER", 12.0, FontStyle::Bold, 0.0);
+    layout.gap(2.0);
+    layout.text(
+        "This report is for informational purposes only and should not replace professional medical advice. \
+         Always consult healthcare providers for proper diagnosis and treatment.",
+        10.0, FontStyle::Regular, 0.0,
+    );
+}
+
+/// Every episode, oldest first, starting on a new page.
+fn appendix(layout: &mut Layout, episodes: &[Episode]) {
+    layout.new_page();
+    if episodes.is_empty() {
+        layout.heading("APPENDIX: EPISODE LOG");
+        layout.text("No episodes were logged in this period.", 11.0, FontStyle::Regular, 0.0);
+    } else {
+        let rows: Vec<Vec<String>> = chronological(episodes).map(episode_row).collect();
+        layout.table("APPENDIX: EPISODE LOG", &episode_columns(), &rows);
+    }
+}
+
+/// A heading and one line per item, or nothing when there are no items.
+fn bullet_section(layout: &mut Layout, title: &str, bullet: &str, items: &[String]) {
+    if items.is_empty() {
+        return;
+    }
 
-        Ok(buf)
+    layout.heading(title);
+    for item in items {
+        layout.bullet(bullet, item, 11.0, 5.0);
     }
-}
\ No newline at end of file
+    layout.gap(5.0);
+}
+
+fn chronological(episodes: &[Episode]) -> impl Iterator<Item = &Episode> {
+    let mut sorted: Vec<&Episode> = episodes.iter().collect();
+    sorted.sort_by_key(|e| (e.timestamp, e.id));
+    sorted.into_iter()
+}
+
+/// The wording the episode form uses for each severity.
+fn severity_label(severity: i32) -> &'static str {
+    match severity {
+        ..=1 => "Very mild",
+        2 => "Mild",
+        3 => "Moderate",
+        4 => "Severe",
+        _ => "Very severe",
+    }
+}
+
+fn duration_text(minutes: i64) -> String {
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{} min", minutes),
+        (hours, 0) => format!("{} h", hours),
+        (hours, minutes) => format!("{} h {} min", hours, minutes),
+    }
+}
+
+fn episode_columns() -> Vec<Column> {
+    vec![
+        Column::new("Date", 28.0),
+        Column::new("Severity", 17.0),
+        Column::new("Duration", 18.0),
+        Column::new("Triggers", 32.0),
+        Column::new("Symptoms", 32.0),
+        Column::new("Notes", 43.0),
+    ]
+}
+
+fn episode_row(episode: &Episode) -> Vec<String> {
+    vec![
+        episode.timestamp.format("%Y-%m-%d %H:%M").to_string(),
+        format!("{}/5", episode.severity),
+        episode.duration_minutes.map_or("--".to_string(), |d| format!("{} min", d)),
+        episode.triggers.clone().unwrap_or_else(|| "--".to_string()),
+        episode.symptoms.clone().unwrap_or_else(|| "--".to_string()),
+        episode.notes.clone().filter(|n| !n.trim().is_empty()).unwrap_or_else(|| "--".to_string()),
+    ]
+}
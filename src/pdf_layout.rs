@@ -0,0 +1,430 @@
+use printpdf::lopdf::content::Operation;
+use printpdf::lopdf::{Object, StringFormat};
+use printpdf::{Color, IndirectFontRef, Line, Mm, PdfDocument, PdfLayerReference, Point, Rect};
+use std::io::BufWriter;
+
+use crate::error::AppError;
+use crate::pdf_fonts::{self, FontSubsets, Run};
+use crate::pdf_text::{text_width, wrap, wrap_limited, POINT_TO_MM};
+
+/// A4 portrait, in millimetres.
+pub const PAGE_WIDTH: f32 = 210.0;
+pub const PAGE_HEIGHT: f32 = 297.0;
+pub const MARGIN_LEFT: f32 = 20.0;
+pub const MARGIN_RIGHT: f32 = 20.0;
+pub const CONTENT_WIDTH: f32 = PAGE_WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+
+/// Baseline of the first line on a page, and the lowest baseline content may use.
+const TOP: f32 = 270.0;
+const BOTTOM: f32 = 25.0;
+const FOOTER_Y: f32 = 12.0;
+
+const HEADING_SIZE: f32 = 14.0;
+const TABLE_HEADER_SIZE: f32 = 10.0;
+const TABLE_ROW_SIZE: f32 = 9.0;
+/// Baseline to baseline within a wrapped header or cell, and the space
+/// between table rows.
+const TABLE_HEADER_LINE: f32 = 4.5;
+const TABLE_LINE_HEIGHT: f32 = 4.0;
+const TABLE_ROW_GAP: f32 = 2.0;
+/// Kept clear on the right of each cell so columns never touch.
+const CELL_PADDING: f32 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FontStyle {
+    Regular,
+    Bold,
+}
+
+/// A colour for lines and fills, each channel from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rgb(pub f32, pub f32, pub f32);
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb(0.0, 0.0, 0.0);
+    pub const WHITE: Rgb = Rgb(1.0, 1.0, 1.0);
+
+    /// The colour `amount` of the way from `self` to `other`.
+    pub fn mix(self, other: Rgb, amount: f32) -> Rgb {
+        let amount = amount.clamp(0.0, 1.0);
+        let channel = |from: f32, to: f32| from + (to - from) * amount;
+        Rgb(channel(self.0, other.0), channel(self.1, other.1), channel(self.2, other.2))
+    }
+
+    fn color(self) -> Color {
+        Color::Rgb(printpdf::Rgb::new(self.0, self.1, self.2, None))
+    }
+}
+
+/// Which point of a label its x coordinate refers to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// Something drawn on a page. Coordinates are millimetres from the bottom left.
+#[derive(Debug, Clone)]
+enum Op {
+    Text { x: f32, y: f32, size: f32, style: FontStyle, text: String },
+    Rule { x1: f32, x2: f32, y: f32 },
+    Path { points: Vec<(f32, f32)>, thickness: f32, color: Rgb },
+    Rect { x: f32, y: f32, width: f32, height: f32, fill: Rgb },
+}
+
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub title: String,
+    /// Millimetres
+    pub width: f32,
+    /// Cells wrap onto as many lines as they need unless limited here, in
+    /// which case the last line ends with an ellipsis.
+    pub max_lines: Option<usize>,
+}
+
+impl Column {
+    pub fn new(title: &str, width: f32) -> Self {
+        Column { title: title.to_string(), width, max_lines: None }
+    }
+
+    pub fn max_lines(mut self, max_lines: usize) -> Self {
+        self.max_lines = Some(max_lines);
+        self
+    }
+
+    fn lines(&self, text: &str, size: f32, style: FontStyle) -> Vec<String> {
+        let width = self.width - CELL_PADDING;
+        match self.max_lines {
+            Some(max_lines) => wrap_limited(text, width, size, style, max_lines),
+            None => wrap(text, width, size, style),
+        }
+    }
+}
+
+/// Flows report content down the page, starting new pages as needed. Nothing
+/// is drawn until `render`, so every page can be numbered "Page X of Y".
+#[derive(Debug, Clone)]
+pub struct Layout {
+    pages: Vec<Vec<Op>>,
+    y: f32,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Layout {
+    pub fn new() -> Self {
+        Layout { pages: vec![Vec::new()], y: TOP }
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn new_page(&mut self) {
+        self.pages.push(Vec::new());
+        self.y = TOP;
+    }
+
+    /// Moves to a new page unless `height` millimetres still fit on this one.
+    /// Returns whether a page was added.
+    pub fn ensure_space(&mut self, height: f32) -> bool {
+        let fresh_page = self.y == TOP;
+        if self.y - height < BOTTOM && !fresh_page {
+            self.new_page();
+            return true;
+        }
+        false
+    }
+
+    pub fn gap(&mut self, height: f32) {
+        self.y -= height;
+    }
+
+    fn push(&mut self, op: Op) {
+        self.pages.last_mut().expect("a layout always has a page").push(op);
+    }
+
+    /// Text at `indent` from the left margin, wrapped to the right margin.
+    /// Lines advance a little over half the font size in millimetres.
+    pub fn text(&mut self, text: impl Into<String>, size: f32, style: FontStyle, indent: f32) {
+        let text = text.into();
+        for line in wrap(&text, CONTENT_WIDTH - indent, size, style) {
+            self.line(line, size, style, indent);
+        }
+    }
+
+    /// A bulleted item whose wrapped lines hang under the first word, not the bullet.
+    pub fn bullet(&mut self, bullet: &str, text: &str, size: f32, indent: f32) {
+        let hang = indent + text_width(&format!("{} ", bullet), size, FontStyle::Regular);
+        let lines = wrap(text, CONTENT_WIDTH - hang, size, FontStyle::Regular);
+
+        for (index, line) in lines.into_iter().enumerate() {
+            if index == 0 {
+                self.ensure_space(line_advance(size));
+                self.push(Op::Text { x: MARGIN_LEFT + indent, y: self.y, size, style: FontStyle::Regular, text: bullet.to_string() });
+            }
+            self.line(line, size, FontStyle::Regular, hang);
+        }
+    }
+
+    fn line(&mut self, text: String, size: f32, style: FontStyle, indent: f32) {
+        let advance = line_advance(size);
+        self.ensure_space(advance);
+        self.push(Op::Text { x: MARGIN_LEFT + indent, y: self.y, size, style, text });
+        self.y -= advance;
+    }
+
+    /// A section heading, kept on the same page as at least one line after it.
+    pub fn heading(&mut self, text: &str) {
+        self.ensure_space(10.0 + line_advance(11.0));
+        self.push(Op::Text { x: MARGIN_LEFT, y: self.y, size: HEADING_SIZE, style: FontStyle::Bold, text: text.to_string() });
+        self.y -= 10.0;
+    }
+
+    /// A table with a bold header row that is repeated, under a "(continued)"
+    /// heading, on every page the table runs onto. Cells wrap within their
+    /// column and each row is as tall as its longest cell. Rows are kept whole
+    /// unless one is taller than a page, which then continues line by line.
+    pub fn table(&mut self, title: &str, columns: &[Column], rows: &[Vec<String>]) {
+        self.heading(title);
+        self.table_header(columns);
+
+        let tallest_row = TOP - BOTTOM - 10.0 - header_height(columns);
+        for row in rows {
+            let cells: Vec<Vec<String>> = columns.iter()
+                .zip(row)
+                .map(|(column, cell)| column.lines(cell, TABLE_ROW_SIZE, FontStyle::Regular))
+                .collect();
+            let line_count = cells.iter().map(Vec::len).max().unwrap_or(0).max(1);
+
+            let height = line_count as f32 * TABLE_LINE_HEIGHT;
+            if height <= tallest_row {
+                self.table_space(title, columns, height);
+            }
+
+            for line in 0..line_count {
+                self.table_space(title, columns, TABLE_LINE_HEIGHT);
+                let mut x = MARGIN_LEFT;
+                for (column, lines) in columns.iter().zip(&cells) {
+                    if let Some(text) = lines.get(line) {
+                        self.push(Op::Text { x, y: self.y, size: TABLE_ROW_SIZE, style: FontStyle::Regular, text: text.clone() });
+                    }
+                    x += column.width;
+                }
+                self.y -= TABLE_LINE_HEIGHT;
+            }
+            self.y -= TABLE_ROW_GAP;
+        }
+    }
+
+    /// Makes room for `height` of table rows, continuing the table on a new page if needed.
+    fn table_space(&mut self, title: &str, columns: &[Column], height: f32) {
+        if self.ensure_space(height) {
+            self.heading(&format!("{} (continued)", title));
+            self.table_header(columns);
+        }
+    }
+
+    fn table_header(&mut self, columns: &[Column]) {
+        self.ensure_space(header_height(columns) + TABLE_LINE_HEIGHT);
+        let titles: Vec<Vec<String>> = columns.iter()
+            .map(|c| wrap(&c.title, c.width - CELL_PADDING, TABLE_HEADER_SIZE, FontStyle::Bold))
+            .collect();
+
+        let mut x = MARGIN_LEFT;
+        for (column, lines) in columns.iter().zip(&titles) {
+            for (index, line) in lines.iter().enumerate() {
+                let y = self.y - index as f32 * TABLE_HEADER_LINE;
+                self.push(Op::Text { x, y, size: TABLE_HEADER_SIZE, style: FontStyle::Bold, text: line.clone() });
+            }
+            x += column.width;
+        }
+
+        self.y -= header_height(columns) - 8.0;
+        let width: f32 = columns.iter().map(|c| c.width).sum();
+        self.push(Op::Rule { x1: MARGIN_LEFT, x2: MARGIN_LEFT + width, y: self.y - 2.0 });
+        self.y -= 8.0;
+    }
+
+    /// Reserves a block `height` millimetres tall, on a new page if it does
+    /// not fit on this one, and returns the y of its bottom edge. `path`,
+    /// `rect` and `label` then draw at absolute positions within the block.
+    pub fn reserve(&mut self, height: f32) -> f32 {
+        self.ensure_space(height);
+        self.y -= height;
+        self.y
+    }
+
+    /// A line through `points`.
+    pub fn path(&mut self, points: Vec<(f32, f32)>, thickness: f32, color: Rgb) {
+        self.push(Op::Path { points, thickness, color });
+    }
+
+    /// A filled rectangle with its bottom left corner at `x`, `y`.
+    pub fn rect(&mut self, x: f32, y: f32, width: f32, height: f32, fill: Rgb) {
+        self.push(Op::Rect { x, y, width, height, fill });
+    }
+
+    /// A single line of text with its baseline at `y`, positioned by `align`.
+    pub fn label(&mut self, text: &str, x: f32, y: f32, size: f32, style: FontStyle, align: Align) {
+        let x = match align {
+            Align::Left => x,
+            Align::Center => x - text_width(text, size, style) / 2.0,
+            Align::Right => x - text_width(text, size, style),
+        };
+        self.push(Op::Text { x, y, size, style, text: text.to_string() });
+    }
+
+    /// Draws every page, with a "Page X of Y" footer, and returns the PDF.
+    pub fn render(mut self, title: &str) -> Result<Vec<u8>, AppError> {
+        let total = self.pages.len();
+        for (index, ops) in self.pages.iter_mut().enumerate() {
+            let footer = format!("Page {} of {}", index + 1, total);
+            let footer_x = PAGE_WIDTH - MARGIN_RIGHT - text_width(&footer, 9.0, FontStyle::Regular);
+            ops.push(Op::Text { x: MARGIN_LEFT, y: FOOTER_Y, size: 9.0, style: FontStyle::Regular, text: title.to_string() });
+            ops.push(Op::Text { x: footer_x, y: FOOTER_Y, size: 9.0, style: FontStyle::Regular, text: footer });
+        }
+
+        // Fonts are cut down to the glyphs they draw, so every line is shaped
+        // before any font can be added
+        let mut subsets = FontSubsets::default();
+        let shaped: Vec<Vec<ShapedText>> = self.pages.iter()
+            .map(|ops| ops.iter()
+                .map(|op| match op {
+                    Op::Text { text, style, .. } => pdf_fonts::shape(text, *style)
+                        .into_iter()
+                        .map(|run| {
+                            let codes = subsets.add(&run);
+                            (run, codes)
+                        })
+                        .collect(),
+                    _ => Vec::new(),
+                })
+                .collect())
+            .collect();
+
+        let (doc, first_page, first_layer) = PdfDocument::new(title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+        let fonts = subsets.embed(&doc)?;
+
+        for (index, (ops, shaped)) in self.pages.into_iter().zip(shaped).enumerate() {
+            let layer = if index == 0 {
+                doc.get_page(first_page).get_layer(first_layer)
+            } else {
+                let (page, layer) = doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+                doc.get_page(page).get_layer(layer)
+            };
+
+            for (op, runs) in ops.into_iter().zip(shaped) {
+                match op {
+                    Op::Text { x, y, size, .. } => {
+                        layer.set_fill_color(Rgb::BLACK.color());
+                        let mut run_x = x;
+                        for (run, codes) in &runs {
+                            draw_run(&layer, &fonts[&run.font], run, codes, size, run_x, y);
+                            run_x += run.width * size * POINT_TO_MM;
+                        }
+                    }
+                    Op::Rule { x1, x2, y } => {
+                        layer.set_outline_color(Rgb::BLACK.color());
+                        layer.set_outline_thickness(0.5);
+                        layer.add_line(Line {
+                            points: vec![(Point::new(Mm(x1), Mm(y)), false), (Point::new(Mm(x2), Mm(y)), false)],
+                            is_closed: false,
+                        });
+                    }
+                    Op::Path { points, thickness, color } => {
+                        layer.set_outline_color(color.color());
+                        layer.set_outline_thickness(thickness);
+                        layer.add_line(Line {
+                            points: points.into_iter().map(|(x, y)| (Point::new(Mm(x), Mm(y)), false)).collect(),
+                            is_closed: false,
+                        });
+                    }
+                    Op::Rect { x, y, width, height, fill } => {
+                        layer.set_fill_color(fill.color());
+                        layer.add_rect(Rect::new(Mm(x), Mm(y), Mm(x + width), Mm(y + height)));
+                    }
+                }
+            }
+        }
+
+        let mut buf = Vec::new();
+        let mut writer = BufWriter::new(&mut buf);
+        doc.save(&mut writer)?;
+        drop(writer);
+
+        subsets.finish(&buf)
+    }
+}
+
+/// Draws one shaped run with its baseline starting at `x`, `y`. Glyphs are
+/// placed where the shaper put them: each is nudged by the difference
+/// between that and where the previous glyph's width left the pen.
+fn draw_run(layer: &PdfLayerReference, font: &IndirectFontRef, run: &Run, codes: &[u16], size: f32, x: f32, y: f32) {
+    layer.begin_text_section();
+    layer.set_font(font, size);
+    layer.set_text_cursor(Mm(x), Mm(y));
+
+    let mut shown: Vec<Object> = Vec::new();
+    let mut codes_pending: Vec<u8> = Vec::new();
+    let mut pen = 0;
+    let mut rise = 0.0;
+
+    for (glyph, &code) in run.glyphs.iter().zip(codes) {
+        let target = (glyph.x * 1000.0).round() as i64;
+        let raised = glyph.y * size;
+        if raised != rise || pen != target {
+            if !codes_pending.is_empty() {
+                shown.push(Object::String(std::mem::take(&mut codes_pending), StringFormat::Hexadecimal));
+            }
+            if raised != rise {
+                show(layer, std::mem::take(&mut shown));
+                layer.set_line_offset(raised);
+                rise = raised;
+            }
+            if pen != target {
+                shown.push(Object::Integer(pen - target));
+            }
+        }
+        codes_pending.extend(code.to_be_bytes());
+        pen = target + pdf_fonts::advance(run.font, glyph.id);
+    }
+
+    if !codes_pending.is_empty() {
+        shown.push(Object::String(codes_pending, StringFormat::Hexadecimal));
+    }
+    show(layer, shown);
+    if rise != 0.0 {
+        layer.set_line_offset(0.0);
+    }
+    layer.end_text_section();
+}
+
+fn show(layer: &PdfLayerReference, shown: Vec<Object>) {
+    if !shown.is_empty() {
+        layer.add_operation(Operation::new("TJ", vec![Object::Array(shown)]));
+    }
+}
+
+/// A line of text as runs, with the character code of each glyph.
+type ShapedText = Vec<(Run, Vec<u16>)>;
+
+fn line_advance(size: f32) -> f32 {
+    size * 0.55
+}
+
+/// From the baseline of the header titles to the first row, with titles
+/// wrapped onto as many lines as the widest column needs.
+fn header_height(columns: &[Column]) -> f32 {
+    let lines = columns.iter()
+        .map(|c| wrap(&c.title, c.width - CELL_PADDING, TABLE_HEADER_SIZE, FontStyle::Bold).len())
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    (lines - 1) as f32 * TABLE_HEADER_LINE + 8.0
+}
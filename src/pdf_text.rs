@@ -0,0 +1,94 @@
+use crate::pdf_fonts;
+use crate::pdf_layout::FontStyle;
+
+pub const POINT_TO_MM: f32 = 0.3528;
+
+pub const ELLIPSIS: char = '…';
+
+/// Width of `text` set at `size` points, in millimetres, as shaped with the
+/// bundled fonts the report is drawn in.
+pub fn text_width(text: &str, size: f32, style: FontStyle) -> f32 {
+    pdf_fonts::width(text, style) * size * POINT_TO_MM
+}
+
+/// Breaks `text` into lines no wider than `width` millimetres. Lines break at
+/// spaces and at the text's own line breaks; a word too long for a line of
+/// its own is split, with a hyphen when the split falls between letters.
+pub fn wrap(text: &str, width: f32, size: f32, style: FontStyle) -> Vec<String> {
+    let fits = |line: &str| text_width(line, size, style) <= width;
+    let mut lines = Vec::new();
+
+    for paragraph in text.lines() {
+        let mut line = String::new();
+
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+            if fits(&candidate) {
+                line = candidate;
+                continue;
+            }
+
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            let mut rest = word;
+            while !fits(rest) {
+                let (head, tail) = split_word(rest, |part| fits(part));
+                lines.push(head);
+                rest = tail;
+            }
+            line = rest.to_string();
+        }
+
+        lines.push(line);
+    }
+
+    // Blank lines inside the text are kept, but not at its ends
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    let leading = lines.iter().take_while(|l| l.is_empty()).count();
+    lines.drain(..leading);
+    lines
+}
+
+/// Splits off the longest head of `word` that fits, hyphenated between letters.
+/// Always takes at least one character so wrapping makes progress.
+fn split_word(word: &str, fits: impl Fn(&str) -> bool) -> (String, &str) {
+    let boundaries: Vec<usize> = word.char_indices().map(|(i, _)| i).skip(1).collect();
+
+    for &at in boundaries.iter().rev() {
+        let (head, tail) = word.split_at(at);
+        let hyphenate = head.chars().last().is_some_and(char::is_alphabetic) && tail.chars().next().is_some_and(char::is_alphabetic);
+        let head = if hyphenate { format!("{}-", head) } else { head.to_string() };
+        if fits(&head) {
+            return (head, tail);
+        }
+    }
+
+    let at = boundaries.first().copied().unwrap_or(word.len());
+    (word[..at].to_string(), &word[at..])
+}
+
+/// Wraps `text` and keeps at most `max_lines`, ending the last kept line
+/// with an ellipsis when anything was cut.
+pub fn wrap_limited(text: &str, width: f32, size: f32, style: FontStyle, max_lines: usize) -> Vec<String> {
+    let mut lines = wrap(text, width, size, style);
+    if lines.len() <= max_lines {
+        return lines;
+    }
+
+    lines.truncate(max_lines);
+    if let Some(last) = lines.last_mut() {
+        let mut kept = last.trim_end_matches('-').to_string();
+        loop {
+            let candidate = format!("{}{}", kept.trim_end(), ELLIPSIS);
+            if text_width(&candidate, size, style) <= width || kept.is_empty() {
+                *last = candidate;
+                break;
+            }
+            kept.pop();
+        }
+    }
+    lines
+}
@@ -12,5 +12,194 @@ diesel::table! {
         notes -> Nullable<Text>,
         ai_analysis -> Nullable<Text>,
         created_at -> Timestamp,
+        user_id -> Nullable<Integer>,
     }
-}
\ No newline at end of file
+}
+
+diesel::table! {
+    episode_revisions (id) {
+        id -> Integer,
+        episode_id -> Integer,
+        field_name -> Text,
+        old_value -> Nullable<Text>,
+        new_value -> Nullable<Text>,
+        changed_by -> Text,
+        changed_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    triggers (id) {
+        id -> Integer,
+        name -> Text,
+        created_at -> Timestamp,
+        canonical -> Bool,
+    }
+}
+
+diesel::table! {
+    trigger_synonyms (id) {
+        id -> Integer,
+        phrase -> Text,
+        trigger_id -> Integer,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    episode_triggers (episode_id, trigger_id) {
+        episode_id -> Integer,
+        trigger_id -> Integer,
+        position -> Integer,
+    }
+}
+
+diesel::table! {
+    episode_symptoms (episode_id) {
+        episode_id -> Integer,
+        vertigo_type -> Nullable<Text>,
+        nausea -> Bool,
+        vomiting -> Bool,
+        tinnitus -> Bool,
+        aural_fullness -> Bool,
+        hearing_loss_side -> Nullable<Text>,
+        headache -> Bool,
+        visual_aura -> Bool,
+        imbalance -> Bool,
+        nystagmus_direction -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    medications (id) {
+        id -> Integer,
+        name -> Text,
+        drug_class -> Nullable<Text>,
+        strength -> Nullable<Text>,
+        usage -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    medication_intakes (id) {
+        id -> Integer,
+        episode_id -> Integer,
+        medication_id -> Integer,
+        dose -> Text,
+        minutes_after_onset -> Nullable<Integer>,
+        perceived_effect -> Nullable<Integer>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    medication_courses (id) {
+        id -> Integer,
+        medication_id -> Integer,
+        started_on -> Date,
+        stopped_on -> Nullable<Date>,
+        notes -> Nullable<Text>,
+        created_at -> Timestamp,
+        user_id -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    users (id) {
+        id -> Integer,
+        username -> Text,
+        password_hash -> Text,
+        created_at -> Timestamp,
+        is_admin -> Bool,
+    }
+}
+
+diesel::table! {
+    sessions (id) {
+        id -> Integer,
+        user_id -> Integer,
+        token_hash -> Text,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    share_links (id) {
+        id -> Integer,
+        user_id -> Integer,
+        label -> Nullable<Text>,
+        from_date -> Nullable<Date>,
+        to_date -> Nullable<Date>,
+        pin_hash -> Nullable<Text>,
+        failed_pin_attempts -> Integer,
+        expires_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    share_access_log (id) {
+        id -> Integer,
+        share_link_id -> Integer,
+        accessed_at -> Timestamp,
+        resource -> Text,
+        outcome -> Text,
+        client_ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    server_secrets (name) {
+        name -> Text,
+        value -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    calendar_feeds (id) {
+        id -> Integer,
+        user_id -> Integer,
+        label -> Nullable<Text>,
+        token_hash -> Text,
+        last_fetched_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::joinable!(episode_revisions -> episodes (episode_id));
+diesel::joinable!(episode_symptoms -> episodes (episode_id));
+diesel::joinable!(medication_intakes -> episodes (episode_id));
+diesel::joinable!(medication_intakes -> medications (medication_id));
+diesel::joinable!(medication_courses -> medications (medication_id));
+diesel::joinable!(episode_triggers -> episodes (episode_id));
+diesel::joinable!(episode_triggers -> triggers (trigger_id));
+diesel::joinable!(trigger_synonyms -> triggers (trigger_id));
+diesel::joinable!(episodes -> users (user_id));
+diesel::joinable!(medication_courses -> users (user_id));
+diesel::joinable!(sessions -> users (user_id));
+diesel::joinable!(share_links -> users (user_id));
+diesel::joinable!(share_access_log -> share_links (share_link_id));
+diesel::joinable!(calendar_feeds -> users (user_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    episodes,
+    episode_revisions,
+    triggers,
+    episode_triggers,
+    trigger_synonyms,
+    episode_symptoms,
+    medications,
+    medication_intakes,
+    medication_courses,
+    users,
+    sessions,
+    share_links,
+    share_access_log,
+    server_secrets,
+    calendar_feeds,
+);
@@ -0,0 +1,296 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, NaiveDateTime};
+use diesel::result::Error;
+use diesel::sqlite::SqliteConnection;
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+use std::env;
+
+use crate::auth;
+use crate::database;
+use crate::html::escape_html;
+use crate::models::{Episode, NewShareAccess, ShareLink, ShareOutcome, ShareResource};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Wrong PINs allowed before a link revokes itself.
+pub const MAX_PIN_ATTEMPTS: i32 = 5;
+
+/// Signing key from `SHARE_LINK_SECRET`, or one generated and kept in the
+/// database so links survive restarts.
+pub fn signing_key(conn: &mut SqliteConnection) -> Result<Vec<u8>, Error> {
+    if let Some(secret) = env::var("SHARE_LINK_SECRET").ok().filter(|s| !s.is_empty()) {
+        return Ok(secret.into_bytes());
+    }
+
+    let stored = database::get_or_create_secret(conn, "share_link_key", || {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        URL_SAFE_NO_PAD.encode(bytes)
+    })?;
+    Ok(stored.into_bytes())
+}
+
+fn mac(key: &[u8], payload: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// `<link id>.<expiry as unix seconds>.<HMAC-SHA256 of the first two parts>`
+pub fn sign_token(key: &[u8], link_id: i32, expires_at: NaiveDateTime) -> String {
+    let payload = format!("{}.{}", link_id, expires_at.and_utc().timestamp());
+    let signature = mac(key, &payload).finalize().into_bytes();
+    format!("{}.{}", payload, URL_SAFE_NO_PAD.encode(signature))
+}
+
+/// The link id and expiry a token was signed for, if the signature holds.
+pub fn verify_token(key: &[u8], token: &str) -> Option<(i32, NaiveDateTime)> {
+    let (payload, signature) = token.rsplit_once('.')?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+    mac(key, payload).verify_slice(&signature).ok()?;
+
+    let (link_id, expires) = payload.split_once('.')?;
+    let expires_at = DateTime::from_timestamp(expires.parse().ok()?, 0)?.naive_utc();
+    Some((link_id.parse().ok()?, expires_at))
+}
+
+/// How long a browser stays let in once its PIN checks out.
+pub const GRANT_MINUTES: i64 = 30;
+
+fn grant_payload(link_id: i32, expires: &str) -> String {
+    format!("grant.{}.{}", link_id, expires)
+}
+
+/// `<expiry as unix seconds>.<HMAC-SHA256 of "grant.<link id>.<expiry>">`,
+/// handed to a browser in place of the PIN it entered. The prefix keeps a
+/// grant from passing as a share token.
+pub fn sign_grant(key: &[u8], link_id: i32, expires_at: NaiveDateTime) -> String {
+    let expires = expires_at.and_utc().timestamp().to_string();
+    let signature = mac(key, &grant_payload(link_id, &expires)).finalize().into_bytes();
+    format!("{}.{}", expires, URL_SAFE_NO_PAD.encode(signature))
+}
+
+/// Whether the grant was signed for this link and has not run out.
+pub fn verify_grant(key: &[u8], link_id: i32, grant: &str, now: NaiveDateTime) -> bool {
+    let Some((expires, signature)) = grant.split_once('.') else { return false };
+    let Ok(signature) = URL_SAFE_NO_PAD.decode(signature) else { return false };
+    if mac(key, &grant_payload(link_id, expires)).verify_slice(&signature).is_err() {
+        return false;
+    }
+    expires.parse().ok()
+        .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+        .is_some_and(|expires_at| expires_at.naive_utc() > now)
+}
+
+/// Each link gets its own cookie, so grants for several links can coexist.
+pub fn grant_cookie_name(link_id: i32) -> String {
+    format!("vertigo_share_{}", link_id)
+}
+
+/// `Set-Cookie` value with a fresh grant, lasting no longer than the link.
+pub fn grant_cookie(key: &[u8], link: &ShareLink, now: NaiveDateTime) -> String {
+    let expires_at = link.expires_at.min(now + chrono::Duration::minutes(GRANT_MINUTES));
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}",
+        grant_cookie_name(link.id),
+        sign_grant(key, link.id, expires_at),
+        (expires_at - now).num_seconds().max(0),
+    )
+}
+
+/// Who is asking, for the access log.
+#[derive(Debug, Clone, Default)]
+pub struct Visitor {
+    pub pin: Option<String>,
+    /// The link's grant cookie, if the browser sent one
+    pub grant: Option<String>,
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// What a visitor's PIN or grant says about a link, before anything is recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShareCheck {
+    Outcome(ShareOutcome),
+    /// A PIN was supplied and it was wrong, which counts towards the lock
+    FailedPin,
+}
+
+/// The link a token was signed for, and the key that signed it. Tokens that
+/// fail the signature check, or name a link that no longer exists, are
+/// NotFound and are not logged since they cannot be tied to a link.
+pub fn resolve_token(conn: &mut SqliteConnection, token: &str) -> Result<(ShareLink, Vec<u8>), Error> {
+    let key = signing_key(conn)?;
+    let (link_id, expires_at) = verify_token(&key, token).ok_or(Error::NotFound)?;
+
+    let link = database::get_share_link(conn, link_id)?;
+    if link.expires_at != expires_at {
+        return Err(Error::NotFound);
+    }
+    Ok((link, key))
+}
+
+/// Checks the visitor against the link. This may hash a PIN, so it needs no
+/// connection and should run without holding one.
+pub fn check_visitor(key: &[u8], link: &ShareLink, visitor: &Visitor) -> ShareCheck {
+    let now = chrono::Utc::now().naive_utc();
+    if link.revoked_at.is_some() {
+        return ShareCheck::Outcome(ShareOutcome::Revoked);
+    }
+    if link.expires_at <= now {
+        return ShareCheck::Outcome(ShareOutcome::Expired);
+    }
+
+    let granted = visitor.grant.as_deref().is_some_and(|grant| verify_grant(key, link.id, grant, now));
+    match (&link.pin_hash, visitor.pin.as_deref()) {
+        (None, _) => ShareCheck::Outcome(ShareOutcome::Granted),
+        _ if granted => ShareCheck::Outcome(ShareOutcome::Granted),
+        // Link previews and first visits carry no PIN; only a PIN that
+        // was actually tried counts towards the lock
+        (Some(_), None) => ShareCheck::Outcome(ShareOutcome::WrongPin),
+        (Some(pin_hash), Some(pin)) if auth::verify_password(pin, pin_hash) => ShareCheck::Outcome(ShareOutcome::Granted),
+        (Some(_), Some(_)) => ShareCheck::FailedPin,
+    }
+}
+
+/// Counts a failed PIN against the link and logs the attempt.
+pub fn record_access(
+    conn: &mut SqliteConnection,
+    link: &ShareLink,
+    resource: ShareResource,
+    visitor: &Visitor,
+    check: ShareCheck,
+) -> Result<ShareOutcome, Error> {
+    let outcome = match check {
+        ShareCheck::Outcome(outcome) => outcome,
+        ShareCheck::FailedPin if database::record_failed_pin(conn, link.id, MAX_PIN_ATTEMPTS)? => ShareOutcome::Locked,
+        ShareCheck::FailedPin => ShareOutcome::WrongPin,
+    };
+
+    database::log_share_access(conn, &NewShareAccess {
+        share_link_id: link.id,
+        resource,
+        outcome,
+        client_ip: visitor.client_ip.as_deref(),
+        user_agent: visitor.user_agent.as_deref(),
+    })?;
+
+    Ok(outcome)
+}
+
+/// Checks a token against its link and logs the attempt, all on one
+/// connection. Handlers run the steps separately so that no pooled
+/// connection is held while a PIN is hashed.
+pub fn open_share(
+    conn: &mut SqliteConnection,
+    token: &str,
+    resource: ShareResource,
+    visitor: &Visitor,
+) -> Result<(ShareLink, ShareOutcome), Error> {
+    let (link, key) = resolve_token(conn, token)?;
+    let check = check_visitor(&key, &link, visitor);
+    let outcome = record_access(conn, &link, resource, visitor, check)?;
+
+    Ok((link, outcome))
+}
+
+/// Page asking for the link's PIN; it submits back to the same URL.
+pub fn render_pin_form(pin_was_wrong: bool) -> String {
+    let message = if pin_was_wrong {
+        "That PIN did not match. Too many wrong PINs lock the link."
+    } else {
+        "This log is protected. Enter the PIN you were given with the link."
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="UTF-8">
+<meta name="viewport" content="width=device-width, initial-scale=1.0">
+<meta name="robots" content="noindex">
+<title>Shared vertigo log</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; color: #222; }}
+input {{ font-size: 1.2em; padding: 0.3em; }}
+</style>
+</head>
+<body>
+<h1>Shared vertigo log</h1>
+<p>{message}</p>
+<form method="post">
+<label for="pin">PIN</label>
+<input id="pin" name="pin" type="password" inputmode="numeric" pattern="[0-9]{{4,12}}" autocomplete="off" required autofocus>
+<button type="submit">Open</button>
+</form>
+</body>
+</html>
+"#,
+        message = message,
+    )
+}
+
+/// Read-only HTML page listing the shared episodes.
+pub fn render_episode_view(link: &ShareLink, episodes: &[Episode], analytics_url: &str, report_url: &str) -> String {
+    let period = match (link.from_date, link.to_date) {
+        (Some(from), Some(to)) => format!("{} to {}", from, to),
+        (Some(from), None) => format!("From {}", from),
+        (None, Some(to)) => format!("Up to {}", to),
+        (None, None) => "All recorded episodes".to_string(),
+    };
+    let cell = |value: Option<&str>| escape_html(value.unwrap_or(""));
+
+    let rows: String = episodes
+        .iter()
+        .map(|e| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                e.timestamp.format("%Y-%m-%d %H:%M"),
+                e.severity,
+                e.duration_minutes.map_or(String::new(), |d| format!("{} min", d)),
+                cell(e.symptoms.as_deref()),
+                cell(e.triggers.as_deref()),
+                cell(e.medications_taken.as_deref()),
+                cell(e.notes.as_deref()),
+            )
+        })
+        .collect();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="UTF-8">
+<meta name="viewport" content="width=device-width, initial-scale=1.0">
+<meta name="robots" content="noindex">
+<title>Shared vertigo log</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; color: #222; }}
+table {{ border-collapse: collapse; width: 100%; }}
+th, td {{ border: 1px solid #ccc; padding: 0.4em; text-align: left; vertical-align: top; }}
+th {{ background: #f0f0f5; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<p>{period} &middot; {count} episodes &middot; link expires {expires} UTC</p>
+<p><a href="{analytics_url}">Analytics (JSON)</a> &middot; <a href="{report_url}">PDF report</a></p>
+<table>
+<thead><tr><th>Date</th><th>Severity</th><th>Duration</th><th>Symptoms</th><th>Triggers</th><th>Medications</th><th>Notes</th></tr></thead>
+<tbody>
+{rows}</tbody>
+</table>
+</body>
+</html>
+"#,
+        title = escape_html(link.label.as_deref().unwrap_or("Shared vertigo log")),
+        period = period,
+        count = episodes.len(),
+        expires = link.expires_at.format("%Y-%m-%d %H:%M"),
+        analytics_url = escape_html(analytics_url),
+        report_url = escape_html(report_url),
+        rows = rows,
+    )
+}
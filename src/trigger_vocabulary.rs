@@ -0,0 +1,90 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::models::{Trigger, TriggerSynonym};
+
+/// How a free-text phrase was matched onto a canonical trigger.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchRule {
+    Name,
+    Synonym,
+    Fuzzy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VocabularyMatch {
+    pub trigger_id: i32,
+    pub rule: MatchRule,
+    pub distance: usize,
+}
+
+struct Entry {
+    phrase: String,
+    trigger_id: i32,
+    rule: MatchRule,
+}
+
+/// Canonical trigger names plus user-editable synonym rules, used to map
+/// whatever the user typed ("poor sleep", "tird") onto one vocabulary term.
+pub struct Vocabulary {
+    entries: Vec<Entry>,
+}
+
+impl Vocabulary {
+    pub fn new(canonical: &[Trigger], synonyms: &[TriggerSynonym]) -> Self {
+        let mut entries: Vec<Entry> = canonical
+            .iter()
+            .map(|t| Entry { phrase: normalize(&t.name), trigger_id: t.id, rule: MatchRule::Name })
+            .chain(synonyms.iter().map(|s| Entry {
+                phrase: normalize(&s.phrase),
+                trigger_id: s.trigger_id,
+                rule: MatchRule::Synonym,
+            }))
+            .collect();
+
+        // Deterministic tie-breaking when two phrases are equally close
+        entries.sort_by(|a, b| a.phrase.cmp(&b.phrase));
+
+        Vocabulary { entries }
+    }
+
+    /// Exact name or synonym matches win; otherwise the closest phrase within
+    /// the edit-distance budget for the input's length.
+    pub fn lookup(&self, phrase: &str) -> Option<VocabularyMatch> {
+        let phrase = normalize(phrase);
+        if phrase.is_empty() {
+            return None;
+        }
+
+        if let Some(entry) = self.entries.iter().find(|e| e.phrase == phrase) {
+            return Some(VocabularyMatch { trigger_id: entry.trigger_id, rule: entry.rule, distance: 0 });
+        }
+
+        let budget = max_distance(&phrase);
+        if budget == 0 {
+            return None;
+        }
+
+        self.entries
+            .iter()
+            .map(|e| (e, strsim::levenshtein(&phrase, &e.phrase)))
+            .filter(|(_, distance)| *distance <= budget)
+            .min_by_key(|(_, distance)| *distance)
+            .map(|(entry, distance)| VocabularyMatch { trigger_id: entry.trigger_id, rule: MatchRule::Fuzzy, distance })
+    }
+}
+
+/// Case-folds and collapses internal whitespace.
+pub fn normalize(phrase: &str) -> String {
+    phrase.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+// Short words get no slack: "beer" and "bear" are different things.
+fn max_distance(phrase: &str) -> usize {
+    match phrase.chars().count() {
+        0..=4 => 0,
+        5..=8 => 1,
+        _ => 2,
+    }
+}
@@ -495,20 +495,24 @@ class VertigoLogger {
 
     async exportPDFReport() {
         try {
-            this.showStatus('Generating medical report...', 'info');
+            this.showStatus('Generating report...', 'info');
 
-            const response = await fetch(`${this.apiBase}/report/pdf`);
+            const template = document.getElementById('report-template').value;
+            const response = await fetch(`${this.apiBase}/report/pdf?template=${encodeURIComponent(template)}`);
             if (response.ok) {
                 const blob = await response.blob();
                 const url = window.URL.createObjectURL(blob);
                 const a = document.createElement('a');
                 a.href = url;
-                a.download = `vertigo-medical-report-${new Date().toISOString().split('T')[0]}.pdf`;
+                // The server names the file after the template
+                const disposition = response.headers.get('Content-Disposition') || '';
+                const match = disposition.match(/filename="([^"]+)"/);
+                a.download = match ? match[1] : `vertigo-report-${new Date().toISOString().split('T')[0]}.pdf`;
                 document.body.appendChild(a);
                 a.click();
                 document.body.removeChild(a);
                 window.URL.revokeObjectURL(url);
-                this.showStatus('Medical report downloaded successfully!', 'success');
+                this.showStatus('Report downloaded successfully!', 'success');
             } else {
                 throw new Error(`HTTP ${response.status}`);
             }